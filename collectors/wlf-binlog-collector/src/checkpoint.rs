@@ -0,0 +1,222 @@
+use std::{
+    collections::VecDeque,
+    fs, future,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use wlf_core::Receipt;
+
+use crate::Error;
+
+/// A binlog position between two transactions, replication can be resumed from it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Checkpoint {
+    pub(crate) filename: String,
+    pub(crate) position: u32,
+}
+
+impl Checkpoint {
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>, Error> {
+        match fs::read(path) {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes to a temporary file first, so that a crash never leaves a partial checkpoint.
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Tracks the replication position and saves it at most once per interval. A position is only
+/// reached once the events before it are delivered, so that none is lost on a restart.
+pub(crate) struct Checkpointer {
+    path: PathBuf,
+    interval: Duration,
+    /// Positions waiting for the events before them to be delivered, oldest first
+    pending: VecDeque<(Option<String>, u32, Receipt)>,
+    current: Option<Checkpoint>,
+    saved: Option<Checkpoint>,
+    saved_at: Instant,
+}
+
+impl Checkpointer {
+    pub(crate) fn new(path: PathBuf, interval: Duration, current: Option<Checkpoint>) -> Self {
+        Self {
+            path,
+            interval,
+            pending: VecDeque::new(),
+            saved: current.clone(),
+            current,
+            saved_at: Instant::now(),
+        }
+    }
+
+    /// Moves to the position, in a new binlog file if `filename` is given, once the events
+    /// of the receipt are delivered.
+    pub(crate) fn advance(
+        &mut self,
+        filename: Option<String>,
+        position: u32,
+        receipt: Receipt,
+    ) -> Result<(), Error> {
+        self.pending.push_back((filename, position, receipt));
+        self.save_if_due()
+    }
+
+    /// The positions waiting for their events to be delivered, as `file:position`.
+    pub(crate) fn pending_positions(&self) -> Vec<String> {
+        let mut filename = self.current.as_ref().map(|c| c.filename.as_str());
+        self.pending
+            .iter()
+            .map(|(file, position, _)| {
+                filename = file.as_deref().or(filename);
+                format!("{}:{position}", filename.unwrap_or("?"))
+            })
+            .collect()
+    }
+
+    /// Waits until the oldest pending position is delivered, or the position reached is due to
+    /// be saved.
+    pub(crate) async fn changed(&self) {
+        let delivered = async {
+            match self.pending.front() {
+                Some((_, _, receipt)) => receipt.delivered().await,
+                None => future::pending().await,
+            }
+        };
+        let due = async {
+            if self.current == self.saved {
+                future::pending::<()>().await;
+            }
+            tokio::time::sleep_until((self.saved_at + self.interval).into()).await;
+        };
+        tokio::select! {
+            _ = delivered => {}
+            _ = due => {}
+        }
+    }
+
+    /// Waits until the events of all pending positions are delivered.
+    pub(crate) async fn delivered(&self) {
+        for (_, _, receipt) in &self.pending {
+            receipt.delivered().await;
+        }
+    }
+
+    pub(crate) fn save_if_due(&mut self) -> Result<(), Error> {
+        self.confirm();
+        if self.saved_at.elapsed() >= self.interval {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Saves the last position whose events, and the ones before, are delivered.
+    pub(crate) fn save(&mut self) -> Result<(), Error> {
+        self.confirm();
+        if self.current != self.saved {
+            if let Some(current) = &self.current {
+                current.save(&self.path)?;
+                info!("binlog checkpoint {current:?} is saved");
+            }
+            self.saved = self.current.clone();
+        }
+        self.saved_at = Instant::now();
+        Ok(())
+    }
+
+    fn confirm(&mut self) {
+        while let Some((_, _, receipt)) = self.pending.front() {
+            if !receipt.is_delivered() {
+                break;
+            }
+            let (filename, position, _) = self.pending.pop_front().unwrap();
+            match (&mut self.current, filename) {
+                (_, Some(filename)) => self.current = Some(Checkpoint { filename, position }),
+                (Some(current), None) => current.position = position,
+                // the binlog file is unknown until the first rotate event
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wlf_core::Delivery;
+
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("wlf-checkpoint-{}", std::process::id()));
+        let mut checkpointer = Checkpointer::new(path.clone(), Duration::from_secs(3600), None);
+
+        let delivered = || Delivery::track().1;
+        checkpointer.advance(None, 120, delivered()).unwrap();
+        checkpointer
+            .advance(Some("binlog.000002".to_string()), 4, delivered())
+            .unwrap();
+        checkpointer.advance(None, 300, delivered()).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+
+        // the events before 500 are still in flight
+        let (delivery, receipt) = Delivery::track();
+        checkpointer.advance(None, 500, receipt).unwrap();
+        checkpointer.advance(None, 600, delivered()).unwrap();
+        checkpointer.save().unwrap();
+        assert_eq!(
+            Checkpoint::load(&path).unwrap(),
+            Some(Checkpoint {
+                filename: "binlog.000002".to_string(),
+                position: 300
+            })
+        );
+        assert_eq!(
+            checkpointer.pending_positions(),
+            vec!["binlog.000002:500", "binlog.000002:600"]
+        );
+
+        drop(delivery);
+        checkpointer.save().unwrap();
+        assert!(checkpointer.pending_positions().is_empty());
+        assert_eq!(Checkpoint::load(&path).unwrap().unwrap().position, 600);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_deliveries() {
+        let path = std::env::temp_dir().join(format!("wlf-deliveries-{}", std::process::id()));
+        let current = Checkpoint {
+            filename: "binlog.000001".to_string(),
+            position: 4,
+        };
+        let mut checkpointer =
+            Checkpointer::new(path.clone(), Duration::from_secs(3600), Some(current));
+        let (delivery, receipt) = Delivery::track();
+        checkpointer.advance(None, 120, receipt).unwrap();
+
+        let wait = Duration::from_secs(60);
+        assert!(tokio::time::timeout(wait, checkpointer.changed())
+            .await
+            .is_err());
+        let (changed, ()) = tokio::join!(
+            tokio::time::timeout(wait, checkpointer.changed()),
+            async move { drop(delivery) }
+        );
+        assert!(changed.is_ok());
+        assert!(tokio::time::timeout(wait, checkpointer.delivered())
+            .await
+            .is_ok());
+    }
+}
@@ -10,6 +10,10 @@ pub enum Error {
     EventRouter(#[from] wlf_core::event_router::Error),
     #[error("failed to analyze sql statement, {0}")]
     SqlAnalyzer(#[from] sql_analyzer::Error),
+    #[error("io error, {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serialize/deserialize error, {0}")]
+    Serde(#[from] serde_json::Error),
     #[error("{0}")]
     Other(String),
 }
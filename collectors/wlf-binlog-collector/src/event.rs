@@ -0,0 +1,282 @@
+//! Conversion of binlog events into wlf events.
+//!
+//! The format is largely borrowed from [maxwell](https://maxwells-daemon.io/dataformat/). Every
+//! inserted, updated or deleted row becomes its own event:
+//!
+//! ```json
+//! {
+//!     "database": "shop",
+//!     "table": "orders",
+//!     "type": "update",
+//!     "timestamp": "2023-07-22T04:26:40Z",
+//!     "server_id": 1,
+//!     "data": { "id": 1, "status": "shipped", "created_at": "2023-07-21 10:02:11" },
+//!     "old": { "status": "paid" }
+//! }
+//! ```
+//!
+//! `type` is `insert`, `update` or `delete`, `data` is the row after an insert or an update and
+//! the removed row of a delete, and `old` holds the previous values of the columns an update
+//! changed. Like maxwell, integers and floats are written as json numbers, dates and times as
+//! strings such as `2023-07-21 10:02:11`, enums as their name and sets as an array of names.
+//! Decimals are kept as strings not to lose their precision, text and blobs are written as
+//! (lossy) utf-8 strings. `timestamp` is the time the statement was logged, as an RFC 3339
+//! string. A SQL statement, e.g. `CREATE TABLE`, becomes a single event with the properties
+//! found by the SQL analyzer instead of `data`.
+
+use chrono::{LocalResult, TimeZone, Utc};
+use mysql_cdc::events::{
+    binlog_event::BinlogEvent,
+    event_header::EventHeader,
+    row_events::{mysql_value::MySqlValue, row_data::RowData},
+};
+use sqlparser::ast::{ColumnDef, DataType};
+use tracing::warn;
+use wlf_core::{value, Event, EventMeta, Value};
+
+use crate::{
+    sql_analyzer::{SqlAnalyzer, TableRef},
+    Error,
+};
+
+pub(crate) fn into_wlf_event(
+    sql_analyzer: &mut SqlAnalyzer,
+    event_header: EventHeader,
+    binlog_event: BinlogEvent,
+) -> Result<Vec<Event>, Error> {
+    let LocalResult::Single(timestamp) = Utc.timestamp_opt(event_header.timestamp as i64, 0) else {
+        return Err(Error::Other("failed to convert timestamp".to_string()));
+    };
+    let row_event = |(database, table): &TableRef, kind: &str, data: Value| Event {
+        value: value!({
+            "database": database,
+            "table": table,
+            "type": kind,
+            "timestamp": timestamp,
+            "server_id": event_header.server_id,
+            "data": data,
+        }),
+        meta: EventMeta::default(),
+    };
+    match binlog_event {
+        BinlogEvent::QueryEvent(e) => {
+            let mut value = value!({
+                "timestamp": timestamp,
+                "server_id": event_header.server_id,
+                "thread_id": e.thread_id,
+            });
+
+            let mut sql_properties = sql_analyzer.analyze(&e.database_name, &e.sql_statement)?;
+            if sql_properties.is_null() {
+                return Ok(vec![]);
+            }
+
+            value
+                .as_object_mut()
+                .unwrap()
+                .append(sql_properties.as_object_mut().unwrap());
+
+            Ok(vec![Event {
+                value,
+                meta: EventMeta::default(),
+            }])
+        }
+        BinlogEvent::TableMapEvent(e) => {
+            sql_analyzer.map_table(&e.database_name, &e.table_name, e.table_id);
+            Ok(vec![])
+        }
+        BinlogEvent::WriteRowsEvent(e) => {
+            let table_ref = sql_analyzer.get_table_info(e.table_id)?;
+            let columns = sql_analyzer.get_column_defs(e.table_id)?;
+            Ok(e.rows
+                .iter()
+                .map(|row| row_event(table_ref, "insert", row_to_value(columns, row)))
+                .collect())
+        }
+        BinlogEvent::UpdateRowsEvent(e) => {
+            let table_ref = sql_analyzer.get_table_info(e.table_id)?;
+            let columns = sql_analyzer.get_column_defs(e.table_id)?;
+            Ok(e.rows
+                .iter()
+                .map(|row| {
+                    let data = row_to_value(columns, &row.after_update);
+                    let mut old = row_to_value(columns, &row.before_update);
+                    // like maxwell, `old` only contains the columns that were changed
+                    if let (Value::Object(old), Value::Object(data)) = (&mut old, &data) {
+                        old.retain(|column, v| data.get(column) != Some(v));
+                    }
+                    let mut event = row_event(table_ref, "update", data);
+                    event.value["old"] = old;
+                    event
+                })
+                .collect())
+        }
+        BinlogEvent::DeleteRowsEvent(e) => {
+            let table_ref = sql_analyzer.get_table_info(e.table_id)?;
+            let columns = sql_analyzer.get_column_defs(e.table_id)?;
+            Ok(e.rows
+                .iter()
+                .map(|row| row_event(table_ref, "delete", row_to_value(columns, row)))
+                .collect())
+        }
+        BinlogEvent::RotateEvent(_)
+        | BinlogEvent::UnknownEvent
+        | BinlogEvent::FormatDescriptionEvent(_)
+        | BinlogEvent::HeartbeatEvent(_)
+        | BinlogEvent::XidEvent(_) => Ok(vec![]),
+        _ => Err(Error::Other("unsupported binlog event".to_string())),
+    }
+}
+
+fn row_to_value(columns: &[ColumnDef], row: &RowData) -> Value {
+    if row.cells.len() != columns.len() {
+        warn!("row data and column definitions do not match");
+    }
+    columns
+        .iter()
+        .zip(&row.cells)
+        .map(|(def, cell)| {
+            (
+                def.name.to_string(),
+                cell.as_ref().map_or(Value::Null, |v| cell_to_value(def, v)),
+            )
+        })
+        .collect()
+}
+
+/// The json value of a cell, the way maxwell writes it.
+fn cell_to_value(def: &ColumnDef, cell: &MySqlValue) -> Value {
+    // integers are read unsigned, signed columns are told apart by their definition
+    let unsigned = matches!(
+        def.data_type,
+        DataType::UnsignedTinyInt(_)
+            | DataType::UnsignedSmallInt(_)
+            | DataType::UnsignedMediumInt(_)
+            | DataType::UnsignedInt(_)
+            | DataType::UnsignedInteger(_)
+            | DataType::UnsignedBigInt(_)
+    );
+    match cell {
+        MySqlValue::TinyInt(n) if !unsigned => (*n as i8).into(),
+        MySqlValue::TinyInt(n) => (*n).into(),
+        MySqlValue::SmallInt(n) if !unsigned => (*n as i16).into(),
+        MySqlValue::SmallInt(n) => (*n).into(),
+        // 24 bits, the sign bit is extended by the arithmetic shift
+        MySqlValue::MediumInt(n) if !unsigned => (((*n << 8) as i32) >> 8).into(),
+        MySqlValue::MediumInt(n) => (*n).into(),
+        MySqlValue::Int(n) if !unsigned => (*n as i32).into(),
+        MySqlValue::Int(n) => (*n).into(),
+        MySqlValue::BigInt(n) if !unsigned => (*n as i64).into(),
+        MySqlValue::BigInt(n) => (*n).into(),
+        // through the shortest representation, so that 0.1 stays 0.1
+        MySqlValue::Float(n) => float(n.to_string().parse().unwrap_or(f64::NAN)),
+        MySqlValue::Double(n) => float(*n),
+        MySqlValue::Decimal(n) => n.as_str().into(),
+        MySqlValue::String(s) => s.as_str().into(),
+        MySqlValue::Blob(bytes) => String::from_utf8_lossy(bytes).into(),
+        MySqlValue::Enum(i) => match &def.data_type {
+            // 1 based, 0 is the empty string of an invalid value
+            DataType::Enum(names) => match *i as usize {
+                0 => "".into(),
+                i => names
+                    .get(i - 1)
+                    .map_or(Value::Null, |name| name.as_str().into()),
+            },
+            _ => (*i).into(),
+        },
+        MySqlValue::Set(bits) => match &def.data_type {
+            DataType::Set(names) => names
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| bits & (1 << i) != 0)
+                .map(|(_, name)| Value::from(name.as_str()))
+                .collect(),
+            _ => (*bits).into(),
+        },
+        MySqlValue::Year(year) => (*year).into(),
+        MySqlValue::Date(d) => format!("{:04}-{:02}-{:02}", d.year, d.month, d.day).into(),
+        MySqlValue::Time(t) => {
+            let time = format!("{:02}:{:02}:{:02}", t.hour, t.minute, t.second);
+            with_millis(time, t.millis).into()
+        }
+        MySqlValue::DateTime(dt) => {
+            let datetime = format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+            );
+            with_millis(datetime, dt.millis).into()
+        }
+        MySqlValue::Timestamp(millis) => match Utc.timestamp_millis_opt(*millis as i64) {
+            LocalResult::Single(t) => {
+                let datetime = t.format("%Y-%m-%d %H:%M:%S").to_string();
+                with_millis(datetime, (*millis % 1000) as u32).into()
+            }
+            _ => Value::Null,
+        },
+        // the most significant bit first
+        MySqlValue::Bit(bits) => bits
+            .iter()
+            .fold(0u64, |n, bit| (n << 1) | *bit as u64)
+            .into(),
+    }
+}
+
+fn float(n: f64) -> Value {
+    serde_json::Number::from_f64(n).map_or(Value::Null, Value::Number)
+}
+
+fn with_millis(time: String, millis: u32) -> String {
+    if millis == 0 {
+        time
+    } else {
+        format!("{time}.{millis:03}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlparser::{ast::Statement, dialect::MySqlDialect, parser::Parser};
+
+    use super::*;
+
+    fn columns(sql: &str) -> Vec<ColumnDef> {
+        match Parser::parse_sql(&MySqlDialect {}, sql).unwrap().remove(0) {
+            Statement::CreateTable { columns, .. } => columns,
+            _ => panic!("not a CREATE TABLE"),
+        }
+    }
+
+    #[test]
+    fn convert_cells() {
+        let columns = columns(
+            "CREATE TABLE t (a INT, b INT UNSIGNED, c MEDIUMINT, d FLOAT, e DECIMAL(10, 2), \
+             f ENUM('new', 'paid'), g SET('red', 'green', 'blue'), h TEXT)",
+        );
+        let row = RowData {
+            cells: vec![
+                Some(MySqlValue::Int(u32::MAX)),
+                Some(MySqlValue::Int(u32::MAX)),
+                Some(MySqlValue::MediumInt(0xffffff)),
+                Some(MySqlValue::Float(0.1)),
+                Some(MySqlValue::Decimal("12.50".to_string())),
+                Some(MySqlValue::Enum(2)),
+                Some(MySqlValue::Set(0b101)),
+                Some(MySqlValue::Blob(b"hello".to_vec())),
+            ],
+        };
+
+        assert_eq!(
+            row_to_value(&columns, &row),
+            value!({
+                "a": -1,
+                "b": 4294967295u32,
+                "c": -1,
+                "d": 0.1,
+                "e": "12.50",
+                "f": "paid",
+                "g": ["red", "blue"],
+                "h": "hello",
+            })
+        );
+    }
+}
@@ -1,22 +1,24 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use chrono::{LocalResult, TimeZone, Utc};
+use chrono::Utc;
 use futures_util::{pin_mut, StreamExt};
-use mysql_cdc::{
-    binlog_client::BinlogClient,
-    events::{binlog_event::BinlogEvent, event_header::EventHeader},
-};
+use mysql_cdc::{binlog_client::BinlogClient, events::binlog_event::BinlogEvent};
 
-use serde::Deserialize;
+use checkpoint::{Checkpoint, Checkpointer};
+use event::into_wlf_event;
+use serde::{Deserialize, Serialize};
 use sql_analyzer::SqlAnalyzer;
 use tracing::{info, warn};
 use wlf_core::{
     event_router::{EventRouter, EventRouterApi},
-    value, ComponentApi, ComponentKind, Event, EventMeta, Value,
+    metrics::Metric,
+    ComponentApi, ComponentKind, Delivery, Event, Value,
 };
 
+mod checkpoint;
 mod error;
+mod event;
 mod sql_analyzer;
 
 pub use error::Error;
@@ -24,7 +26,7 @@ pub use mysql_cdc::binlog_options::BinlogOptions;
 pub use mysql_cdc::replica_options::ReplicaOptions;
 pub use mysql_cdc::ssl_mode::SslMode;
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BinlogCollector {
     pub id: String,
     pub destination: String,
@@ -33,8 +35,34 @@ pub struct BinlogCollector {
     pub user: String,
     #[serde(default)]
     pub password: String,
-    #[serde(default = "default_port")]
+    #[serde(default = "default_port", deserialize_with = "utils::number")]
     pub port: u16,
+    /// Server id of the collector as a replica, unique among the replicas of the server.
+    #[serde(
+        default = "default_replica_server_id",
+        deserialize_with = "utils::number"
+    )]
+    pub replica_server_id: u32,
+    #[serde(default)]
+    pub output: BinlogOutput,
+    /// File keeping the binlog position, replication resumes from it after a restart instead of
+    /// starting at the end of the binlog.
+    #[serde(default)]
+    pub checkpoint_file: Option<PathBuf>,
+    /// Save the position at most this often. Only the positions whose events are delivered are
+    /// saved, on shutdown after waiting for the events in flight.
+    #[serde(
+        default = "default_checkpoint_interval_secs",
+        deserialize_with = "utils::number"
+    )]
+    pub checkpoint_interval_secs: u64,
+    /// How long shutting down waits for the events in flight to be delivered. The events after
+    /// the saved position are replicated again on start.
+    #[serde(
+        default = "default_delivery_timeout_secs",
+        deserialize_with = "utils::number"
+    )]
+    pub delivery_timeout_secs: u64,
 }
 
 pub fn default_host() -> String {
@@ -45,6 +73,86 @@ pub const fn default_port() -> u16 {
     3306
 }
 
+pub const fn default_replica_server_id() -> u32 {
+    65535
+}
+
+pub const fn default_checkpoint_interval_secs() -> u64 {
+    5
+}
+
+pub const fn default_delivery_timeout_secs() -> u64 {
+    10
+}
+
+/// What the events contain.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BinlogOutput {
+    /// Emit the events of SQL statements, e.g. `CREATE TABLE`.
+    pub ddl: bool,
+    /// Add the binlog position as `file:position`.
+    pub binlog_position: bool,
+    pub server_id: bool,
+    /// Keep the columns whose value is null.
+    pub nulls: bool,
+    /// Add the values of the primary key columns of a row as `primary_key`.
+    pub primary_key: bool,
+    /// Add the names of the primary key columns of a row as `primary_key_columns`.
+    pub primary_key_columns: bool,
+}
+
+impl Default for BinlogOutput {
+    fn default() -> Self {
+        Self {
+            ddl: true,
+            binlog_position: false,
+            server_id: true,
+            nulls: true,
+            primary_key: false,
+            primary_key_columns: false,
+        }
+    }
+}
+
+impl BinlogOutput {
+    fn apply(&self, event: &mut Event, position: Option<&str>, sql_analyzer: &SqlAnalyzer) {
+        let Value::Object(value) = &mut event.value else {
+            return;
+        };
+        if let (
+            Some(Value::String(database)),
+            Some(Value::String(table)),
+            Some(Value::Object(data)),
+        ) = (value.get("database"), value.get("table"), value.get("data"))
+        {
+            let columns = sql_analyzer.get_primary_key(database, table);
+            let values: Vec<Value> = columns
+                .iter()
+                .map(|c| data.get(c).cloned().unwrap_or(Value::Null))
+                .collect();
+            let columns: Vec<Value> = columns.iter().map(|c| c.as_str().into()).collect();
+            if self.primary_key {
+                value.insert("primary_key".to_string(), values.into());
+            }
+            if self.primary_key_columns {
+                value.insert("primary_key_columns".to_string(), columns.into());
+            }
+        }
+        if !self.server_id {
+            value.remove("server_id");
+        }
+        if !self.nulls {
+            if let Some(Value::Object(data)) = value.get_mut("data") {
+                data.retain(|_, v| !v.is_null());
+            }
+        }
+        if let (true, Some(position)) = (self.binlog_position, position) {
+            value.insert("position".to_string(), position.into());
+        }
+    }
+}
+
 #[async_trait]
 impl ComponentApi for BinlogCollector {
     fn id(&self) -> &str {
@@ -53,113 +161,155 @@ impl ComponentApi for BinlogCollector {
     fn kind(&self) -> ComponentKind {
         ComponentKind::Collector
     }
+    fn reports_readiness(&self) -> bool {
+        true
+    }
 
     async fn run(&self, router: Arc<EventRouter>) -> Result<(), Box<dyn std::error::Error>> {
+        let checkpoint = match &self.checkpoint_file {
+            Some(path) => Checkpoint::load(path)?,
+            None => None,
+        };
+        let binlog = match &checkpoint {
+            Some(Checkpoint { filename, position }) => {
+                info!("{} resumes from {filename}:{position}", self.id);
+                BinlogOptions::from_position(filename.clone(), *position)
+            }
+            None => BinlogOptions::from_end(),
+        };
+        // the binlog file is unknown until the first rotate event, unless resumed
+        let mut binlog_file = checkpoint.as_ref().map(|c| c.filename.clone());
+        let mut checkpointer = self.checkpoint_file.clone().map(|path| {
+            let interval = Duration::from_secs(self.checkpoint_interval_secs);
+            Checkpointer::new(path, interval, checkpoint)
+        });
+
         // create the binlog client
         let mut client = BinlogClient::new(ReplicaOptions {
+            hostname: self.host.clone(),
+            port: self.port,
             username: self.user.clone(),
             password: self.password.clone(),
+            server_id: self.replica_server_id,
             ssl_mode: SslMode::Disabled,
-            binlog: BinlogOptions::from_end(),
+            binlog,
             ..Default::default()
         });
 
         let events_stream = client.replicate().await?;
+        router.set_ready(&self.id, true);
         pin_mut!(events_stream);
 
         // create sql parser
         let mut sql_parser = SqlAnalyzer::new();
 
-        while let Some(Ok((event_header, binlog_event))) = events_stream.next().await {
+        // the events up to the next boundary share a delivery, whose receipt tells the
+        // checkpointer when they are all handled
+        let (mut delivery, mut receipt) = Delivery::track();
+        let mut last_timestamp = None;
+        loop {
+            let next = tokio::select! {
+                next = events_stream.next() => next,
+                _ = router.shutdown_requested(&self.id) => {
+                    info!("{} stops replicating", self.id);
+                    break;
+                }
+                // the deliveries are also followed while the binlog is quiet
+                _ = async { checkpointer.as_ref().unwrap().changed().await },
+                    if checkpointer.is_some() =>
+                {
+                    if let Some(checkpointer) = &mut checkpointer {
+                        checkpointer.save_if_due()?;
+                    }
+                    continue;
+                }
+            };
+            let Some(Ok((event_header, binlog_event))) = next else {
+                break;
+            };
             info!("new binlog event:\n\t{event_header:?}\n\t{binlog_event:?}");
-            match into_wlf_event(&mut sql_parser, event_header, binlog_event) {
-                Ok(Some(event)) => router.send_event(event, &self.destination).await?,
-                Ok(None) => {}
-                Err(e) => warn!("failed to convert binlog event, {e}"),
+            // some events, like the rotate event at the start or the heartbeats sent while
+            // the binlog is quiet, are not timestamped but still refresh the lag
+            if event_header.timestamp > 0 {
+                last_timestamp = Some(event_header.timestamp as i64);
+            }
+            if let Some(timestamp) = last_timestamp {
+                let lag = (Utc::now().timestamp() - timestamp).max(0) as f64;
+                router.metrics().set(Metric::BinlogLag, &self.id, lag);
             }
-        }
 
-        Ok(())
-    }
-}
+            // only resume between transactions, where no table map is missing
+            let boundary = match &binlog_event {
+                BinlogEvent::RotateEvent(e) => {
+                    Some((Some(e.binlog_filename.clone()), e.binlog_position as u32))
+                }
+                BinlogEvent::XidEvent(_) => Some((None, event_header.next_event_position)),
+                BinlogEvent::QueryEvent(e) if is_commit(&e.sql_statement) => {
+                    Some((None, event_header.next_event_position))
+                }
+                _ => None,
+            };
 
-/// The event structure is largely borrowed from [maxwells](https://maxwells-daemon.io/dataformat/)
-fn into_wlf_event(
-    sql_analyzer: &mut SqlAnalyzer,
-    event_header: EventHeader,
-    binlog_event: BinlogEvent,
-) -> Result<Option<Event>, Error> {
-    let LocalResult::Single(timestamp) = Utc.timestamp_opt(event_header.timestamp as i64, 0) else {
-        return Err(Error::Other("failed to convert timestamp".to_string()));
-    };
-    match binlog_event {
-        BinlogEvent::QueryEvent(e) => {
-            let mut value = value!({
-                "timestamp": timestamp,
-                "server_id": event_header.server_id,
-                "thread_id": e.thread_id,
-            });
-
-            let mut sql_properties = sql_analyzer.analyze(&e.database_name, &e.sql_statement)?;
-            if sql_properties.is_null() {
-                return Ok(None);
+            if let BinlogEvent::RotateEvent(e) = &binlog_event {
+                binlog_file = Some(e.binlog_filename.clone());
             }
+            let position = binlog_file
+                .as_ref()
+                .map(|file| format!("{file}:{}", event_header.next_event_position));
+            let ddl = matches!(binlog_event, BinlogEvent::QueryEvent(_));
 
-            value
-                .as_object_mut()
-                .unwrap()
-                .append(sql_properties.as_object_mut().unwrap());
+            match into_wlf_event(&mut sql_parser, event_header, binlog_event) {
+                Ok(_) if ddl && !self.output.ddl => {}
+                Ok(events) => {
+                    for mut event in events {
+                        self.output
+                            .apply(&mut event, position.as_deref(), &sql_parser);
+                        event.meta.delivery = Some(delivery.clone());
+                        router
+                            .send_event(event, &self.id, &self.destination)
+                            .await?;
+                    }
+                }
+                Err(e) => warn!("failed to convert binlog event, {e}"),
+            }
+            if let Some(position) = position {
+                router.set_position(&self.id, position);
+            }
 
-            Ok(Some(Event {
-                value,
-                meta: EventMeta {},
-            }))
-        }
-        BinlogEvent::TableMapEvent(e) => {
-            sql_analyzer.map_table(&e.database_name, &e.table_name, e.table_id);
-            Ok(None)
-        }
-        BinlogEvent::WriteRowsEvent(e) => {
-            let (database, table) = sql_analyzer.get_table_info(e.table_id)?;
-            let columns = sql_analyzer.get_column_defs(e.table_id)?;
-            let mut value = value!({
-                "database": database,
-                "table": table,
-                "type": "insert",
-                "timestamp": timestamp,
-                "server_id": event_header.server_id,
-                "data": []
-            });
-            let data = value.pointer_mut("/data").unwrap().as_array_mut().unwrap();
-            for (i, r) in e.rows.iter().enumerate() {
-                let mut row_value = value!({});
-                let row_data = row_value.as_object_mut().unwrap();
-                for c in &r.cells {
-                    let Some(def) = columns.get(i) else {
-                        warn!("row data and column definitions do not match");
-                        break;
-                    };
-                    row_data.insert(
-                        def.name.to_string(),
-                        c.as_ref().map_or(Value::Null, |v| format!("{v:?}").into()),
-                    );
+            if let Some((filename, position)) = boundary {
+                let (next_delivery, next_receipt) = Delivery::track();
+                delivery = next_delivery;
+                let receipt = std::mem::replace(&mut receipt, next_receipt);
+                if let Some(checkpointer) = &mut checkpointer {
+                    checkpointer.advance(filename, position, receipt)?;
                 }
-                data.push(row_value);
             }
-            Ok(Some(Event {
-                value,
-                meta: EventMeta {},
-            }))
         }
-        BinlogEvent::RotateEvent(_)
-        | BinlogEvent::UnknownEvent
-        | BinlogEvent::FormatDescriptionEvent(_)
-        | BinlogEvent::HeartbeatEvent(_)
-        | BinlogEvent::XidEvent(_) => Ok(None),
-        _ => Err(Error::Other("unsupported binlog event".to_string())),
+
+        if let Some(checkpointer) = &mut checkpointer {
+            let timeout = Duration::from_secs(self.delivery_timeout_secs);
+            let delivered = tokio::time::timeout(timeout, checkpointer.delivered()).await;
+            checkpointer.save()?;
+            if delivered.is_err() {
+                warn!(
+                    "{} stops before the events up to {:?} are delivered, they will be \
+                     replicated again",
+                    self.id,
+                    checkpointer.pending_positions()
+                );
+            }
+        }
+
+        Ok(())
     }
 }
 
+/// Whether the statement ends a transaction, e.g. one of a non-transactional table, whose
+/// changes are not followed by a XID event.
+fn is_commit(sql: &str) -> bool {
+    sql.trim().eq_ignore_ascii_case("COMMIT")
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
@@ -167,10 +317,14 @@ mod tests {
     use utils::test_utils::DummyComponent;
     use wlf_core::{
         event_router::{EventRouter, EventRouterApi},
-        ComponentApi, ComponentKind,
+        value, ComponentApi, ComponentKind, Event, EventMeta,
     };
 
-    use crate::{default_host, default_port, BinlogCollector};
+    use crate::{
+        default_checkpoint_interval_secs, default_delivery_timeout_secs, default_host,
+        default_port, default_replica_server_id, is_commit, sql_analyzer::SqlAnalyzer,
+        BinlogCollector, BinlogOutput,
+    };
 
     #[tokio::test]
     async fn collect() {
@@ -181,11 +335,16 @@ mod tests {
             host: default_host(),
             password: "password".to_string(),
             port: default_port(),
+            replica_server_id: default_replica_server_id(),
+            output: Default::default(),
+            checkpoint_file: None,
+            checkpoint_interval_secs: default_checkpoint_interval_secs(),
+            delivery_timeout_secs: default_delivery_timeout_secs(),
         };
 
         let dummy_dispatcher = DummyComponent::new("dispatcher", ComponentKind::Dispatcher);
 
-        let mut router = EventRouter::new();
+        let router = EventRouter::new();
         router.register_component(&collector);
         router.register_component(&dummy_dispatcher);
         let router = Arc::new(router);
@@ -202,4 +361,58 @@ mod tests {
             println!("{event:#?}");
         }
     }
+
+    #[test]
+    fn end_transactions() {
+        assert!(is_commit("COMMIT"));
+        assert!(is_commit(" commit\n"));
+        assert!(!is_commit("BEGIN"));
+        assert!(!is_commit("CREATE TABLE t (id INT)"));
+    }
+
+    #[test]
+    fn output_options() {
+        let output = BinlogOutput {
+            ddl: true,
+            binlog_position: true,
+            server_id: false,
+            nulls: false,
+            primary_key: true,
+            primary_key_columns: true,
+        };
+        let mut sql_analyzer = SqlAnalyzer::new();
+        sql_analyzer
+            .analyze("d1", "CREATE TABLE t1 (id INT PRIMARY KEY, name TEXT)")
+            .unwrap();
+        let mut event = Event {
+            value: value!({
+                "database": "d1",
+                "table": "t1",
+                "server_id": 1,
+                "data": { "id": 1, "name": null },
+            }),
+            meta: EventMeta::default(),
+        };
+        output.apply(&mut event, Some("binlog.000002:120"), &sql_analyzer);
+        assert_eq!(
+            event.value,
+            value!({
+                "database": "d1",
+                "table": "t1",
+                "data": { "id": 1 },
+                "primary_key": [1],
+                "primary_key_columns": ["id"],
+                "position": "binlog.000002:120",
+            })
+        );
+
+        sql_analyzer
+            .analyze(
+                "d1",
+                "CREATE TABLE t2 (a INT, b INT, c INT, PRIMARY KEY (a, b))",
+            )
+            .unwrap();
+        assert_eq!(sql_analyzer.get_primary_key("d1", "t2"), ["a", "b"]);
+        assert!(sql_analyzer.get_primary_key("d1", "t3").is_empty());
+    }
 }
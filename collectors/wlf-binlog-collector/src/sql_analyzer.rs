@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use sqlparser::{
-    ast::{ColumnDef, Statement},
+    ast::{ColumnDef, ColumnOption, Statement, TableConstraint},
     dialect::MySqlDialect,
     parser::{Parser, ParserError},
 };
@@ -26,6 +26,7 @@ pub(crate) type TableRef = (String, String);
 pub(crate) struct SqlAnalyzer {
     table_map: HashMap<u64, TableRef>,
     columns_map: HashMap<TableRef, Vec<ColumnDef>>,
+    primary_keys: HashMap<TableRef, Vec<String>>,
 }
 
 impl SqlAnalyzer {
@@ -33,6 +34,7 @@ impl SqlAnalyzer {
         Self {
             table_map: HashMap::new(),
             columns_map: HashMap::new(),
+            primary_keys: HashMap::new(),
         }
     }
 
@@ -53,7 +55,12 @@ impl SqlAnalyzer {
                     "table": table_name.to_string()
                 })
             }
-            Statement::CreateTable { name, columns, .. } => {
+            Statement::CreateTable {
+                name,
+                columns,
+                constraints,
+                ..
+            } => {
                 let mut value = value!({
                     "type": "table-create",
                     "database" : database,
@@ -68,8 +75,10 @@ impl SqlAnalyzer {
                 for column in &columns {
                     map.insert(column.name.to_string(), column.data_type.to_string().into());
                 }
-                self.columns_map
-                    .insert((database.to_string(), name.to_string()), columns);
+                let table_ref = (database.to_string(), name.to_string());
+                self.primary_keys
+                    .insert(table_ref.clone(), primary_key(&columns, &constraints));
+                self.columns_map.insert(table_ref, columns);
                 value
             }
             Statement::CreateDatabase { .. } => {
@@ -96,6 +105,13 @@ impl SqlAnalyzer {
             .ok_or(Error::TableIdNotFound(table_id))
     }
 
+    /// The primary key columns of the table, empty if it has none or is unknown.
+    pub(crate) fn get_primary_key(&self, database: &str, table: &str) -> &[String] {
+        self.primary_keys
+            .get(&(database.to_string(), table.to_string()))
+            .map_or(&[], Vec::as_slice)
+    }
+
     pub(crate) fn get_column_defs(&self, table_id: u64) -> Result<&Vec<ColumnDef>, Error> {
         let table_ref = self
             .table_map
@@ -107,3 +123,26 @@ impl SqlAnalyzer {
         ))
     }
 }
+
+/// The primary key columns, declared either with a column or as a constraint of the table.
+fn primary_key(columns: &[ColumnDef], constraints: &[TableConstraint]) -> Vec<String> {
+    for constraint in constraints {
+        if let TableConstraint::Unique {
+            columns,
+            is_primary: true,
+            ..
+        } = constraint
+        {
+            return columns.iter().map(|c| c.to_string()).collect();
+        }
+    }
+    columns
+        .iter()
+        .filter(|c| {
+            c.options
+                .iter()
+                .any(|o| matches!(o.option, ColumnOption::Unique { is_primary: true }))
+        })
+        .map(|c| c.name.to_string())
+        .collect()
+}
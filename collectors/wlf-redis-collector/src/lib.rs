@@ -0,0 +1,588 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures_util::{FutureExt, StreamExt};
+use redis::{
+    aio, streams::StreamReadReply, ConnectionAddr, ConnectionInfo, FromRedisValue, Msg,
+    RedisConnectionInfo, RedisError,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{info, warn};
+use wlf_core::{
+    event_router::{EventRouter, EventRouterApi},
+    value, ComponentApi, ComponentKind, Delivery, Event, EventMeta, Receipt, Value,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("redis error, {0}")]
+    Redis(#[from] RedisError),
+    #[error("event router error, {0}")]
+    EventRouter(#[from] wlf_core::event_router::Error),
+    #[error("bad configuration, {0}")]
+    Config(String),
+    #[error("the connection to redis is closed")]
+    Disconnected,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RedisCollector {
+    pub id: String,
+    pub destination: String,
+    pub mode: Mode,
+    #[serde(flatten)]
+    pub config: Config,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Config {
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port", deserialize_with = "utils::number")]
+    pub port: u16,
+    /// ACL user, the default user is used if not set
+    pub username: Option<String>,
+    pub auth: Option<String>,
+    #[serde(
+        default = "default_database_number",
+        deserialize_with = "utils::number"
+    )]
+    pub database_number: u8,
+    /// Connect with TLS, requires the `tls` feature
+    #[serde(default)]
+    pub tls: bool,
+    /// Skip the verification of the server certificate
+    #[serde(default)]
+    pub tls_insecure: bool,
+}
+
+impl Config {
+    fn connection_info(&self) -> ConnectionInfo {
+        let addr = if self.tls {
+            ConnectionAddr::TcpTls {
+                host: self.host.clone(),
+                port: self.port,
+                insecure: self.tls_insecure,
+            }
+        } else {
+            ConnectionAddr::Tcp(self.host.clone(), self.port)
+        };
+        ConnectionInfo {
+            addr,
+            redis: RedisConnectionInfo {
+                db: self.database_number as i64,
+                username: self.username.clone(),
+                password: self.auth.clone(),
+            },
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: default_host(),
+            port: default_port(),
+            username: None,
+            auth: None,
+            database_number: default_database_number(),
+            tls: false,
+            tls_insecure: false,
+        }
+    }
+}
+
+pub fn default_host() -> String {
+    "localhost".to_string()
+}
+
+pub fn default_port() -> u16 {
+    6379
+}
+
+pub fn default_database_number() -> u8 {
+    0
+}
+
+/// Where the messages are consumed from. Payloads that are valid json are decoded, others are
+/// kept as strings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Mode {
+    /// `SUBSCRIBE`/`PSUBSCRIBE`, messages published while the collector is down are lost.
+    Subscribe {
+        #[serde(default)]
+        channels: Vec<String>,
+        #[serde(default)]
+        patterns: Vec<String>,
+    },
+    /// `BLPOP` from list queues, an element is removed as soon as it is popped.
+    BLPop {
+        keys: Vec<String>,
+        /// How long a single `BLPOP` waits for an element, 0 to wait forever. Shutting down
+        /// waits for the pending `BLPOP` to return.
+        #[serde(default = "default_block_secs", deserialize_with = "utils::number")]
+        timeout_secs: u64,
+    },
+    /// `XREADGROUP` from streams, entries are acknowledged once their events are delivered by
+    /// the dispatchers, filtered out or dead-lettered. Entries delivered to this consumer but
+    /// never acknowledged are read again on start.
+    XReadGroup {
+        keys: Vec<String>,
+        group: String,
+        consumer: String,
+        /// Maximum number of entries per read
+        #[serde(default = "default_count", deserialize_with = "utils::number")]
+        count: usize,
+        /// How long a read waits for new entries, 0 to wait forever. Reads wait at most
+        /// 100ms while entries are waiting to be acknowledged.
+        #[serde(default = "default_block_ms", deserialize_with = "utils::number")]
+        block_ms: usize,
+        /// Create the group (and the stream) if it does not exist yet, starting at new entries.
+        #[serde(default = "default_create_group")]
+        create_group: bool,
+        /// How long shutting down waits for the events read to be delivered. The entries left
+        /// unacknowledged are read again on start.
+        #[serde(
+            default = "default_delivery_timeout_secs",
+            deserialize_with = "utils::number"
+        )]
+        delivery_timeout_secs: u64,
+    },
+}
+
+pub fn default_block_secs() -> u64 {
+    5
+}
+
+pub fn default_count() -> usize {
+    100
+}
+
+pub fn default_block_ms() -> usize {
+    5000
+}
+
+/// How long a read blocks at most while entries are waiting to be acknowledged, acks are only
+/// sent between reads.
+const ACK_INTERVAL_MS: usize = 100;
+
+pub fn default_create_group() -> bool {
+    true
+}
+
+pub fn default_delivery_timeout_secs() -> u64 {
+    10
+}
+
+impl RedisCollector {
+    pub fn new(id: impl Into<String>, destination: impl Into<String>, mode: Mode) -> Self {
+        Self {
+            id: id.into(),
+            destination: destination.into(),
+            mode,
+            config: Default::default(),
+        }
+    }
+
+    pub fn set_password(&mut self, password: impl Into<String>) -> &mut Self {
+        self.config.auth = Some(password.into());
+        self
+    }
+}
+
+#[async_trait]
+impl ComponentApi for RedisCollector {
+    fn id(&self) -> &str {
+        self.id.as_str()
+    }
+    fn kind(&self) -> ComponentKind {
+        ComponentKind::Collector
+    }
+
+    fn reports_readiness(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, router: Arc<EventRouter>) -> Result<(), Box<dyn std::error::Error>> {
+        let con = redis::Client::open(self.config.connection_info())?
+            .get_async_connection()
+            .await?;
+        router.set_ready(&self.id, true);
+
+        match &self.mode {
+            Mode::Subscribe { channels, patterns } => {
+                self.subscribe(con, channels, patterns, &router).await?
+            }
+            Mode::BLPop { keys, timeout_secs } => {
+                self.blpop(con, keys, *timeout_secs, &router).await?
+            }
+            Mode::XReadGroup { .. } => self.xreadgroup(con, &router).await?,
+        }
+
+        Ok(())
+    }
+}
+
+impl RedisCollector {
+    async fn subscribe(
+        &self,
+        con: aio::Connection,
+        channels: &[String],
+        patterns: &[String],
+        router: &EventRouter,
+    ) -> Result<(), Error> {
+        if channels.is_empty() && patterns.is_empty() {
+            return Err(Error::Config(
+                "no channels or patterns to subscribe to".to_string(),
+            ));
+        }
+
+        let mut pubsub = con.into_pubsub();
+        for channel in channels {
+            pubsub.subscribe(channel).await?;
+        }
+        for pattern in patterns {
+            pubsub.psubscribe(pattern).await?;
+        }
+        info!("{} subscribed to {channels:?} and {patterns:?}", self.id);
+
+        let mut messages = pubsub.on_message();
+        loop {
+            let msg = tokio::select! {
+                msg = messages.next() => msg,
+                _ = router.shutdown_requested(&self.id) => break,
+            };
+            let Some(msg) = msg else {
+                return Err(Error::Disconnected);
+            };
+            router
+                .send_event(message_event(&msg), &self.id, &self.destination)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn blpop(
+        &self,
+        mut con: aio::Connection,
+        keys: &[String],
+        timeout_secs: u64,
+        router: &EventRouter,
+    ) -> Result<(), Error> {
+        if keys.is_empty() {
+            return Err(Error::Config("no keys to pop from".to_string()));
+        }
+
+        // a pop is never interrupted, the element would be lost
+        while router.shutdown_requested(&self.id).now_or_never().is_none() {
+            let popped: Option<(String, Vec<u8>)> = redis::cmd("BLPOP")
+                .arg(keys)
+                .arg(timeout_secs)
+                .query_async(&mut con)
+                .await?;
+            let Some((key, payload)) = popped else {
+                continue;
+            };
+            let event = Event {
+                value: value!({
+                    "key": key,
+                    "message": decode(&payload),
+                }),
+                meta: EventMeta::default(),
+            };
+            router
+                .send_event(event, &self.id, &self.destination)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn xreadgroup(
+        &self,
+        mut con: aio::Connection,
+        router: &EventRouter,
+    ) -> Result<(), Error> {
+        let Mode::XReadGroup {
+            keys,
+            group,
+            consumer,
+            count,
+            block_ms,
+            create_group,
+            delivery_timeout_secs,
+        } = &self.mode
+        else {
+            unreachable!("only called for the XReadGroup mode");
+        };
+        if keys.is_empty() {
+            return Err(Error::Config("no streams to read from".to_string()));
+        }
+
+        if *create_group {
+            for key in keys {
+                let res = redis::cmd("XGROUP")
+                    .arg("CREATE")
+                    .arg(key)
+                    .arg(group)
+                    .arg("$")
+                    .arg("MKSTREAM")
+                    .query_async::<_, ()>(&mut con)
+                    .await;
+                match res {
+                    Err(e) if e.code() == Some("BUSYGROUP") => {}
+                    res => res?,
+                }
+            }
+        }
+
+        // first drain what was delivered to us before but never acknowledged, then new entries
+        let mut pending = true;
+        // the last pending entry read of every stream
+        let mut last_ids = vec!["0".to_string(); keys.len()];
+        let mut unacked = Unacked::default();
+        loop {
+            unacked.ack_delivered(&mut con, group).await?;
+
+            let mut cmd = redis::cmd("XREADGROUP");
+            cmd.arg("GROUP")
+                .arg(group)
+                .arg(consumer)
+                .arg("COUNT")
+                .arg(count);
+            if !pending {
+                cmd.arg("BLOCK").arg(unacked.block_ms(*block_ms));
+            }
+            cmd.arg("STREAMS").arg(keys);
+            if pending {
+                cmd.arg(&last_ids);
+            } else {
+                cmd.arg(vec![">"; keys.len()]);
+            }
+
+            // interrupting a read is fine, what is read but not acknowledged is read again
+            let reply: Option<StreamReadReply> = tokio::select! {
+                reply = cmd.query_async(&mut con) => reply?,
+                _ = router.shutdown_requested(&self.id) => break,
+            };
+            let reply = reply.unwrap_or_default();
+            if pending && reply.keys.iter().all(|k| k.ids.is_empty()) {
+                info!("{} has no pending stream entries left", self.id);
+                pending = false;
+                continue;
+            }
+
+            for stream in reply.keys {
+                if let (true, Some(entry)) = (pending, stream.ids.last()) {
+                    if let Some(i) = keys.iter().position(|key| *key == stream.key) {
+                        last_ids[i] = entry.id.clone();
+                    }
+                }
+                for entry in stream.ids {
+                    let (delivery, receipt) = Delivery::track();
+                    // entries deleted while pending come back without fields
+                    if !entry.map.is_empty() {
+                        let event = Event {
+                            value: value!({
+                                "stream": stream.key,
+                                "id": entry.id,
+                                "fields": fields_to_value(&entry.map),
+                            }),
+                            meta: EventMeta {
+                                delivery: Some(delivery),
+                                ..Default::default()
+                            },
+                        };
+                        router
+                            .send_event(event, &self.id, &self.destination)
+                            .await?;
+                    }
+                    unacked.push(stream.key.clone(), entry.id, receipt);
+                }
+            }
+        }
+
+        let timeout = Duration::from_secs(*delivery_timeout_secs);
+        let delivered = tokio::time::timeout(timeout, unacked.delivered()).await;
+        unacked.ack_delivered(&mut con, group).await?;
+        if delivered.is_err() {
+            warn!(
+                "{} stops before the events of stream entries {:?} are delivered, they will be \
+                 read again",
+                self.id,
+                unacked.ids()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Stream entries read but not acknowledged yet, waiting for their events to be delivered.
+#[derive(Default)]
+struct Unacked(Vec<(String, String, Receipt)>);
+
+impl Unacked {
+    fn push(&mut self, stream: String, id: String, receipt: Receipt) {
+        self.0.push((stream, id, receipt));
+    }
+
+    /// Waits until the events of all entries are delivered.
+    async fn delivered(&self) {
+        for (_, _, receipt) in &self.0 {
+            receipt.delivered().await;
+        }
+    }
+
+    fn ids(&self) -> Vec<&str> {
+        self.0.iter().map(|(_, id, _)| id.as_str()).collect()
+    }
+
+    /// How long the next read may block, short enough to acknowledge the entries soon after
+    /// their events are delivered.
+    fn block_ms(&self, block_ms: usize) -> usize {
+        match block_ms {
+            _ if self.0.is_empty() => block_ms,
+            // 0 blocks forever
+            0 => ACK_INTERVAL_MS,
+            _ => block_ms.min(ACK_INTERVAL_MS),
+        }
+    }
+
+    /// Acknowledges the entries whose events are delivered.
+    async fn ack_delivered(&mut self, con: &mut aio::Connection, group: &str) -> Result<(), Error> {
+        for (stream, ids) in self.take_delivered() {
+            xack(&stream, group, &ids).query_async::<_, ()>(con).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes the entries whose events are delivered, returning their ids by stream.
+    fn take_delivered(&mut self) -> BTreeMap<String, Vec<String>> {
+        let mut delivered: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        self.0.retain(|(stream, id, receipt)| {
+            if receipt.is_delivered() {
+                delivered
+                    .entry(stream.clone())
+                    .or_default()
+                    .push(id.clone());
+            }
+            !receipt.is_delivered()
+        });
+        delivered
+    }
+}
+
+fn xack(stream: &str, group: &str, ids: &[String]) -> redis::Cmd {
+    let mut cmd = redis::cmd("XACK");
+    cmd.arg(stream).arg(group).arg(ids);
+    cmd
+}
+
+fn message_event(msg: &Msg) -> Event {
+    let mut value = value!({
+        "channel": msg.get_channel_name(),
+        "message": decode(msg.get_payload_bytes()),
+    });
+    if msg.from_pattern() {
+        match msg.get_pattern::<String>() {
+            Ok(pattern) => value["pattern"] = Value::String(pattern),
+            Err(e) => warn!("failed to get the pattern of a message, {e}"),
+        }
+    }
+    Event {
+        value,
+        meta: EventMeta::default(),
+    }
+}
+
+fn fields_to_value(fields: &HashMap<String, redis::Value>) -> Value {
+    let fields = fields
+        .iter()
+        .map(|(name, v)| {
+            let v = match Vec::<u8>::from_redis_value(v) {
+                Ok(bytes) => decode(&bytes),
+                Err(_) => Value::Null,
+            };
+            (name.clone(), v)
+        })
+        .collect();
+    Value::Object(fields)
+}
+
+/// Json payloads are decoded, anything else is kept as a (lossy) string.
+fn decode(payload: &[u8]) -> Value {
+    serde_json::from_slice(payload)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(payload).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ack_delivered_entries() {
+        let mut unacked = Unacked::default();
+        assert_eq!(unacked.block_ms(0), 0);
+        assert_eq!(unacked.block_ms(5000), 5000);
+        let mut deliveries = Vec::new();
+        for (stream, id) in [("a", "1-0"), ("b", "1-0"), ("a", "2-0"), ("a", "3-0")] {
+            let (delivery, receipt) = Delivery::track();
+            unacked.push(stream.to_string(), id.to_string(), receipt);
+            deliveries.push(delivery);
+        }
+        // the events of the second entry of `a` are still in flight
+        let in_flight = deliveries.remove(2);
+        drop(deliveries);
+
+        let delivered = unacked.take_delivered();
+        assert_eq!(
+            delivered,
+            BTreeMap::from([
+                ("a".to_string(), vec!["1-0".to_string(), "3-0".to_string()]),
+                ("b".to_string(), vec!["1-0".to_string()]),
+            ])
+        );
+        assert_eq!(unacked.ids(), vec!["2-0"]);
+        assert_eq!(unacked.block_ms(0), ACK_INTERVAL_MS);
+        assert_eq!(unacked.block_ms(5000), ACK_INTERVAL_MS);
+        assert_eq!(unacked.block_ms(10), 10);
+        assert_eq!(
+            xack("a", "wlf", &delivered["a"]).get_packed_command(),
+            redis::cmd("XACK")
+                .arg("a")
+                .arg("wlf")
+                .arg("1-0")
+                .arg("3-0")
+                .get_packed_command()
+        );
+
+        drop(in_flight);
+        assert_eq!(unacked.take_delivered().len(), 1);
+        assert!(unacked.ids().is_empty());
+    }
+
+    #[test]
+    fn decode_payloads() {
+        assert_eq!(decode(br#"{"a": 1}"#), value!({ "a": 1 }));
+        assert_eq!(decode(b"hello"), value!("hello"));
+
+        let fields = HashMap::from([
+            (
+                "event".to_string(),
+                redis::Value::Data(br#"{"value": {"id": 1}}"#.to_vec()),
+            ),
+            ("op".to_string(), redis::Value::Data(b"insert".to_vec())),
+        ]);
+        assert_eq!(
+            fields_to_value(&fields),
+            value!({ "event": { "value": { "id": 1 } }, "op": "insert" })
+        );
+    }
+}
@@ -0,0 +1,498 @@
+use std::{future::Future, time::Duration};
+
+use elasticsearch::{
+    http::{request::JsonBody, StatusCode},
+    BulkParts, Elasticsearch,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+use utils::retry::{Backoff, RetryConfig, Wait};
+use wlf_core::Event;
+
+use crate::{Action, Error};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BulkConfig {
+    /// Flush once this many actions are buffered.
+    #[serde(default = "default_max_actions", deserialize_with = "utils::number")]
+    pub max_actions: usize,
+    /// Flush once the buffered request body exceeds this many bytes.
+    #[serde(default = "default_max_bytes", deserialize_with = "utils::number")]
+    pub max_bytes: usize,
+    /// Flush buffered actions at least this often, in milliseconds.
+    #[serde(
+        default = "default_flush_interval_ms",
+        deserialize_with = "utils::non_zero"
+    )]
+    pub flush_interval_ms: u64,
+    /// How many times items rejected with a retriable status are resent, waiting as configured by
+    /// `retry` in between.
+    #[serde(default = "default_max_retries", deserialize_with = "utils::number")]
+    pub max_retries: u32,
+}
+
+impl BulkConfig {
+    /// Settings that send every event in its own request.
+    pub fn unbatched() -> Self {
+        Self {
+            max_actions: 1,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for BulkConfig {
+    fn default() -> Self {
+        Self {
+            max_actions: default_max_actions(),
+            max_bytes: default_max_bytes(),
+            flush_interval_ms: default_flush_interval_ms(),
+            max_retries: default_max_retries(),
+        }
+    }
+}
+
+pub fn default_max_actions() -> usize {
+    1000
+}
+
+pub fn default_max_bytes() -> usize {
+    5 * 1024 * 1024
+}
+
+pub fn default_flush_interval_ms() -> u64 {
+    1000
+}
+
+pub fn default_max_retries() -> u32 {
+    3
+}
+
+/// One action of a bulk request, i.e. the action line and the optional source line.
+#[derive(Debug, Clone)]
+pub(crate) struct BulkItem {
+    pub(crate) action: Value,
+    pub(crate) source: Option<Value>,
+}
+
+impl BulkItem {
+    pub(crate) fn new(
+        action: Action,
+        index: &str,
+        id: Option<String>,
+        doc: Value,
+    ) -> Result<Self, Error> {
+        let mut metadata = json!({ "_index": index });
+        if let Some(id) = &id {
+            metadata["_id"] = id.as_str().into();
+        }
+
+        let item = match action {
+            Action::Index => Self {
+                action: json!({ "index": metadata }),
+                source: Some(doc),
+            },
+            Action::Create => Self {
+                action: json!({ "create": metadata }),
+                source: Some(doc),
+            },
+            Action::Upsert => {
+                if id.is_none() {
+                    return Err(Error::MissingDocumentId(action));
+                }
+                Self {
+                    action: json!({ "update": metadata }),
+                    source: Some(json!({ "doc": doc, "doc_as_upsert": true })),
+                }
+            }
+            Action::Delete => {
+                if id.is_none() {
+                    return Err(Error::MissingDocumentId(action));
+                }
+                Self {
+                    action: json!({ "delete": metadata }),
+                    source: None,
+                }
+            }
+        };
+
+        Ok(item)
+    }
+
+    fn size(&self) -> usize {
+        self.action.to_string().len() + self.source.as_ref().map_or(0, |s| s.to_string().len())
+    }
+}
+
+/// An event whose bulk item is given up.
+pub(crate) struct DroppedEvent {
+    pub(crate) event: Event,
+    pub(crate) error: String,
+    /// How many bulk requests the item was part of
+    pub(crate) attempts: usize,
+}
+
+/// The outcome of a flush.
+#[derive(Default)]
+pub(crate) struct Flushed {
+    /// Events whose bulk items are given up
+    pub(crate) dropped: Vec<DroppedEvent>,
+    /// Events left undelivered because `stop` resolved while waiting to resend them
+    pub(crate) abandoned: Vec<Event>,
+}
+
+/// Buffers bulk actions until one of the size thresholds is reached.
+pub(crate) struct BulkBuffer {
+    config: BulkConfig,
+    retry: RetryConfig,
+    items: Vec<BulkItem>,
+    /// The events the items are built from, kept for dead-lettering
+    events: Vec<Event>,
+    bytes: usize,
+    nodes: usize,
+}
+
+impl BulkBuffer {
+    /// `nodes` is the number of nodes the client rotates through, a request that can't reach a
+    /// node is tried on the other ones before giving up.
+    pub(crate) fn new(config: BulkConfig, retry: RetryConfig, nodes: usize) -> Self {
+        Self {
+            config,
+            retry,
+            items: Vec::new(),
+            events: Vec::new(),
+            bytes: 0,
+            nodes,
+        }
+    }
+
+    pub(crate) fn push(&mut self, item: BulkItem, event: Event) {
+        self.bytes += item.size();
+        self.items.push(item);
+        self.events.push(event);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.items.len() >= self.config.max_actions || self.bytes >= self.config.max_bytes
+    }
+
+    pub(crate) fn flush_interval(&self) -> Duration {
+        Duration::from_millis(self.config.flush_interval_ms)
+    }
+
+    /// Sends all buffered items, resending the items that failed with a retriable status.
+    /// Returns the events of the items that are given up, and those that are not resent
+    /// because `stop` resolved in the meantime. `set_ready` is told whether the cluster could
+    /// be reached.
+    pub(crate) async fn flush(
+        &mut self,
+        client: &Elasticsearch,
+        set_ready: impl Fn(bool),
+        stop: impl Future<Output = ()>,
+    ) -> Flushed {
+        let mut items = std::mem::take(&mut self.items);
+        let mut events = std::mem::take(&mut self.events);
+        self.bytes = 0;
+        tokio::pin!(stop);
+
+        let mut dropped = Vec::new();
+        let mut requests = 0;
+        // retriable items are resent with the configured delays, up to `max_retries` times
+        let mut item_retry = Backoff::new(&RetryConfig {
+            max_retries: Some(self.config.max_retries as usize),
+            ..self.retry.clone()
+        });
+        let mut reconnect = Backoff::new(&self.retry);
+        while !items.is_empty() {
+            requests += 1;
+            let res = self.send(client, &items).await;
+            set_ready(!matches!(&res, Err(e) if is_connection_error(e)));
+            // the whole request failed, keep the items until the cluster is reachable again
+            let failures = match res {
+                Ok(failures) => failures,
+                Err(e) => {
+                    if is_transient(&e) {
+                        let reason = format!("bulk request failed, {e}");
+                        match reconnect.wait_unless(reason, &mut stop).await {
+                            Wait::Retry => continue,
+                            Wait::Exhausted => {}
+                            Wait::Stopped => {
+                                return Flushed {
+                                    dropped,
+                                    abandoned: events,
+                                }
+                            }
+                        }
+                    }
+                    let error = e.to_string();
+                    dropped.extend(events.into_iter().map(|event| DroppedEvent {
+                        event,
+                        error: error.clone(),
+                        attempts: requests,
+                    }));
+                    return Flushed {
+                        dropped,
+                        abandoned: Vec::new(),
+                    };
+                }
+            };
+            reconnect = Backoff::new(&self.retry);
+            let sent = items.len();
+            let mut retriable = Vec::new();
+            for failure in failures {
+                let (item, event) = (&items[failure.position], &events[failure.position]);
+                let error = format!("status {}, {}", failure.status, failure.reason);
+                if failure.is_retriable() {
+                    retriable.push((item.clone(), event.clone(), error));
+                } else {
+                    warn!(
+                        "bulk item failed, status: {}, reason: {}, action: {}",
+                        failure.status, failure.reason, item.action
+                    );
+                    dropped.push(DroppedEvent {
+                        event: event.clone(),
+                        error,
+                        attempts: requests,
+                    });
+                }
+            }
+            info!(
+                "bulk request of {sent} actions is dispatched to elasticsearch, {} to retry",
+                retriable.len()
+            );
+
+            if !retriable.is_empty() {
+                let reason = format!("{} bulk items failed", retriable.len());
+                match item_retry.wait_unless(reason, &mut stop).await {
+                    Wait::Retry => {}
+                    Wait::Exhausted => {
+                        warn!(
+                            "giving up {} bulk items after {requests} attempts",
+                            retriable.len()
+                        );
+                        dropped.extend(retriable.into_iter().map(|(_, event, error)| {
+                            DroppedEvent {
+                                event,
+                                error,
+                                attempts: requests,
+                            }
+                        }));
+                        break;
+                    }
+                    Wait::Stopped => {
+                        let abandoned = retriable.into_iter().map(|(_, event, _)| event);
+                        return Flushed {
+                            dropped,
+                            abandoned: abandoned.collect(),
+                        };
+                    }
+                }
+            }
+            (items, events) = retriable
+                .into_iter()
+                .map(|(item, event, _)| (item, event))
+                .unzip();
+        }
+
+        Flushed {
+            dropped,
+            abandoned: Vec::new(),
+        }
+    }
+
+    async fn send(
+        &self,
+        client: &Elasticsearch,
+        items: &[BulkItem],
+    ) -> Result<Vec<ItemFailure>, Error> {
+        let mut tries = 1;
+        loop {
+            match send(client, items).await {
+                Err(e) if is_connection_error(&e) && tries < self.nodes => {
+                    // the client moves on to the next node on every request
+                    warn!("bulk request failed, trying another node, {e}");
+                    tries += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// Connection errors, throttling and server errors are worth retrying, other statuses would
+/// be returned again. A request that timed out or whose response can't be read may have been
+/// applied, resending it would duplicate the documents without an id.
+fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::Elasticsearch(e) => match e.status_code() {
+            Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+            None => !e.is_timeout(),
+        },
+        _ => false,
+    }
+}
+
+/// The request never reached the cluster, as opposed to an error status or a timeout.
+fn is_connection_error(e: &Error) -> bool {
+    matches!(e, Error::Elasticsearch(e) if e.status_code().is_none() && !e.is_timeout())
+}
+
+async fn send(client: &Elasticsearch, items: &[BulkItem]) -> Result<Vec<ItemFailure>, Error> {
+    let mut body: Vec<JsonBody<Value>> = Vec::with_capacity(items.len() * 2);
+    for item in items {
+        body.push(item.action.clone().into());
+        if let Some(source) = &item.source {
+            body.push(source.clone().into());
+        }
+    }
+
+    let resp = client
+        .bulk(BulkParts::None)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status_code()?;
+    let resp: Value = resp.json().await.map_err(Error::Response)?;
+
+    Ok(parse_bulk_response(&resp))
+}
+
+/// A bulk item the cluster refused, `position` is its index in the request.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ItemFailure {
+    pub(crate) position: usize,
+    pub(crate) status: u16,
+    pub(crate) reason: String,
+}
+
+impl ItemFailure {
+    fn is_retriable(&self) -> bool {
+        self.status == 429 || self.status >= 500
+    }
+}
+
+/// Extracts the failed items from a bulk response.
+pub(crate) fn parse_bulk_response(resp: &Value) -> Vec<ItemFailure> {
+    if !matches!(resp.get("errors"), Some(Value::Bool(true))) {
+        return Vec::new();
+    }
+    let Some(items) = resp.get("items").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+
+    items
+        .iter()
+        .enumerate()
+        .filter_map(|(position, item)| {
+            // each item is an object with a single key, the action name
+            let result = item.as_object()?.values().next()?;
+            let error = result.get("error")?;
+            let status = result.get("status").and_then(Value::as_u64).unwrap_or(0) as u16;
+            let reason = error
+                .get("reason")
+                .and_then(Value::as_str)
+                .map_or_else(|| error.to_string(), ToString::to_string);
+            Some(ItemFailure {
+                position,
+                status,
+                reason,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_items() {
+        let doc = json!({ "id": "1" });
+
+        let item = BulkItem::new(Action::Index, "wlf", None, doc.clone()).unwrap();
+        assert_eq!(item.action, json!({ "index": { "_index": "wlf" } }));
+        assert_eq!(item.source, Some(doc.clone()));
+
+        let item =
+            BulkItem::new(Action::Upsert, "wlf", Some("1".to_string()), doc.clone()).unwrap();
+        assert_eq!(
+            item.action,
+            json!({ "update": { "_index": "wlf", "_id": "1" } })
+        );
+        assert_eq!(
+            item.source,
+            Some(json!({ "doc": doc, "doc_as_upsert": true }))
+        );
+
+        let item =
+            BulkItem::new(Action::Delete, "wlf", Some("1".to_string()), doc.clone()).unwrap();
+        assert_eq!(
+            item.action,
+            json!({ "delete": { "_index": "wlf", "_id": "1" } })
+        );
+        assert_eq!(item.source, None);
+
+        assert!(matches!(
+            BulkItem::new(Action::Delete, "wlf", None, doc),
+            Err(Error::MissingDocumentId(Action::Delete))
+        ));
+    }
+
+    #[test]
+    fn reject_zero_flush_interval() {
+        let config: BulkConfig = serde_json::from_value(json!({ "flush_interval_ms": 5 })).unwrap();
+        assert_eq!(config.flush_interval_ms, 5);
+        assert!(serde_json::from_value::<BulkConfig>(json!({ "flush_interval_ms": 0 })).is_err());
+    }
+
+    #[test]
+    fn parse_failed_items() {
+        let resp = json!({
+            "took": 30,
+            "errors": true,
+            "items": [
+                { "index": { "_index": "wlf", "status": 201 } },
+                { "index": { "_index": "wlf", "status": 429, "error": { "type": "es_rejected_execution_exception", "reason": "queue is full" } } },
+                { "index": { "_index": "wlf", "status": 400, "error": { "type": "mapper_parsing_exception", "reason": "failed to parse" } } }
+            ]
+        });
+
+        let failures = parse_bulk_response(&resp);
+        assert_eq!(
+            failures,
+            vec![
+                ItemFailure {
+                    position: 1,
+                    status: 429,
+                    reason: "queue is full".to_string()
+                },
+                ItemFailure {
+                    position: 2,
+                    status: 400,
+                    reason: "failed to parse".to_string()
+                }
+            ]
+        );
+        assert!(failures[0].is_retriable());
+        assert!(!failures[1].is_retriable());
+    }
+
+    #[test]
+    fn parse_successful_response() {
+        let resp = json!({
+            "took": 30,
+            "errors": false,
+            "items": [{ "index": { "_index": "wlf", "status": 201 } }]
+        });
+        assert!(parse_bulk_response(&resp).is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use wlf_core::{Event, Value};
+
+use crate::Error;
+
+/// Decides what part of an event is stored as the document.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DocumentConfig {
+    /// Index `event.value` instead of the whole event, dropping `meta`.
+    #[serde(default)]
+    pub value_only: bool,
+    /// Only index the part of `event.value` at this json pointer, e.g. `/data`. Implies `value_only`.
+    #[serde(default)]
+    pub pointer: Option<String>,
+    /// Copy the field of `event.value` at this json pointer into the `@timestamp` of the document.
+    #[serde(default)]
+    pub timestamp_field: Option<String>,
+}
+
+impl DocumentConfig {
+    pub(crate) fn shape(&self, event: Event) -> Result<Value, Error> {
+        let timestamp = match &self.timestamp_field {
+            Some(field) => Some(event.value.pointer(field).cloned().ok_or_else(|| {
+                Error::Document(format!("no timestamp field {field} in the event"))
+            })?),
+            None => None,
+        };
+
+        let mut doc = match &self.pointer {
+            Some(pointer) => {
+                let mut value = event.value;
+                value
+                    .pointer_mut(pointer)
+                    .map(Value::take)
+                    .ok_or_else(|| Error::Document(format!("no {pointer} in the event")))?
+            }
+            None if self.value_only => event.value,
+            None => serde_json::to_value(event)?,
+        };
+
+        if let Some(timestamp) = timestamp {
+            let Value::Object(doc) = &mut doc else {
+                return Err(Error::Document(
+                    "can't set @timestamp, the document is not an object".to_string(),
+                ));
+            };
+            doc.insert("@timestamp".to_string(), timestamp);
+        }
+
+        Ok(doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wlf_core::EventMeta;
+
+    use super::*;
+
+    fn event() -> Event {
+        Event {
+            value: json!({
+                "table": "t1",
+                "timestamp": "2023-08-01T00:00:00Z",
+                "data": { "id": "1" }
+            }),
+            meta: EventMeta::default(),
+        }
+    }
+
+    #[test]
+    fn whole_event() {
+        let doc = DocumentConfig::default().shape(event()).unwrap();
+        assert_eq!(doc["value"], event().value);
+        assert!(doc.get("meta").is_some());
+    }
+
+    #[test]
+    fn value_at_pointer_with_timestamp() {
+        let config = DocumentConfig {
+            pointer: Some("/data".to_string()),
+            timestamp_field: Some("/timestamp".to_string()),
+            ..Default::default()
+        };
+        let doc = config.shape(event()).unwrap();
+        assert_eq!(
+            doc,
+            json!({ "id": "1", "@timestamp": "2023-08-01T00:00:00Z" })
+        );
+
+        let config = DocumentConfig {
+            pointer: Some("/nothing".to_string()),
+            ..Default::default()
+        };
+        assert!(config.shape(event()).is_err());
+    }
+}
@@ -1,29 +1,109 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use async_trait::async_trait;
-use elasticsearch::{http::transport::Transport, Elasticsearch, IndexParts};
-use serde::Deserialize;
+use bulk::{BulkBuffer, BulkItem, DroppedEvent};
+use elasticsearch::Elasticsearch;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::{error, info};
-use utils::substitute_with_event;
+use tracing::{info, warn};
+use utils::{
+    dead_letter::{abandon, send_to_dead_letter},
+    retry::{Backoff, RetryConfig, Wait},
+    substitute_with_event,
+};
 use wlf_core::{
     event_router::{EventRouter, EventRouterApi},
-    ComponentApi, ComponentKind,
+    metrics::Metric,
+    ComponentApi, ComponentKind, Event, Value,
 };
 
+mod bulk;
+mod document;
+mod template;
+mod transport;
+
+pub use bulk::BulkConfig;
+pub use document::DocumentConfig;
+pub use template::TemplatesConfig;
+pub use transport::{Auth, TlsConfig};
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("serialize/deserialize error, {0}")]
     Serde(#[from] serde_json::Error),
+    #[error("elasticsearch client error, {0}")]
+    Elasticsearch(#[from] elasticsearch::Error),
+    /// The request was handled, but its response can't be read, so what was applied is unknown
+    #[error("failed to read the response, {0}")]
+    Response(elasticsearch::Error),
+    #[error("failed to render template, {0}")]
+    Template(String),
+    #[error("a document id is required by the {0:?} action")]
+    MissingDocumentId(Action),
+    #[error("bad configuration, {0}")]
+    Config(String),
+    #[error("failed to build the document, {0}")]
+    Document(String),
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ElasticsearchDispatcher {
     pub id: String,
     #[serde(default = "default_url")]
     pub url: String,
+    /// Urls of several nodes of a cluster, requests are distributed among them in turn.
+    /// Overrides `url` if not empty.
+    #[serde(default)]
+    pub nodes: Vec<String>,
+    /// Refresh the list of live nodes this often, only used with multiple `nodes`.
+    #[serde(default, deserialize_with = "utils::option_number")]
+    pub sniff_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub auth: Option<Auth>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default, deserialize_with = "utils::option_number")]
+    pub request_timeout_secs: Option<u64>,
+    /// Template of the target index, e.g. `wlf-%{/table}-%{+yyyy.MM.dd}`.
     #[serde(default = "default_index")]
     pub index: String,
+    /// The target is a data stream, every event is written with the `create` action.
+    #[serde(default)]
+    pub data_stream: bool,
+    /// Policies and templates to install at startup.
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+    /// Template of the document id, e.g. `%{/data/id}`. Documents get auto generated ids if unset.
+    #[serde(default)]
+    pub document_id: Option<String>,
+    #[serde(default)]
+    pub document: DocumentConfig,
+    /// Maps the `/type` of an event to the action it becomes, unmapped types are indexed. Without
+    /// a `document_id`, events mapped to `Upsert` are indexed too and events mapped to `Delete`
+    /// are dropped, as there is no document to delete.
+    #[serde(default = "default_actions")]
+    pub actions: HashMap<String, Action>,
+    /// Batch events into bulk requests instead of sending them one by one.
+    #[serde(default)]
+    pub bulk: Option<BulkConfig>,
+    /// Backoff of resending requests while the cluster is unreachable.
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Id of the component that receives the events failed to be dispatched.
+    #[serde(default)]
+    pub dead_letter: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Add or replace the document
+    Index,
+    /// Add the document, fails if it already exists
+    Create,
+    /// Update the document, or add it if it does not exist
+    Upsert,
+    /// Delete the document
+    Delete,
 }
 
 pub fn default_url() -> String {
@@ -34,6 +114,16 @@ pub fn default_index() -> String {
     "wlf".to_string()
 }
 
+/// Mirrors a MySQL table given a `document_id`: inserts and updates upsert the row, deletes
+/// remove it.
+pub fn default_actions() -> HashMap<String, Action> {
+    HashMap::from([
+        ("insert".to_string(), Action::Index),
+        ("update".to_string(), Action::Upsert),
+        ("delete".to_string(), Action::Delete),
+    ])
+}
+
 #[async_trait]
 impl ComponentApi for ElasticsearchDispatcher {
     fn id(&self) -> &str {
@@ -44,47 +134,217 @@ impl ComponentApi for ElasticsearchDispatcher {
         ComponentKind::Dispatcher
     }
 
+    fn reports_readiness(&self) -> bool {
+        true
+    }
+
     async fn run(&self, router: Arc<EventRouter>) -> Result<(), Box<dyn std::error::Error>> {
-        let transport = Transport::single_node(&self.url)?;
-        let client = Elasticsearch::new(transport);
+        let client = self.build_client()?;
+        let mut backoff = Backoff::new(&self.retry);
+        while let Err(e) = self.templates.install(&client).await {
+            let reason = format!("{} failed to install templates, {e}", self.id);
+            match backoff
+                .wait_unless(reason, router.shutdown_requested(&self.id))
+                .await
+            {
+                Wait::Retry => {}
+                Wait::Exhausted => return Err(e.into()),
+                Wait::Stopped => {
+                    abandon(&router, Vec::new(), &self.id).await;
+                    return Ok(());
+                }
+            }
+        }
+        // pinged up front to tell when it is ready, then every bulk request tells
+        let reachable = match ping(&client).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("{} failed to reach elasticsearch, {e}", self.id);
+                false
+            }
+        };
+        router.set_ready(&self.id, reachable);
 
-        while let Ok(event) = router.poll_event(self.id()).await {
-            info!("{} receives new event:\n\t{event:?}", self.id);
+        // without bulk settings, every event is sent as soon as it arrives
+        let config = self.bulk.clone().unwrap_or_else(BulkConfig::unbatched);
+        let mut buffer = BulkBuffer::new(config, self.retry.clone(), self.node_urls()?.len());
+        let mut ticker = tokio::time::interval(buffer.flush_interval());
 
-            let Ok(index) = substitute_with_event(&self.index, &event) else {
-                continue;
-            };
+        loop {
+            tokio::select! {
+                event = router.poll_event(self.id()) => {
+                    let Ok(event) = event else {
+                        break;
+                    };
+                    info!("{} receives new event:\n\t{event:?}", self.id);
 
-            let resp = client
-                .index(IndexParts::Index(&index))
-                .body(event)
-                .send()
-                .await?;
+                    match self.bulk_item(&event) {
+                        Ok(Some(item)) => buffer.push(item, event),
+                        Ok(None) => {
+                            info!("{} drops a delete event without document id", self.id);
+                            router.metrics().increment(Metric::EventsDropped, &self.id, 1);
+                            continue;
+                        }
+                        Err(e) => {
+                            self.dead_letter(&router, event, e, 0).await;
+                            continue;
+                        }
+                    }
+
+                    if buffer.is_full() {
+                        self.flush(&router, &client, &mut buffer).await;
+                    }
+                }
+                _ = ticker.tick() => {
+                    if !buffer.is_empty() {
+                        self.flush(&router, &client, &mut buffer).await;
+                    }
+                }
+            }
+        }
 
-            info!("event is dispatched to elasticsearch, resp: {resp:?}");
+        if !buffer.is_empty() {
+            self.flush(&router, &client, &mut buffer).await;
         }
 
         Ok(())
     }
 }
 
+impl ElasticsearchDispatcher {
+    async fn flush(&self, router: &EventRouter, client: &Elasticsearch, buffer: &mut BulkBuffer) {
+        let len = buffer.len();
+        let start = Instant::now();
+        let flushed = buffer
+            .flush(
+                client,
+                |ready| router.set_ready(&self.id, ready),
+                router.shutdown_requested(&self.id),
+            )
+            .await;
+        let metrics = router.metrics();
+        metrics.observe(Metric::BatchSize, &self.id, len as f64);
+        let latency = start.elapsed().as_secs_f64();
+        metrics.observe(Metric::DispatchLatency, &self.id, latency);
+
+        for dropped in flushed.dropped {
+            let DroppedEvent {
+                event,
+                error,
+                attempts,
+            } = dropped;
+            self.dead_letter(router, event, error, attempts).await;
+        }
+        // the queue is left empty, so the dispatcher stops
+        if !flushed.abandoned.is_empty() {
+            abandon(router, flushed.abandoned, &self.id).await;
+        }
+    }
+
+    async fn dead_letter(
+        &self,
+        router: &EventRouter,
+        event: Event,
+        error: impl std::fmt::Display,
+        attempts: usize,
+    ) {
+        let dead_letter = self.dead_letter.as_deref();
+        send_to_dead_letter(router, dead_letter, event, &self.id, error, attempts).await;
+    }
+
+    /// The bulk item of the event, none if there is nothing to write.
+    fn bulk_item(&self, event: &Event) -> Result<Option<BulkItem>, Error> {
+        let index = substitute_with_event(&self.index, event).map_err(Error::Template)?;
+        let id = self
+            .document_id
+            .as_ref()
+            .map(|template| substitute_with_event(template, event))
+            .transpose()
+            .map_err(Error::Template)?;
+        let action = match event.value.pointer("/type") {
+            // data streams are append only
+            _ if self.data_stream => Action::Create,
+            Some(Value::String(t)) => match self.actions.get(t) {
+                // without an id there is no document to update or delete
+                Some(Action::Delete) if id.is_none() => return Ok(None),
+                Some(Action::Upsert) if id.is_none() => Action::Index,
+                Some(action) => *action,
+                None => Action::Index,
+            },
+            _ => Action::Index,
+        };
+
+        BulkItem::new(action, &index, id, self.document.shape(event.clone())?).map(Some)
+    }
+}
+
+async fn ping(client: &Elasticsearch) -> Result<(), Error> {
+    client.ping().send().await?.error_for_status_code()?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use utils::test_utils::DummyComponent;
 
     use super::*;
 
+    #[test]
+    fn actions_without_document_id() {
+        let event = |kind| Event {
+            value: wlf_core::value!({ "type": kind, "data": { "id": 1 } }),
+            meta: Default::default(),
+        };
+
+        let mut dispatcher: ElasticsearchDispatcher =
+            serde_json::from_value(serde_json::json!({ "id": "es" })).unwrap();
+        let item = dispatcher.bulk_item(&event("update")).unwrap().unwrap();
+        assert_eq!(
+            item.action,
+            serde_json::json!({ "index": { "_index": "wlf" } })
+        );
+        assert!(dispatcher.bulk_item(&event("delete")).unwrap().is_none());
+
+        dispatcher.document_id = Some("%{/data/id}".to_string());
+        let item = dispatcher.bulk_item(&event("delete")).unwrap().unwrap();
+        assert_eq!(
+            item.action,
+            serde_json::json!({ "delete": { "_index": "wlf", "_id": "1" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn keep_undelivered_events() {
+        let dispatcher: ElasticsearchDispatcher =
+            serde_json::from_value(serde_json::json!({ "id": "es", "url": "http://127.0.0.1:1" }))
+                .unwrap();
+        utils::test_utils::assert_keeps_undelivered_events(dispatcher).await;
+    }
+
     #[tokio::test]
     async fn collect() {
         let collector = ElasticsearchDispatcher {
             id: "es".to_string(),
             url: default_url(),
+            nodes: vec![],
+            sniff_interval_secs: None,
+            auth: None,
+            tls: None,
+            request_timeout_secs: None,
             index: default_index(),
+            data_stream: false,
+            templates: Default::default(),
+            document_id: None,
+            document: Default::default(),
+            actions: default_actions(),
+            bulk: None,
+            retry: Default::default(),
+            dead_letter: None,
         };
 
         let dummy_dispatcher = DummyComponent::new("dispatcher", ComponentKind::Dispatcher);
 
-        let mut router = EventRouter::new();
+        let router = EventRouter::new();
         router.register_component(&collector);
         router.register_component(&dummy_dispatcher);
         let router = Arc::new(router);
@@ -0,0 +1,256 @@
+use std::{collections::BTreeMap, fmt};
+
+use elasticsearch::{
+    cluster::{ClusterExistsComponentTemplateParts, ClusterPutComponentTemplateParts},
+    http::{response::Response, StatusCode},
+    ilm::{IlmGetLifecycleParts, IlmPutLifecycleParts},
+    indices::{IndicesExistsIndexTemplateParts, IndicesPutIndexTemplateParts},
+    Elasticsearch,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::Error;
+
+/// Resources installed (created or replaced) when the dispatcher starts, keyed by their names.
+/// The values are the request bodies of the corresponding elasticsearch APIs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TemplatesConfig {
+    #[serde(default)]
+    pub ilm_policies: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub component_templates: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub index_templates: BTreeMap<String, Value>,
+    /// Replace the resources that already exist, otherwise they are kept as they are.
+    #[serde(default = "default_overwrite")]
+    pub overwrite: bool,
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        Self {
+            ilm_policies: BTreeMap::new(),
+            component_templates: BTreeMap::new(),
+            index_templates: BTreeMap::new(),
+            overwrite: default_overwrite(),
+        }
+    }
+}
+
+pub fn default_overwrite() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resource {
+    IlmPolicy,
+    ComponentTemplate,
+    IndexTemplate,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Resource::IlmPolicy => "ilm policy",
+            Resource::ComponentTemplate => "component template",
+            Resource::IndexTemplate => "index template",
+        })
+    }
+}
+
+/// A resource to install and the body of its request.
+struct Install<'a> {
+    resource: Resource,
+    name: &'a str,
+    body: &'a Value,
+}
+
+impl TemplatesConfig {
+    /// Installs everything in dependency order: policies, component templates, index templates.
+    pub(crate) async fn install(&self, client: &Elasticsearch) -> Result<(), Error> {
+        for install in self.installs() {
+            if !self.overwrite && install.exists(client).await? {
+                info!(
+                    "{} {} already exists, keeping it",
+                    install.resource, install.name
+                );
+                continue;
+            }
+            install.put(client).await?;
+            info!("{} {} is installed", install.resource, install.name);
+        }
+        Ok(())
+    }
+
+    /// The resources in the order they have to be installed.
+    fn installs(&self) -> impl Iterator<Item = Install<'_>> {
+        fn installs(
+            resource: Resource,
+            resources: &BTreeMap<String, Value>,
+        ) -> impl Iterator<Item = Install<'_>> {
+            resources.iter().map(move |(name, body)| Install {
+                resource,
+                name,
+                body,
+            })
+        }
+        installs(Resource::IlmPolicy, &self.ilm_policies)
+            .chain(installs(
+                Resource::ComponentTemplate,
+                &self.component_templates,
+            ))
+            .chain(installs(Resource::IndexTemplate, &self.index_templates))
+    }
+}
+
+impl Install<'_> {
+    async fn exists(&self, client: &Elasticsearch) -> Result<bool, Error> {
+        let resp = match self.resource {
+            Resource::IlmPolicy => {
+                client
+                    .ilm()
+                    .get_lifecycle(IlmGetLifecycleParts::Policy(self.name))
+                    .send()
+                    .await?
+            }
+            Resource::ComponentTemplate => {
+                client
+                    .cluster()
+                    .exists_component_template(ClusterExistsComponentTemplateParts::Name(self.name))
+                    .send()
+                    .await?
+            }
+            Resource::IndexTemplate => {
+                client
+                    .indices()
+                    .exists_index_template(IndicesExistsIndexTemplateParts::Name(self.name))
+                    .send()
+                    .await?
+            }
+        };
+        exists(resp)
+    }
+
+    async fn put(&self, client: &Elasticsearch) -> Result<(), Error> {
+        let resp = match self.resource {
+            Resource::IlmPolicy => {
+                client
+                    .ilm()
+                    .put_lifecycle(IlmPutLifecycleParts::Policy(self.name))
+                    .body(self.body)
+                    .send()
+                    .await?
+            }
+            Resource::ComponentTemplate => {
+                client
+                    .cluster()
+                    .put_component_template(ClusterPutComponentTemplateParts::Name(self.name))
+                    .body(self.body)
+                    .send()
+                    .await?
+            }
+            Resource::IndexTemplate => {
+                client
+                    .indices()
+                    .put_index_template(IndicesPutIndexTemplateParts::Name(self.name))
+                    .body(self.body)
+                    .send()
+                    .await?
+            }
+        };
+        resp.error_for_status_code()?;
+        Ok(())
+    }
+}
+
+/// Whether the response of an existence check found the resource.
+fn exists(resp: Response) -> Result<bool, Error> {
+    match found(resp.status_code()) {
+        Some(found) => Ok(found),
+        None => resp
+            .error_for_status_code()
+            .map(|_| true)
+            .map_err(Into::into),
+    }
+}
+
+/// `None` if the status is an error other than not found.
+fn found(status: StatusCode) -> Option<bool> {
+    if status == StatusCode::NOT_FOUND {
+        Some(false)
+    } else if status.is_client_error() || status.is_server_error() {
+        None
+    } else {
+        Some(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn install_in_dependency_order() {
+        let config: TemplatesConfig = serde_json::from_value(json!({
+            "index_templates": {
+                "wlf": {
+                    "index_patterns": ["wlf-*"],
+                    "data_stream": {},
+                    "composed_of": ["wlf-mappings"]
+                }
+            },
+            "component_templates": {
+                "wlf-mappings": {
+                    "template": { "mappings": { "properties": { "table": { "type": "keyword" } } } }
+                }
+            },
+            "ilm_policies": {
+                "wlf-retention": {
+                    "policy": { "phases": { "delete": { "min_age": "7d", "actions": { "delete": {} } } } }
+                }
+            }
+        }))
+        .unwrap();
+        assert!(config.overwrite);
+
+        let installs: Vec<_> = config
+            .installs()
+            .map(|install| (install.resource, install.name, install.body.clone()))
+            .collect();
+        assert_eq!(
+            installs,
+            vec![
+                (
+                    Resource::IlmPolicy,
+                    "wlf-retention",
+                    json!({ "policy": { "phases": { "delete": { "min_age": "7d", "actions": { "delete": {} } } } } })
+                ),
+                (
+                    Resource::ComponentTemplate,
+                    "wlf-mappings",
+                    json!({ "template": { "mappings": { "properties": { "table": { "type": "keyword" } } } } })
+                ),
+                (
+                    Resource::IndexTemplate,
+                    "wlf",
+                    json!({ "index_patterns": ["wlf-*"], "data_stream": {}, "composed_of": ["wlf-mappings"] })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_existing_resources() {
+        let config: TemplatesConfig =
+            serde_json::from_value(json!({ "overwrite": false })).unwrap();
+        assert!(!config.overwrite);
+
+        assert_eq!(found(StatusCode::OK), Some(true));
+        assert_eq!(found(StatusCode::NOT_FOUND), Some(false));
+        assert_eq!(found(StatusCode::INTERNAL_SERVER_ERROR), None);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{path::PathBuf, time::Duration};
+
+use elasticsearch::{
+    auth::Credentials,
+    http::{
+        transport::{MultiNodeConnectionPool, SingleNodeConnectionPool, TransportBuilder},
+        Url,
+    },
+    Elasticsearch,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{ElasticsearchDispatcher, Error};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: String,
+    },
+    ApiKey {
+        id: String,
+        api_key: String,
+    },
+    /// The base64 encoded `id:api_key`, as returned by the create API key API
+    EncodedApiKey {
+        key: String,
+    },
+    Bearer {
+        token: String,
+    },
+}
+
+impl From<Auth> for Credentials {
+    fn from(auth: Auth) -> Self {
+        match auth {
+            Auth::Basic { username, password } => Credentials::Basic(username, password),
+            Auth::ApiKey { id, api_key } => Credentials::ApiKey(id, api_key),
+            Auth::EncodedApiKey { key } => Credentials::EncodedApiKey(key),
+            Auth::Bearer { token } => Credentials::Bearer(token),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM encoded CA certificate used to verify the nodes, in addition to the system roots
+    pub ca_cert: Option<PathBuf>,
+    /// Skip the verification of node certificates
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+impl ElasticsearchDispatcher {
+    /// Urls of all the configured nodes, `nodes` takes precedence over `url`.
+    pub(crate) fn node_urls(&self) -> Result<Vec<Url>, Error> {
+        let urls = if self.nodes.is_empty() {
+            std::slice::from_ref(&self.url)
+        } else {
+            self.nodes.as_slice()
+        };
+        urls.iter()
+            .map(|url| Url::parse(url).map_err(|e| Error::Config(format!("bad url {url}, {e}"))))
+            .collect()
+    }
+
+    pub(crate) fn build_client(&self) -> Result<Elasticsearch, Error> {
+        let mut urls = self.node_urls()?;
+        let mut builder = if urls.len() == 1 {
+            TransportBuilder::new(SingleNodeConnectionPool::new(urls.remove(0)))
+        } else {
+            // the live nodes are rediscovered periodically, so that dead nodes are dropped from
+            // the rotation and new ones are picked up
+            let sniff_interval = self.sniff_interval_secs.map(Duration::from_secs);
+            TransportBuilder::new(MultiNodeConnectionPool::round_robin(urls, sniff_interval))
+        };
+
+        if let Some(auth) = &self.auth {
+            builder = builder.auth(auth.clone().into());
+        }
+        if let Some(tls) = &self.tls {
+            builder = with_tls(builder, tls)?;
+        }
+        if let Some(timeout) = self.request_timeout_secs {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+
+        Ok(Elasticsearch::new(builder.build()?))
+    }
+}
+
+#[cfg(feature = "tls")]
+fn with_tls(builder: TransportBuilder, tls: &TlsConfig) -> Result<TransportBuilder, Error> {
+    use elasticsearch::cert::{Certificate, CertificateValidation};
+
+    let validation = if tls.insecure {
+        CertificateValidation::None
+    } else if let Some(path) = &tls.ca_cert {
+        let pem = std::fs::read(path)
+            .map_err(|e| Error::Config(format!("can't read {}, {e}", path.display())))?;
+        CertificateValidation::Full(Certificate::from_pem(&pem)?)
+    } else {
+        CertificateValidation::Default
+    };
+
+    Ok(builder.cert_validation(validation))
+}
+
+#[cfg(not(feature = "tls"))]
+fn with_tls(_builder: TransportBuilder, _tls: &TlsConfig) -> Result<TransportBuilder, Error> {
+    Err(Error::Config(
+        "tls options require the `tls` feature of wlf-elasticsearch-dispatcher".to_string(),
+    ))
+}
@@ -0,0 +1,116 @@
+use std::{io, path::PathBuf, pin::Pin, sync::Arc};
+
+use async_trait::async_trait;
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+};
+use wlf_core::{
+    event_router::{EventRouter, EventRouterApi},
+    ComponentApi, ComponentKind, Event,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("event router error, {0}")]
+    EventRouter(#[from] wlf_core::event_router::Error),
+    #[error("io error, {0}")]
+    Io(#[from] io::Error),
+    #[error("serialize/deserialize error, {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// Writes the value of every event as a line of JSON.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileDispatcher {
+    pub id: String,
+    /// The file the events are appended to, the standard output if not set.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[async_trait]
+impl ComponentApi for FileDispatcher {
+    fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    fn kind(&self) -> ComponentKind {
+        ComponentKind::Dispatcher
+    }
+
+    async fn run(&self, router: Arc<EventRouter>) -> Result<(), Box<dyn std::error::Error>> {
+        let out: Pin<Box<dyn AsyncWrite + Send>> = match &self.path {
+            Some(path) => Box::pin(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            ),
+            None => Box::pin(tokio::io::stdout()),
+        };
+        let mut out = BufWriter::new(out);
+        // the events written but not flushed yet, they are only delivered once flushed
+        let mut unflushed: Vec<Event> = Vec::new();
+
+        loop {
+            // the lines are flushed whenever there are no more events waiting
+            let event = match router.poll_event(self.id()).now_or_never() {
+                Some(event) => event,
+                None => {
+                    out.flush().await.map_err(Error::from)?;
+                    unflushed.clear();
+                    router.poll_event(self.id()).await
+                }
+            };
+            let Ok(event) = event else {
+                break;
+            };
+
+            let mut line = serde_json::to_vec(&event.value).map_err(Error::from)?;
+            line.push(b'\n');
+            out.write_all(&line).await.map_err(Error::from)?;
+            unflushed.push(event);
+        }
+
+        out.flush().await.map_err(Error::from)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use wlf_core::{value, Event, EventMeta};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn write_lines() {
+        let path = std::env::temp_dir().join(format!("wlf-file-{}.jsonl", std::process::id()));
+        let dispatcher = FileDispatcher {
+            id: "file".to_string(),
+            path: Some(path.clone()),
+        };
+
+        let router = Arc::new(EventRouter::new());
+        router.register_component(&dispatcher);
+        for n in 0..2 {
+            let event = Event {
+                value: value!({ "n": n }),
+                meta: EventMeta::default(),
+            };
+            router.send_event(event, "collector", "file").await.unwrap();
+        }
+        router.request_shutdown("file");
+        dispatcher.run(router).await.unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":0}\n{\"n\":1}\n");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,21 +1,33 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 use chrono::Utc;
 use rskafka::{
     client::{
+        controller::ControllerClient,
+        error::{Error as ClientError, ProtocolError},
         partition::{Compression, UnknownTopicHandling},
-        ClientBuilder,
+        Client, ClientBuilder,
     },
     record::Record,
+    topic::Topic,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::{error, info};
-use utils::substitute_with_event;
+use tracing::{info, warn};
+use utils::{
+    dead_letter::{abandon, send_to_dead_letter},
+    retry::{Backoff, RetryConfig, Wait},
+    substitute_with_event,
+};
 use wlf_core::{
     event_router::{EventRouter, EventRouterApi},
-    ComponentApi, ComponentKind,
+    metrics::Metric,
+    ComponentApi, ComponentKind, Event,
 };
 
 #[derive(Error, Debug)]
@@ -26,19 +38,76 @@ pub enum Error {
     KafkaClient(#[from] rskafka::client::error::Error),
     #[error("serialize/deserialize error, {0}")]
     Serde(#[from] serde_json::Error),
+    #[error("the record is not acknowledged in time")]
+    AckTimeout,
+}
+
+impl Error {
+    /// Whether the connection to the brokers is broken and has to be reopened.
+    fn is_connection_error(&self) -> bool {
+        matches!(
+            self,
+            Error::KafkaClient(
+                ClientError::Connection(_)
+                    | ClientError::Request(_)
+                    | ClientError::RetryFailed(_)
+                    | ClientError::Timeout
+            ) | Error::AckTimeout
+        )
+    }
+
+    /// Whether the brokers reject the record itself, so that producing it again is pointless.
+    fn is_permanent(&self) -> bool {
+        match self {
+            Error::KafkaClient(ClientError::ServerError(e, _)) => matches!(
+                e,
+                ProtocolError::MessageTooLarge
+                    | ProtocolError::RecordListTooLarge
+                    | ProtocolError::CorruptMessage
+                    | ProtocolError::InvalidRecord
+                    | ProtocolError::InvalidTimestamp
+                    | ProtocolError::InvalidTopicException
+                    | ProtocolError::TopicAuthorizationFailed
+                    | ProtocolError::ClusterAuthorizationFailed
+                    | ProtocolError::PolicyViolation
+                    | ProtocolError::UnsupportedForMessageFormat
+            ),
+            Error::EventRouter(_) | Error::Serde(_) => true,
+            _ => false,
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KafkaDispatcher {
     pub id: String,
     #[serde(default = "default_topic")]
     pub topic: String,
     pub bootstrap_brokers: Vec<String>,
+    /// Template of the record key, records with the same key go to the same partition, the one
+    /// the Java client would choose. Records without key are spread over the partitions.
+    #[serde(default)]
+    pub key: Option<String>,
     #[serde(default)]
     pub compression_type: CompressionType,
+    /// Client id sent to the brokers, e.g. to tell the producers apart in their logs and quotas.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// Give up waiting for the brokers to acknowledge a record after this many milliseconds, it
+    /// is then resent as configured by `retry`. Records are acknowledged by all the in-sync
+    /// replicas.
+    #[serde(default, deserialize_with = "utils::option_number")]
+    pub ack_timeout_ms: Option<u64>,
+    /// Backoff of reconnecting to the brokers and resending an event. Events the brokers reject,
+    /// e.g. too large ones, are dead-lettered without retrying.
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Id of the component that receives the events failed to be dispatched.
+    #[serde(default)]
+    pub dead_letter: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum CompressionType {
     #[default]
     NoCompression,
@@ -56,54 +125,286 @@ impl ComponentApi for KafkaDispatcher {
         ComponentKind::Dispatcher
     }
 
+    fn reports_readiness(&self) -> bool {
+        true
+    }
+
     async fn run(&self, router: Arc<EventRouter>) -> Result<(), Box<dyn std::error::Error>> {
-        let client = ClientBuilder::new(self.bootstrap_brokers.clone())
-            .build()
-            .await?;
-        let controller_client = client.controller_client()?;
-        let mut topics_cache = client.list_topics().await?;
         let compression = match self.compression_type {
             CompressionType::NoCompression => Compression::default(),
             CompressionType::Snappy => Compression::Snappy,
             CompressionType::Gzip => Compression::Gzip,
         };
+        // connected up front to tell when it is ready, and dropped to reconnect after an error
+        let mut producer = match self.connect().await {
+            Ok(producer) => Some(producer),
+            Err(e) => {
+                warn!("{} failed to connect to kafka, {e}", self.id);
+                None
+            }
+        };
+        router.set_ready(&self.id, producer.is_some());
         while let Ok(event) = router.poll_event(self.id()).await {
             info!("{} receives new event:\n\t{event:?}", self.id);
 
             // get the topic
-            let Ok(topic_name) = substitute_with_event(&self.topic, &event) else {
-                continue;
+            let topic_name = match substitute_with_event(&self.topic, &event) {
+                Ok(topic_name) => topic_name,
+                Err(e) => {
+                    self.dead_letter(&router, event, e, 0).await;
+                    continue;
+                }
             };
 
-            // create the topic in kafka if topic does not exist
-            if !topics_cache.iter().any(|topic| topic.name == topic_name) {
-                controller_client
-                    .create_topic(topic_name.clone(), 1, 1, 5_000)
-                    .await?;
-                topics_cache = client.list_topics().await?;
-            }
-
-            // get the partition client
-            let partition_client = client
-                .partition_client(topic_name.clone(), 0, UnknownTopicHandling::Retry)
-                .await?;
-
             // create record
+            let value = match serde_json::to_vec(&event) {
+                Ok(value) => value,
+                Err(e) => {
+                    self.dead_letter(&router, event, e, 0).await;
+                    continue;
+                }
+            };
+            let key = self.key.as_ref().and_then(|key| {
+                substitute_with_event(key, &event)
+                    .map_err(|e| warn!("{} sends a record without key, {e}", self.id))
+                    .ok()
+            });
             let record = Record {
-                key: None,
-                value: Some(serde_json::to_vec(&event)?),
+                key: key.map(String::into_bytes),
+                value: Some(value),
                 headers: BTreeMap::new(),
                 timestamp: Utc::now(),
             };
-            partition_client.produce(vec![record], compression).await?;
 
-            // dispatch event to corresponding kafka table
-            info!("event is dispatched to topic {}", topic_name);
+            // dispatch event to corresponding kafka topic, keep it until it is produced
+            let mut backoff = Backoff::new(&self.retry);
+            let start = Instant::now();
+            loop {
+                let res = match &mut producer {
+                    Some(producer) => {
+                        self.produce(producer, &topic_name, record.clone(), compression)
+                            .await
+                    }
+                    None => match self.connect().await {
+                        Ok(p) => {
+                            producer = Some(p);
+                            router.set_ready(&self.id, true);
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    },
+                };
+                match res {
+                    Ok(()) => {
+                        info!("event is dispatched to topic {}", topic_name);
+                        let latency = start.elapsed().as_secs_f64();
+                        router
+                            .metrics()
+                            .observe(Metric::DispatchLatency, &self.id, latency);
+                        break;
+                    }
+                    Err(e) if e.is_permanent() => {
+                        self.dead_letter(&router, event, e, backoff.attempts() + 1)
+                            .await;
+                        break;
+                    }
+                    Err(e) => {
+                        // other errors, like a partition without leader, may go away by themselves
+                        if e.is_connection_error() {
+                            producer = None;
+                            router.set_ready(&self.id, false);
+                        }
+                        let reason = format!("{} failed to produce, {e}", self.id);
+                        let stop = router.shutdown_requested(&self.id);
+                        match backoff.wait_unless(reason, stop).await {
+                            Wait::Retry => {}
+                            Wait::Exhausted => {
+                                let attempts = backoff.attempts() + 1;
+                                self.dead_letter(&router, event, e, attempts).await;
+                                break;
+                            }
+                            Wait::Stopped => {
+                                abandon(&router, vec![event], &self.id).await;
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+            }
         }
         Ok(())
     }
 }
 
+impl KafkaDispatcher {
+    async fn dead_letter(
+        &self,
+        router: &EventRouter,
+        event: Event,
+        error: impl std::fmt::Display,
+        attempts: usize,
+    ) {
+        let dead_letter = self.dead_letter.as_deref();
+        send_to_dead_letter(router, dead_letter, event, &self.id, error, attempts).await;
+    }
+
+    async fn connect(&self) -> Result<Producer, Error> {
+        let mut builder = ClientBuilder::new(self.bootstrap_brokers.clone());
+        if let Some(client_id) = &self.client_id {
+            builder = builder.client_id(client_id.as_str());
+        }
+        Producer::new(builder.build().await?).await
+    }
+
+    async fn produce(
+        &self,
+        producer: &mut Producer,
+        topic_name: &str,
+        record: Record,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        // create the topic in kafka if topic does not exist
+        if !producer
+            .topics_cache
+            .iter()
+            .any(|topic| topic.name == topic_name)
+        {
+            // the topic may have been created since the cache was filled
+            match producer
+                .controller_client
+                .create_topic(topic_name, 1, 1, 5_000)
+                .await
+            {
+                Ok(()) | Err(ClientError::ServerError(ProtocolError::TopicAlreadyExists, _)) => {}
+                Err(e) => return Err(e.into()),
+            }
+            producer.topics_cache = producer.client.list_topics().await?;
+        }
+
+        // records are spread over the partitions by key, or in turn without key
+        let partitions = producer
+            .topics_cache
+            .iter()
+            .find(|topic| topic.name == topic_name)
+            .map(|topic| topic.partitions.iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let partition = match (&record.key, partitions.len()) {
+            (_, 0) => 0,
+            (Some(key), n) => partitions[(murmur2(key) & 0x7fffffff) as usize % n],
+            (None, n) => {
+                producer.next_partition = producer.next_partition.wrapping_add(1);
+                partitions[producer.next_partition % n]
+            }
+        };
+
+        // get the partition client
+        let partition_client = producer
+            .client
+            .partition_client(topic_name, partition, UnknownTopicHandling::Retry)
+            .await?;
+
+        let produce = partition_client.produce(vec![record], compression);
+        match self.ack_timeout_ms {
+            Some(ms) => tokio::time::timeout(Duration::from_millis(ms), produce)
+                .await
+                .map_err(|_| Error::AckTimeout)??,
+            None => produce.await?,
+        };
+        Ok(())
+    }
+}
+
+/// A connection to the kafka cluster.
+struct Producer {
+    client: Client,
+    controller_client: ControllerClient,
+    topics_cache: Vec<Topic>,
+    /// Counter of the records without key, to pick their partition in turn
+    next_partition: usize,
+}
+
+impl Producer {
+    async fn new(client: Client) -> Result<Self, Error> {
+        let controller_client = client.controller_client()?;
+        let topics_cache = client.list_topics().await?;
+        Ok(Self {
+            client,
+            controller_client,
+            topics_cache,
+            next_partition: 0,
+        })
+    }
+}
+
+/// The murmur2 hash of the Java client's default partitioner.
+fn murmur2(data: &[u8]) -> u32 {
+    const SEED: u32 = 0x9747b28c;
+    const M: u32 = 0x5bd1e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, b) in rest.iter().enumerate() {
+            h ^= (*b as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h
+}
+
 pub fn default_topic() -> String {
     "wasm-log-flex".to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keep_undelivered_events() {
+        let dispatcher = KafkaDispatcher {
+            id: "kafka".to_string(),
+            topic: default_topic(),
+            bootstrap_brokers: vec!["127.0.0.1:1".to_string()],
+            key: None,
+            compression_type: CompressionType::NoCompression,
+            client_id: None,
+            ack_timeout_ms: None,
+            retry: Default::default(),
+            dead_letter: None,
+        };
+        utils::test_utils::assert_keeps_undelivered_events(dispatcher).await;
+    }
+
+    #[test]
+    fn murmur2_like_the_java_client() {
+        // the values of the Java client's tests
+        let cases: [(&[u8], i32); 6] = [
+            (b"21", -973932308),
+            (b"foobar", -790332482),
+            (b"a-little-bit-long-string", -985981536),
+            (b"a-little-bit-longer-string", -1486304829),
+            (
+                b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8",
+                -58897971,
+            ),
+            (b"abc", 479470107),
+        ];
+        for (data, hash) in cases {
+            assert_eq!(murmur2(data) as i32, hash);
+        }
+    }
+}
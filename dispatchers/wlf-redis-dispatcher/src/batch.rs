@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use redis::Cmd;
+use serde::{Deserialize, Serialize};
+use wlf_core::Event;
+
+use crate::{
+    connection::{Connection, Replies},
+    Config, Error,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchConfig {
+    /// Flush once this many commands are queued.
+    #[serde(default = "default_max_commands", deserialize_with = "utils::number")]
+    pub max_commands: usize,
+    /// Flush queued commands at least this often, in milliseconds.
+    #[serde(
+        default = "default_flush_interval_ms",
+        deserialize_with = "utils::non_zero"
+    )]
+    pub flush_interval_ms: u64,
+    /// Wrap every flush in MULTI/EXEC, so that a batch is applied all at once. If a command
+    /// fails while the transaction runs, the others are still applied, as redis does not roll
+    /// back, and only its event is dead-lettered. Not supported with the cluster topology,
+    /// where a batch spans several nodes, nor with TLS.
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+impl BatchConfig {
+    /// Settings that send every command as soon as it is queued.
+    pub fn unbatched() -> Self {
+        Self {
+            max_commands: 1,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_commands: default_max_commands(),
+            flush_interval_ms: default_flush_interval_ms(),
+            atomic: false,
+        }
+    }
+}
+
+pub fn default_max_commands() -> usize {
+    100
+}
+
+pub fn default_flush_interval_ms() -> u64 {
+    100
+}
+
+/// Queues commands and sends them in a single pipeline.
+pub(crate) struct Batch {
+    config: BatchConfig,
+    cmds: Vec<Cmd>,
+    /// The events the commands are built from, kept for dead-lettering
+    events: Vec<Event>,
+    /// The events of the commands redis rejected, which are not retried
+    rejected: Vec<(Event, Error)>,
+}
+
+impl Batch {
+    pub(crate) fn new(config: BatchConfig) -> Self {
+        Self {
+            config,
+            cmds: Vec::new(),
+            events: Vec::new(),
+            rejected: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, cmd: Cmd, event: Event) {
+        self.cmds.push(cmd);
+        self.events.push(event);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.cmds.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.cmds.is_empty()
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.cmds.len() >= self.config.max_commands
+    }
+
+    pub(crate) fn flush_interval(&self) -> Duration {
+        Duration::from_millis(self.config.flush_interval_ms)
+    }
+
+    /// Whether the batch is sent as a transaction.
+    pub(crate) fn atomic(&self) -> bool {
+        self.config.atomic
+    }
+
+    /// Sends the queued commands. The ones that failed for a reason that may go away, like a
+    /// connection error, are kept for a retry and the first such error is returned. The events
+    /// of the ones redis rejected are set aside, see [`Batch::take_rejected`].
+    pub(crate) async fn flush(
+        &mut self,
+        con: &mut Connection,
+        config: &Config,
+    ) -> Result<(), Error> {
+        let replies = con.send(config, &self.cmds).await?;
+        match self.settle(replies) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the commands that succeeded or were rejected, returning the first error of the
+    /// others.
+    fn settle(&mut self, replies: Replies) -> Option<Error> {
+        let mut first_err = None;
+        let cmds = std::mem::take(&mut self.cmds);
+        let events = std::mem::take(&mut self.events);
+        for ((cmd, event), reply) in cmds.into_iter().zip(events).zip(replies) {
+            let Err(e) = reply else {
+                continue;
+            };
+            // the other commands of a transaction aborted by a rejected one are fine
+            let retry = e.code() == Some("EXECABORT");
+            let e = Error::Redis(e);
+            if retry || e.is_connection_error() {
+                first_err.get_or_insert(e);
+                self.cmds.push(cmd);
+                self.events.push(event);
+            } else {
+                self.rejected.push((event, e));
+            }
+        }
+        first_err
+    }
+
+    /// Returns the events of the commands rejected since the last call, with their error.
+    pub(crate) fn take_rejected(&mut self) -> Vec<(Event, Error)> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    /// Empties the batch, returning the events of the queued commands.
+    pub(crate) fn take(&mut self) -> Vec<Event> {
+        self.cmds.clear();
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wlf_core::{value, EventMeta};
+
+    use super::*;
+
+    fn event(id: i64) -> Event {
+        Event {
+            value: value!({ "id": id }),
+            meta: EventMeta::default(),
+        }
+    }
+
+    #[test]
+    fn flush_when_full() {
+        let config: BatchConfig =
+            serde_json::from_value(json!({ "max_commands": 2, "flush_interval_ms": 50 })).unwrap();
+        let mut batch = Batch::new(config);
+        assert_eq!(batch.flush_interval(), Duration::from_millis(50));
+
+        batch.push(redis::cmd("RPUSH").arg("k").arg(1).to_owned(), event(1));
+        assert!(!batch.is_full());
+        batch.push(redis::cmd("RPUSH").arg("k").arg(2).to_owned(), event(2));
+        assert!(batch.is_full());
+
+        let events = batch.take();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].value, value!({ "id": 2 }));
+        assert!(batch.is_empty());
+
+        assert!(serde_json::from_value::<BatchConfig>(json!({ "flush_interval_ms": 0 })).is_err());
+    }
+
+    #[test]
+    fn keep_failed_commands() {
+        let mut batch = Batch::new(BatchConfig::default());
+        for id in 1..=4 {
+            batch.push(redis::cmd("RPUSH").arg("k").arg(id).to_owned(), event(id));
+        }
+
+        let error = batch.settle(vec![
+            Ok(()),
+            Err((redis::ErrorKind::ReadOnly, "replica").into()),
+            Err((redis::ErrorKind::TypeError, "wrong type").into()),
+            Ok(()),
+        ]);
+        let Some(Error::Redis(error)) = error else {
+            panic!("the read-only error is not returned");
+        };
+        assert_eq!(error.code(), Some("READONLY"));
+        let rejected = batch.take_rejected();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].0.value, value!({ "id": 3 }));
+        assert!(batch.take_rejected().is_empty());
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch.take()[0].value, value!({ "id": 2 }));
+    }
+}
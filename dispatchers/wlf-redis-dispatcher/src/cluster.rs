@@ -0,0 +1,412 @@
+use std::{collections::HashMap, io};
+
+use redis::{aio, Arg, Cmd, ErrorKind, RedisError, Value};
+use tracing::{info, warn};
+
+use crate::{
+    connection::{connect, parse_addr, send_pipelined, Addr, Replies},
+    Config, Error,
+};
+
+const SLOTS: u16 = 16384;
+
+/// A connection to every master of a Redis Cluster, commands are routed by the slot of their key.
+pub(crate) struct ClusterConnection {
+    config: Config,
+    seeds: Vec<Addr>,
+    /// Slot ranges sorted by their first slot
+    slots: Vec<SlotRange>,
+    connections: HashMap<Addr, aio::MultiplexedConnection>,
+}
+
+#[derive(Debug, PartialEq)]
+struct SlotRange {
+    start: u16,
+    end: u16,
+    node: Addr,
+}
+
+impl ClusterConnection {
+    pub(crate) async fn open(config: &Config, nodes: &[String]) -> Result<Self, Error> {
+        let seeds = nodes
+            .iter()
+            .map(|node| parse_addr(node))
+            .collect::<Result<Vec<_>, _>>()?;
+        if seeds.is_empty() {
+            return Err(Error::Config("no cluster nodes are given".to_string()));
+        }
+
+        let mut cluster = Self {
+            config: config.clone(),
+            seeds,
+            slots: Vec::new(),
+            connections: HashMap::new(),
+        };
+        cluster.refresh_slots().await?;
+        Ok(cluster)
+    }
+
+    /// Sends the commands in one pipeline per node and returns the reply of each of them. The
+    /// commands redirected with ASK are sent again to the node importing their slot. The ones
+    /// moved or left unanswered by a failed node are sent again once the slots are refreshed.
+    /// The others, like TRYAGAIN, keep their errors to be retried later.
+    pub(crate) async fn send(&mut self, cmds: &[Cmd]) -> Result<Replies, Error> {
+        let all: Vec<usize> = (0..cmds.len()).collect();
+        let mut replies: Replies = cmds.iter().map(|_| Ok(())).collect();
+        self.send_to_nodes(cmds, &all, &mut replies).await?;
+
+        let mut asked: Vec<(Addr, Vec<usize>)> = Vec::new();
+        let mut moved = Vec::new();
+        for i in all {
+            match replies[i].as_ref().err().and_then(redirect) {
+                Some(Redirect::Ask(node)) => match asked.iter_mut().find(|(n, _)| *n == node) {
+                    Some((_, group)) => group.push(i),
+                    None => asked.push((node, vec![i])),
+                },
+                Some(Redirect::Moved) => moved.push(i),
+                None => {}
+            }
+        }
+
+        for (node, indices) in asked {
+            self.send_asking(&node, cmds, &indices, &mut replies).await;
+        }
+
+        if moved.is_empty() {
+            return Ok(replies);
+        }
+        // the slots were migrated or a master failed over
+        warn!(
+            "{} commands were moved or not answered, refreshing slots",
+            moved.len()
+        );
+        let resent = match self.refresh_slots().await {
+            Ok(()) => self.send_to_nodes(cmds, &moved, &mut replies).await,
+            Err(e) => Err(e),
+        };
+        // the commands keep their first errors, to be retried later
+        if let Err(e) = resent {
+            warn!("failed to resend the moved commands, {e}");
+        }
+        Ok(replies)
+    }
+
+    /// Sends the commands at `indices` to the node their slot is being migrated to, each one
+    /// preceded by ASKING so that the node accepts it.
+    async fn send_asking(
+        &mut self,
+        node: &Addr,
+        cmds: &[Cmd],
+        indices: &[usize],
+        replies: &mut Replies,
+    ) {
+        let node_cmds = asking(indices.iter().map(|&i| &cmds[i]));
+        let node_replies = match self.connection(node).await {
+            Ok(con) => send_pipelined(con, &node_cmds).await,
+            Err(e) => {
+                // the commands keep their ASK errors, to be retried later
+                warn!("failed to connect to redis node {node:?}, {e}");
+                return;
+            }
+        };
+        if node_replies
+            .iter()
+            .any(|reply| matches!(reply, Err(e) if e.is_io_error()))
+        {
+            self.connections.remove(node);
+        }
+        // the replies to the commands, not to ASKING
+        for (&i, reply) in indices
+            .iter()
+            .zip(node_replies.into_iter().skip(1).step_by(2))
+        {
+            replies[i] = reply;
+        }
+    }
+
+    /// Sends the commands at `indices`, setting their replies.
+    async fn send_to_nodes(
+        &mut self,
+        cmds: &[Cmd],
+        indices: &[usize],
+        replies: &mut Replies,
+    ) -> Result<(), Error> {
+        for (node, indices) in self.group_by_node(cmds, indices)? {
+            let node_cmds: Vec<Cmd> = indices.iter().map(|&i| cmds[i].clone()).collect();
+            let node_replies = match self.connection(&node).await {
+                Ok(con) => send_pipelined(con, &node_cmds).await,
+                Err(e) => node_cmds
+                    .iter()
+                    .map(|_| {
+                        let e = format!("failed to connect to redis node {node:?}, {e}");
+                        Err(io::Error::other(e).into())
+                    })
+                    .collect(),
+            };
+            if node_replies
+                .iter()
+                .any(|reply| matches!(reply, Err(e) if e.is_io_error()))
+            {
+                self.connections.remove(&node);
+            }
+            for (i, reply) in indices.into_iter().zip(node_replies) {
+                replies[i] = reply;
+            }
+        }
+        Ok(())
+    }
+
+    /// The indices of the commands grouped by the node serving them.
+    fn group_by_node(
+        &self,
+        cmds: &[Cmd],
+        indices: &[usize],
+    ) -> Result<Vec<(Addr, Vec<usize>)>, Error> {
+        let mut groups: Vec<(Addr, Vec<usize>)> = Vec::new();
+        for &i in indices {
+            let node = self.node_of(&cmds[i])?;
+            match groups.iter_mut().find(|(n, _)| *n == node) {
+                Some((_, group)) => group.push(i),
+                None => groups.push((node, vec![i])),
+            }
+        }
+        Ok(groups)
+    }
+
+    /// The node serving the key of the command, all our commands take the key as first argument.
+    fn node_of(&self, cmd: &Cmd) -> Result<Addr, Error> {
+        let slot = match cmd.args_iter().nth(1) {
+            Some(Arg::Simple(key)) => slot(key),
+            _ => 0,
+        };
+        let i = self.slots.partition_point(|range| range.end < slot);
+        match self.slots.get(i) {
+            Some(range) if range.start <= slot => Ok(range.node.clone()),
+            _ => Err(Error::Cluster(format!(
+                "slot {slot} is not served by any node"
+            ))),
+        }
+    }
+
+    async fn connection(&mut self, node: &Addr) -> Result<&mut aio::MultiplexedConnection, Error> {
+        if !self.connections.contains_key(node) {
+            let mut info = self.config.connection_info(node.0.clone(), node.1);
+            // cluster nodes only have database 0
+            info.redis.db = 0;
+            let con = connect(info).await?;
+            self.connections.insert(node.clone(), con);
+        }
+        Ok(self
+            .connections
+            .get_mut(node)
+            .expect("connection was just inserted"))
+    }
+
+    async fn refresh_slots(&mut self) -> Result<(), Error> {
+        // ask the masters we already know first, then fall back to the configured seeds
+        let mut candidates: Vec<Addr> = Vec::new();
+        for node in self.slots.iter().map(|r| &r.node).chain(&self.seeds) {
+            if !candidates.contains(node) {
+                candidates.push(node.clone());
+            }
+        }
+
+        for node in candidates {
+            let res = match self.connection(&node).await {
+                Ok(con) => redis::cmd("CLUSTER")
+                    .arg("SLOTS")
+                    .query_async::<_, Value>(con)
+                    .await
+                    .map_err(Error::from),
+                Err(e) => Err(e),
+            };
+            match res.and_then(|slots| parse_slots(&slots, &node)) {
+                Ok(mut slots) => {
+                    slots.sort_by_key(|r| r.start);
+                    self.connections
+                        .retain(|addr, _| slots.iter().any(|r| r.node == *addr));
+                    self.slots = slots;
+                    info!("redis cluster slots are refreshed from {node:?}");
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("failed to get cluster slots from {node:?}, {e}");
+                    self.connections.remove(&node);
+                }
+            }
+        }
+
+        Err(Error::Cluster(
+            "none of the cluster nodes returned the slots".to_string(),
+        ))
+    }
+}
+
+/// Where a command has to be sent instead of the node it was sent to.
+#[derive(Debug, PartialEq)]
+enum Redirect {
+    /// The slot is served by another node now, or the node failed
+    Moved,
+    /// The slot is being migrated to the node, which accepts the command after ASKING
+    Ask(Addr),
+}
+
+fn redirect(e: &RedisError) -> Option<Redirect> {
+    if e.kind() == ErrorKind::Ask {
+        let (node, _slot) = e.redirect_node()?;
+        return parse_addr(node).ok().map(Redirect::Ask);
+    }
+    let moved = e.is_io_error() || e.is_connection_dropped() || e.kind() == ErrorKind::Moved;
+    moved.then_some(Redirect::Moved)
+}
+
+/// The commands each preceded by ASKING.
+fn asking<'a>(cmds: impl Iterator<Item = &'a Cmd>) -> Vec<Cmd> {
+    cmds.flat_map(|cmd| [redis::cmd("ASKING"), cmd.clone()])
+        .collect()
+}
+
+/// Parses the reply of `CLUSTER SLOTS`, only the masters are kept.
+fn parse_slots(value: &Value, queried: &Addr) -> Result<Vec<SlotRange>, Error> {
+    let Value::Bulk(ranges) = value else {
+        return Err(Error::Cluster(format!(
+            "unexpected CLUSTER SLOTS reply {value:?}"
+        )));
+    };
+
+    let mut slots = Vec::new();
+    for range in ranges {
+        let Value::Bulk(items) = range else {
+            continue;
+        };
+        let (Some(Value::Int(start)), Some(Value::Int(end)), Some(Value::Bulk(master))) =
+            (items.first(), items.get(1), items.get(2))
+        else {
+            continue;
+        };
+        let (Some(Value::Data(host)), Some(Value::Int(port))) = (master.first(), master.get(1))
+        else {
+            continue;
+        };
+        // an empty host means the node we asked
+        let host = match String::from_utf8_lossy(host).to_string() {
+            host if host.is_empty() => queried.0.clone(),
+            host => host,
+        };
+        slots.push(SlotRange {
+            start: *start as u16,
+            end: *end as u16,
+            node: (host, *port as u16),
+        });
+    }
+    Ok(slots)
+}
+
+/// The hash slot of a key, honoring `{hash tags}`.
+pub(crate) fn slot(key: &[u8]) -> u16 {
+    let key = match key.iter().position(|&b| b == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &key[open + 1..open + 1 + len],
+            _ => key,
+        },
+        None => key,
+    };
+    crc16(key) % SLOTS
+}
+
+/// CRC16-CCITT (XMODEM), as used by Redis Cluster.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_slots() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(slot(b"foo"), 12182);
+        assert_eq!(slot(b"{user1000}.following"), slot(b"{user1000}.followers"));
+        assert_eq!(slot(b"foo{}bar"), crc16(b"foo{}bar") % SLOTS);
+    }
+
+    #[test]
+    fn follow_redirects() {
+        let error = |reply: &[u8]| redis::parse_redis_value(reply).unwrap_err();
+
+        let ask = error(b"-ASK 3999 10.0.0.3:6381\r\n");
+        assert_eq!(
+            redirect(&ask),
+            Some(Redirect::Ask(("10.0.0.3".to_string(), 6381)))
+        );
+        let moved = error(b"-MOVED 3999 10.0.0.3:6381\r\n");
+        assert_eq!(redirect(&moved), Some(Redirect::Moved));
+        let dropped = RedisError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert_eq!(redirect(&dropped), Some(Redirect::Moved));
+        assert_eq!(redirect(&error(b"-TRYAGAIN\r\n")), None);
+        assert_eq!(
+            redirect(&error(b"-ERR wrong number of arguments\r\n")),
+            None
+        );
+
+        let cmds = [
+            redis::cmd("RPUSH").arg("a").arg(1).to_owned(),
+            redis::cmd("RPUSH").arg("b").arg(2).to_owned(),
+        ];
+        let packed: Vec<u8> = asking(cmds.iter())
+            .iter()
+            .flat_map(|cmd| cmd.get_packed_command())
+            .collect();
+        assert_eq!(
+            String::from_utf8(packed).unwrap(),
+            "*1\r\n$6\r\nASKING\r\n*3\r\n$5\r\nRPUSH\r\n$1\r\na\r\n$1\r\n1\r\n\
+             *1\r\n$6\r\nASKING\r\n*3\r\n$5\r\nRPUSH\r\n$1\r\nb\r\n$1\r\n2\r\n"
+        );
+    }
+
+    #[test]
+    fn parse_cluster_slots() {
+        let reply = Value::Bulk(vec![
+            Value::Bulk(vec![
+                Value::Int(0),
+                Value::Int(5460),
+                Value::Bulk(vec![Value::Data(b"10.0.0.1".to_vec()), Value::Int(6379)]),
+                Value::Bulk(vec![Value::Data(b"10.0.0.4".to_vec()), Value::Int(6379)]),
+            ]),
+            Value::Bulk(vec![
+                Value::Int(5461),
+                Value::Int(16383),
+                Value::Bulk(vec![Value::Data(b"".to_vec()), Value::Int(6380)]),
+            ]),
+        ]);
+        let queried = ("10.0.0.2".to_string(), 6380);
+
+        assert_eq!(
+            parse_slots(&reply, &queried).unwrap(),
+            vec![
+                SlotRange {
+                    start: 0,
+                    end: 5460,
+                    node: ("10.0.0.1".to_string(), 6379)
+                },
+                SlotRange {
+                    start: 5461,
+                    end: 16383,
+                    node: queried
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    future::{poll_fn, Future},
+    task::Poll,
+};
+
+use redis::{aio, Cmd, IntoConnectionInfo, RedisError, RedisResult, Value};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{cluster::ClusterConnection, transaction::TransactionConnection, Config, Error};
+
+/// Host and port of a redis server.
+pub(crate) type Addr = (String, u16);
+
+/// The outcome of every command sent, in the order of the commands.
+pub(crate) type Replies = Vec<Result<(), RedisError>>;
+
+/// How the redis server(s) are found.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "type")]
+pub enum Topology {
+    /// A single server at `host` and `port`
+    #[default]
+    Standalone,
+    /// The master is looked up from the sentinels, and looked up again when it becomes
+    /// unreachable or read-only.
+    Sentinel {
+        /// `host:port` of the sentinels
+        sentinels: Vec<String>,
+        master_name: String,
+        /// Password of the sentinels, if different from the one of the master
+        #[serde(default)]
+        sentinel_auth: Option<String>,
+    },
+    /// A Redis Cluster, commands are sent to the master serving the slot of their key. Use
+    /// `{hash tags}` in key templates to keep related keys on the same node. Atomic batches
+    /// are not supported, as they would span several nodes.
+    Cluster {
+        /// `host:port` of some of the cluster nodes, the rest are discovered
+        nodes: Vec<String>,
+    },
+}
+
+pub(crate) enum Connection {
+    Single(aio::MultiplexedConnection),
+    /// Sends every batch as a transaction
+    Atomic(TransactionConnection),
+    Cluster(Box<ClusterConnection>),
+}
+
+impl Connection {
+    /// Opens a connection that sends the batches as transactions if `atomic`.
+    pub(crate) async fn open(config: &Config, atomic: bool) -> Result<Self, Error> {
+        let info = match &config.topology {
+            Topology::Standalone => config.clone().into_connection_info()?,
+            Topology::Sentinel { .. } => {
+                let (host, port) = discover_master(config).await?;
+                config.connection_info(host, port)
+            }
+            Topology::Cluster { nodes } => {
+                let cluster = ClusterConnection::open(config, nodes).await?;
+                return Ok(Self::Cluster(Box::new(cluster)));
+            }
+        };
+        let con = if atomic {
+            Self::Atomic(TransactionConnection::open(info).await?)
+        } else {
+            Self::Single(connect(info).await?)
+        };
+        Ok(con)
+    }
+
+    /// Sends the commands in a pipeline, or in a transaction on an atomic connection, and
+    /// returns the reply of each of them. In a cluster, each node receives its own pipeline.
+    pub(crate) async fn send(&mut self, config: &Config, cmds: &[Cmd]) -> Result<Replies, Error> {
+        let mut replies = match self {
+            Self::Single(con) => send_pipelined(con, cmds).await,
+            Self::Atomic(con) => con.send(cmds).await,
+            Self::Cluster(cluster) => return cluster.send(cmds).await,
+        };
+
+        let failed: Vec<usize> = (0..cmds.len())
+            .filter(|&i| matches!(&replies[i], Err(e) if is_failover(e)))
+            .collect();
+        if failed.is_empty() || !matches!(config.topology, Topology::Sentinel { .. }) {
+            return Ok(replies);
+        }
+        warn!("redis master is unavailable, asking the sentinels again");
+        let atomic = matches!(self, Self::Atomic(_));
+        *self = Self::open(config, atomic).await?;
+        // only the commands the old master did not take are sent to the new one, all of them
+        // when they make a transaction
+        let failed = if atomic {
+            (0..cmds.len()).collect()
+        } else {
+            failed
+        };
+        let cmds: Vec<Cmd> = failed.iter().map(|&i| cmds[i].clone()).collect();
+        let new_replies = match self {
+            Self::Single(con) => send_pipelined(con, &cmds).await,
+            Self::Atomic(con) => con.send(&cmds).await,
+            Self::Cluster(_) => unreachable!("sentinel masters are not clusters"),
+        };
+        for (i, reply) in failed.into_iter().zip(new_replies) {
+            replies[i] = reply;
+        }
+        Ok(replies)
+    }
+}
+
+pub(crate) async fn connect(
+    info: impl IntoConnectionInfo,
+) -> Result<aio::MultiplexedConnection, Error> {
+    Ok(redis::Client::open(info)?
+        .get_multiplexed_tokio_connection()
+        .await?)
+}
+
+/// Sends the commands without waiting for each reply.
+pub(crate) async fn send_pipelined(con: &aio::MultiplexedConnection, cmds: &[Cmd]) -> Replies {
+    let replies = query_in_order(con, cmds.to_vec()).await;
+    replies.into_iter().map(|reply| reply.map(drop)).collect()
+}
+
+/// Sends the commands on the multiplexed connection and waits for all of their replies.
+async fn query_in_order(
+    con: &aio::MultiplexedConnection,
+    cmds: Vec<Cmd>,
+) -> Vec<RedisResult<Value>> {
+    let mut queries: Vec<_> = cmds
+        .into_iter()
+        .map(|cmd| {
+            let mut con = con.clone();
+            Box::pin(async move { cmd.query_async::<_, Value>(&mut con).await })
+        })
+        .collect();
+    let mut replies: Vec<Option<RedisResult<Value>>> = queries.iter().map(|_| None).collect();
+    poll_fn(|cx| {
+        // polled in order, so that the commands are queued on the connection in order
+        let mut done = true;
+        for (query, reply) in queries.iter_mut().zip(&mut replies) {
+            if reply.is_none() {
+                match query.as_mut().poll(cx) {
+                    Poll::Ready(res) => *reply = Some(res),
+                    Poll::Pending => done = false,
+                }
+            }
+        }
+        if done {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+    replies
+        .into_iter()
+        .map(|reply| reply.expect("every query is done"))
+        .collect()
+}
+
+/// Parses `host:port`, IPv6 hosts may be enclosed in brackets, e.g. `[::1]:6379`.
+pub(crate) fn parse_addr(addr: &str) -> Result<Addr, Error> {
+    addr.rsplit_once(':')
+        .and_then(|(host, port)| {
+            let host = host
+                .strip_prefix('[')
+                .and_then(|host| host.strip_suffix(']'))
+                .unwrap_or(host);
+            Some((host.to_string(), port.parse().ok()?))
+        })
+        .filter(|(host, _)| !host.is_empty())
+        .ok_or_else(|| Error::Config(format!("bad address {addr}, expect host:port")))
+}
+
+/// Errors after which the master should be looked up again.
+fn is_failover(e: &RedisError) -> bool {
+    e.is_io_error()
+        || e.is_connection_dropped()
+        || e.is_connection_refusal()
+        || e.code() == Some("READONLY")
+}
+
+async fn discover_master(config: &Config) -> Result<Addr, Error> {
+    let Topology::Sentinel {
+        sentinels,
+        master_name,
+        sentinel_auth,
+    } = &config.topology
+    else {
+        unreachable!("only called for the sentinel topology");
+    };
+
+    for sentinel in sentinels {
+        let (host, port) = parse_addr(sentinel)?;
+        let mut info = config.connection_info(host, port);
+        info.redis.db = 0;
+        info.redis.username = None;
+        info.redis.password = sentinel_auth.clone();
+
+        let res = async {
+            let mut con = connect(info).await?;
+            let master: Option<Addr> = redis::cmd("SENTINEL")
+                .arg("get-master-addr-by-name")
+                .arg(master_name)
+                .query_async(&mut con)
+                .await?;
+            Ok::<_, Error>(master)
+        }
+        .await;
+        match res {
+            Ok(Some(master)) => {
+                info!("sentinel {sentinel} reports {master:?} as master {master_name}");
+                return Ok(master);
+            }
+            Ok(None) => warn!("sentinel {sentinel} does not know master {master_name}"),
+            Err(e) => warn!("failed to ask sentinel {sentinel} for the master, {e}"),
+        }
+    }
+
+    Err(Error::Sentinel(format!(
+        "no sentinel knows the address of master {master_name}"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_addrs() {
+        assert_eq!(
+            parse_addr("10.0.0.1:6379").unwrap(),
+            ("10.0.0.1".to_string(), 6379)
+        );
+        assert_eq!(parse_addr("[::1]:6380").unwrap(), ("::1".to_string(), 6380));
+        assert_eq!(
+            parse_addr("fe80::1:7000").unwrap(),
+            ("fe80::1".to_string(), 7000)
+        );
+        assert!(parse_addr("localhost").is_err());
+        assert!(parse_addr(":6379").is_err());
+    }
+}
@@ -1,58 +1,146 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use async_trait::async_trait;
+use batch::Batch;
+use connection::Connection;
 use redis::{
-    AsyncCommands, ConnectionAddr, ConnectionInfo, IntoConnectionInfo, RedisConnectionInfo,
-    RedisError,
+    Cmd, ConnectionAddr, ConnectionInfo, IntoConnectionInfo, RedisConnectionInfo, RedisError,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::info;
-use utils::substitute_with_event;
+use tracing::{info, warn};
+use utils::{
+    dead_letter::{abandon, send_to_dead_letter},
+    retry::{Backoff, RetryConfig, Wait},
+    substitute_with_event,
+};
 use wlf_core::{
     event_router::{EventRouter, EventRouterApi},
-    ComponentApi, ComponentKind,
+    metrics::Metric,
+    ComponentApi, ComponentKind, Event, Value,
 };
 
+mod batch;
+mod cluster;
+mod connection;
+mod transaction;
+
+pub use batch::BatchConfig;
+pub use connection::Topology;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("redis error, {0}")]
     Redis(#[from] RedisError),
     #[error("serialize/deserialize error, {0}")]
     Serde(#[from] serde_json::Error),
+    #[error("failed to render template, {0}")]
+    Template(String),
+    #[error("bad configuration, {0}")]
+    Config(String),
+    #[error("redis cluster error, {0}")]
+    Cluster(String),
+    #[error("redis sentinel error, {0}")]
+    Sentinel(String),
+}
+
+impl Error {
+    /// Whether the error may go away after reconnecting.
+    fn is_connection_error(&self) -> bool {
+        match self {
+            Error::Redis(e) => {
+                e.is_io_error()
+                    || e.is_connection_dropped()
+                    || e.is_connection_refusal()
+                    || e.is_timeout()
+                    || matches!(
+                        e.code(),
+                        Some(
+                            "READONLY"
+                                | "MOVED"
+                                | "ASK"
+                                | "TRYAGAIN"
+                                | "CLUSTERDOWN"
+                                | "LOADING"
+                                | "MASTERDOWN"
+                        )
+                    )
+            }
+            Error::Cluster(_) | Error::Sentinel(_) => true,
+            Error::Serde(_) | Error::Template(_) | Error::Config(_) => false,
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RedisDispatcher {
     pub id: String,
     #[serde(default)]
     pub mode: Mode,
+    /// Pipeline events instead of waiting for a round-trip per event.
+    #[serde(default)]
+    pub batch: Option<BatchConfig>,
+    /// Backoff of reconnecting and resending after a connection error.
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Id of the component that receives the events failed to be dispatched.
+    #[serde(default)]
+    pub dead_letter: Option<String>,
     // TODO: use default here after https://github.com/serde-rs/serde/issues/1626 is fixed
     #[serde(flatten)]
     pub config: Config,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     #[serde(default = "default_host")]
     pub host: String,
-    #[serde(default = "default_port")]
+    #[serde(default = "default_port", deserialize_with = "utils::number")]
     pub port: u16,
+    /// ACL user, the default user is used if not set
+    pub username: Option<String>,
     pub auth: Option<String>,
-    #[serde(default = "default_database_number")]
+    #[serde(
+        default = "default_database_number",
+        deserialize_with = "utils::number"
+    )]
     pub database_number: u8,
+    /// Connect with TLS, requires the `tls` feature
+    #[serde(default)]
+    pub tls: bool,
+    /// Skip the verification of the server certificate
+    #[serde(default)]
+    pub tls_insecure: bool,
+    #[serde(default)]
+    pub topology: Topology,
 }
 
-impl IntoConnectionInfo for Config {
-    fn into_connection_info(self) -> redis::RedisResult<ConnectionInfo> {
-        Ok(ConnectionInfo {
-            addr: ConnectionAddr::Tcp(self.host, self.port),
+impl Config {
+    /// Connection info of the server at `host` and `port`, with the configured credentials.
+    pub(crate) fn connection_info(&self, host: String, port: u16) -> ConnectionInfo {
+        let addr = if self.tls {
+            ConnectionAddr::TcpTls {
+                host,
+                port,
+                insecure: self.tls_insecure,
+            }
+        } else {
+            ConnectionAddr::Tcp(host, port)
+        };
+        ConnectionInfo {
+            addr,
             redis: RedisConnectionInfo {
                 db: self.database_number as i64,
-                username: None,
-                password: self.auth,
+                username: self.username.clone(),
+                password: self.auth.clone(),
             },
-        })
+        }
+    }
+}
+
+impl IntoConnectionInfo for Config {
+    fn into_connection_info(self) -> redis::RedisResult<ConnectionInfo> {
+        Ok(self.connection_info(self.host.clone(), self.port))
     }
 }
 
@@ -61,8 +149,12 @@ impl Default for Config {
         Self {
             host: default_host(),
             port: default_port(),
+            username: None,
             auth: None,
             database_number: default_database_number(),
+            tls: false,
+            tls_insecure: false,
+            topology: Topology::default(),
         }
     }
 }
@@ -79,13 +171,123 @@ pub fn default_database_number() -> u8 {
     0
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum Mode {
-    LPush { key: String },
-    RPush { key: String },
-    Pub { channel: String },
-    XADD { key: String },
+    LPush {
+        key: String,
+    },
+    RPush {
+        key: String,
+    },
+    Pub {
+        channel: String,
+    },
+    XADD {
+        key: String,
+        /// Name of the stream field holding the serialized event.
+        #[serde(default = "default_stream_field")]
+        field: String,
+        /// Write every top-level field of the event value as its own stream field instead.
+        /// Values that are not objects, or empty ones, are still written to `field`.
+        #[serde(default)]
+        flatten: bool,
+        #[serde(default)]
+        trim: Option<StreamTrim>,
+    },
+    /// `SET key value [EX ttl_secs]`, the key is deleted on delete events.
+    Set {
+        key: String,
+        /// Expire the key this long after it is written, 0 or unset to keep it
+        #[serde(default, deserialize_with = "utils::option_number")]
+        ttl_secs: Option<u64>,
+        #[serde(flatten)]
+        value: ValueConfig,
+    },
+    /// `HSET key field value`, the field is deleted on delete events.
+    HSet {
+        key: String,
+        /// Template of the hash field, e.g. `%{/data/id}`
+        field: String,
+        #[serde(flatten)]
+        value: ValueConfig,
+    },
+    /// `ZADD key score member`, the member is removed on delete events.
+    ZAdd {
+        key: String,
+        /// Json pointer to the score, a number or a numeric string
+        score: String,
+        /// Template of the member, e.g. `%{/data/id}`. Required unless `keep_deleted` is set,
+        /// the serialized value is the member then.
+        #[serde(default)]
+        member: Option<String>,
+        #[serde(flatten)]
+        value: ValueConfig,
+    },
+}
+
+/// Decides what part of an event is written by the key/value modes.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ValueConfig {
+    /// Only write the part of `event.value` at this json pointer, e.g. `/data`.
+    /// Strings are written as is, anything else as json.
+    #[serde(default)]
+    pub pointer: Option<String>,
+    /// Don't delete on events whose `/type` is `delete`, write them like any other event.
+    #[serde(default)]
+    pub keep_deleted: bool,
+}
+
+impl ValueConfig {
+    fn serialize(&self, event: &Event) -> Result<String, Error> {
+        match &self.pointer {
+            Some(pointer) => match event.value.pointer(pointer) {
+                Some(Value::String(s)) => Ok(s.clone()),
+                Some(value) => Ok(value.to_string()),
+                None => Err(Error::Template(format!("no {pointer} in the event"))),
+            },
+            None => Ok(serde_json::to_string(&event.value)?),
+        }
+    }
+
+    /// Whether the event removes the row it describes, as maxwell-style `delete` events do.
+    fn deletes(&self, event: &Event) -> bool {
+        !self.keep_deleted
+            && matches!(event.value.pointer("/type"), Some(Value::String(t)) if t == "delete")
+    }
+}
+
+/// Trimming applied by XADD, e.g. `MAXLEN ~ 1000`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum StreamTrim {
+    /// Keep at most `threshold` entries
+    MaxLen {
+        #[serde(deserialize_with = "utils::number")]
+        threshold: usize,
+        #[serde(default = "default_approximate")]
+        approximate: bool,
+    },
+    /// Evict the entries with ids lower than `threshold`
+    MinId {
+        threshold: String,
+        #[serde(default = "default_approximate")]
+        approximate: bool,
+    },
+}
+
+impl StreamTrim {
+    fn write_args(&self, cmd: &mut Cmd) {
+        let (strategy, approximate) = match self {
+            StreamTrim::MaxLen { approximate, .. } => ("MAXLEN", approximate),
+            StreamTrim::MinId { approximate, .. } => ("MINID", approximate),
+        };
+        cmd.arg(strategy).arg(if *approximate { "~" } else { "=" });
+        match self {
+            StreamTrim::MaxLen { threshold, .. } => cmd.arg(threshold),
+            StreamTrim::MinId { threshold, .. } => cmd.arg(threshold),
+        };
+    }
 }
 
 impl Default for Mode {
@@ -100,16 +302,28 @@ pub fn default_key() -> String {
     "wlf".to_string()
 }
 
+pub fn default_stream_field() -> String {
+    "event".to_string()
+}
+
+/// The stream field of maxwell, used for converted maxwell configs.
 pub fn default_redis_stream_json_key() -> String {
     "message".to_string()
 }
 
+fn default_approximate() -> bool {
+    true
+}
+
 impl RedisDispatcher {
     pub fn new(id: impl Into<String>) -> Self {
         Self {
             id: id.into(),
             config: Default::default(),
             mode: Default::default(),
+            batch: None,
+            retry: Default::default(),
+            dead_letter: None,
         }
     }
 
@@ -122,6 +336,21 @@ impl RedisDispatcher {
         self.config.auth = Some(password.into());
         self
     }
+
+    pub fn set_batch(&mut self, batch: BatchConfig) -> &mut Self {
+        self.batch = Some(batch);
+        self
+    }
+
+    pub fn set_retry(&mut self, retry: RetryConfig) -> &mut Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn set_dead_letter(&mut self, dead_letter: impl Into<String>) -> &mut Self {
+        self.dead_letter = Some(dead_letter.into());
+        self
+    }
 }
 
 #[async_trait]
@@ -134,53 +363,293 @@ impl ComponentApi for RedisDispatcher {
         ComponentKind::Dispatcher
     }
 
+    fn reports_readiness(&self) -> bool {
+        true
+    }
+
     async fn run(&self, router: Arc<EventRouter>) -> Result<(), Box<dyn std::error::Error>> {
-        let mut redis_client = redis::Client::open(self.config.clone())?
-            .get_async_connection()
-            .await?;
+        self.check()?;
+        // without batch settings, every event is sent as soon as it arrives
+        let batch = self.batch.clone().unwrap_or_else(BatchConfig::unbatched);
+
+        // connected up front to tell when it is ready, and dropped to reconnect after a
+        // connection error
+        let mut con = match Connection::open(&self.config, batch.atomic).await {
+            Ok(con) => Some(con),
+            Err(e @ Error::Config(_)) => return Err(e.into()),
+            Err(e) => {
+                warn!("{} failed to connect to redis, {e}", self.id);
+                None
+            }
+        };
+        router.set_ready(&self.id, con.is_some());
 
-        while let Ok(event) = router.poll_event(&self.id).await {
-            info!("{} receives new event:\n\t{event:?}", self.id);
+        let mut batch = Batch::new(batch);
+        let mut ticker = tokio::time::interval(batch.flush_interval());
 
-            match &self.mode {
-                Mode::LPush { key } => {
-                    let Ok(key) = substitute_with_event(key, &event) else {
-                        continue;
+        loop {
+            tokio::select! {
+                event = router.poll_event(&self.id) => {
+                    let Ok(event) = event else {
+                        break;
                     };
-                    let value = serde_json::to_string(&event)?;
-                    redis_client.lpush(&key, value).await?;
-                    info!("event is dispatched to list {key}");
+                    info!("{} receives new event:\n\t{event:?}", self.id);
+
+                    match self.command(&event) {
+                        Ok(cmd) => batch.push(cmd, event),
+                        Err(e) => {
+                            self.dead_letter(&router, vec![event], e, 0).await;
+                            continue;
+                        }
+                    }
+
+                    if batch.is_full() {
+                        self.flush(&router, &mut batch, &mut con).await?;
+                    }
                 }
-                Mode::RPush { key } => {
-                    let Ok(key) = substitute_with_event(key, &event) else {
-                        continue;
-                    };
-                    let value = serde_json::to_string(&event)?;
-                    redis_client.rpush(&key, value).await?;
-                    info!("event is dispatched to list {key}");
+                _ = ticker.tick() => {
+                    if !batch.is_empty() {
+                        self.flush(&router, &mut batch, &mut con).await?;
+                    }
                 }
-                Mode::Pub { channel } => {
-                    let Ok(channel) = substitute_with_event(channel, &event) else {
-                        continue; 
-                    };
-                    let value = serde_json::to_string(&event)?;
-                    redis_client.publish(&channel, value).await?;
-                    info!("event is dispatched to channel {channel}");
+            }
+        }
+
+        if !batch.is_empty() {
+            self.flush(&router, &mut batch, &mut con).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RedisDispatcher {
+    /// Rejects settings that can't work together, without connecting to redis.
+    pub fn check(&self) -> Result<(), Error> {
+        // the replies of a transaction are read without redis-rs, over plain TCP only
+        if self.config.tls && self.batch.as_ref().is_some_and(|batch| batch.atomic) {
+            return Err(Error::Config(
+                "atomic batches are not supported over TLS".to_string(),
+            ));
+        }
+        if let Topology::Cluster { .. } = self.config.topology {
+            if self.batch.as_ref().is_some_and(|batch| batch.atomic) {
+                return Err(Error::Config(
+                    "atomic batches are not supported in a redis cluster".to_string(),
+                ));
+            }
+            if self.config.database_number != 0 {
+                return Err(Error::Config(
+                    "a redis cluster only has database 0".to_string(),
+                ));
+            }
+        }
+        // a delete event carries the old row, which would not match the member written for it
+        if let Mode::ZAdd {
+            member: None,
+            value,
+            ..
+        } = &self.mode
+        {
+            if !value.keep_deleted {
+                return Err(Error::Config(
+                    "ZAdd needs a member template to remove members on delete events, or \
+                     keep_deleted"
+                        .to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the command that delivers the event according to the mode.
+    fn command(&self, event: &Event) -> Result<Cmd, Error> {
+        let value = || serde_json::to_string(event);
+        let cmd = match &self.mode {
+            Mode::LPush { key } => {
+                let key = substitute_with_event(key, event).map_err(Error::Template)?;
+                redis::cmd("LPUSH").arg(key).arg(value()?).to_owned()
+            }
+            Mode::RPush { key } => {
+                let key = substitute_with_event(key, event).map_err(Error::Template)?;
+                redis::cmd("RPUSH").arg(key).arg(value()?).to_owned()
+            }
+            Mode::Pub { channel } => {
+                let channel = substitute_with_event(channel, event).map_err(Error::Template)?;
+                redis::cmd("PUBLISH").arg(channel).arg(value()?).to_owned()
+            }
+            Mode::XADD {
+                key,
+                field,
+                flatten,
+                trim,
+            } => {
+                let key = substitute_with_event(key, event).map_err(Error::Template)?;
+                let mut cmd = redis::cmd("XADD");
+                cmd.arg(key);
+                if let Some(trim) = trim {
+                    trim.write_args(&mut cmd);
+                }
+                cmd.arg("*");
+                match &event.value {
+                    Value::Object(fields) if *flatten && !fields.is_empty() => {
+                        for (name, value) in fields {
+                            match value {
+                                Value::String(s) => cmd.arg(name).arg(s),
+                                v => cmd.arg(name).arg(v.to_string()),
+                            };
+                        }
+                    }
+                    // an entry needs at least one field
+                    _ => {
+                        cmd.arg(field).arg(value()?);
+                    }
+                }
+                cmd
+            }
+            Mode::Set {
+                key,
+                ttl_secs,
+                value,
+            } => {
+                let key = substitute_with_event(key, event).map_err(Error::Template)?;
+                if value.deletes(event) {
+                    redis::cmd("DEL").arg(key).to_owned()
+                } else {
+                    let mut cmd = redis::cmd("SET");
+                    cmd.arg(key).arg(value.serialize(event)?);
+                    if let Some(ttl) = ttl_secs.filter(|ttl| *ttl > 0) {
+                        cmd.arg("EX").arg(ttl);
+                    }
+                    cmd
                 }
-                Mode::XADD { key } => {
-                    let Ok(key) = substitute_with_event(key, &event) else {
+            }
+            Mode::HSet { key, field, value } => {
+                let key = substitute_with_event(key, event).map_err(Error::Template)?;
+                let field = substitute_with_event(field, event).map_err(Error::Template)?;
+                if value.deletes(event) {
+                    redis::cmd("HDEL").arg(key).arg(field).to_owned()
+                } else {
+                    redis::cmd("HSET")
+                        .arg(key)
+                        .arg(field)
+                        .arg(value.serialize(event)?)
+                        .to_owned()
+                }
+            }
+            Mode::ZAdd {
+                key,
+                score,
+                member,
+                value,
+            } => {
+                let key = substitute_with_event(key, event).map_err(Error::Template)?;
+                let member = match member {
+                    Some(member) => {
+                        substitute_with_event(member, event).map_err(Error::Template)?
+                    }
+                    None => value.serialize(event)?,
+                };
+                if value.deletes(event) {
+                    redis::cmd("ZREM").arg(key).arg(member).to_owned()
+                } else {
+                    let score = match event.value.pointer(score) {
+                        Some(Value::Number(n)) => n.as_f64(),
+                        Some(Value::String(s)) => s.parse().ok(),
+                        _ => None,
+                    }
+                    .ok_or_else(|| Error::Template(format!("no numeric score at {score}")))?;
+                    redis::cmd("ZADD")
+                        .arg(key)
+                        .arg(score)
+                        .arg(member)
+                        .to_owned()
+                }
+            }
+        };
+        Ok(cmd)
+    }
+
+    /// Flushes the batch, reconnecting and retrying on connection errors. Only configuration
+    /// errors are returned, the events of commands rejected by redis or out of retries are
+    /// dead-lettered, and the events are abandoned if the shutdown is requested meanwhile.
+    async fn flush(
+        &self,
+        router: &EventRouter,
+        batch: &mut Batch,
+        con: &mut Option<Connection>,
+    ) -> Result<(), Error> {
+        let len = batch.len();
+        let mut rejected = 0;
+        let mut backoff = Backoff::new(&self.retry);
+        let start = Instant::now();
+        loop {
+            let res = match con {
+                Some(con) => batch.flush(con, &self.config).await,
+                None => match Connection::open(&self.config, batch.atomic()).await {
+                    Ok(c) => {
+                        *con = Some(c);
+                        router.set_ready(&self.id, true);
                         continue;
-                    };
-                    let value = serde_json::to_string(&event)?;
-                    redis_client
-                        .xadd(&key, "*", &[("event".to_string(), value)])
-                        .await?;
-                    info!("event is dispatched to stream {key}");
+                    }
+                    Err(e) => Err(e),
+                },
+            };
+            // sending the commands redis rejected again would not help
+            for (event, e) in batch.take_rejected() {
+                rejected += 1;
+                self.dead_letter(router, vec![event], e, backoff.attempts() + 1)
+                    .await;
+            }
+            let e = match res {
+                Ok(()) => {
+                    let delivered = len - rejected;
+                    info!("{} dispatched {delivered} events to redis", self.id);
+                    let metrics = router.metrics();
+                    metrics.observe(Metric::BatchSize, &self.id, delivered as f64);
+                    let latency = start.elapsed().as_secs_f64();
+                    metrics.observe(Metric::DispatchLatency, &self.id, latency);
+                    return Ok(());
+                }
+                Err(e @ Error::Config(_)) => return Err(e),
+                Err(e) => e,
+            };
+
+            if e.is_connection_error() {
+                *con = None;
+                router.set_ready(&self.id, false);
+            }
+            let reason = format!("{} failed to send to redis, {e}", self.id);
+            match backoff
+                .wait_unless(reason, router.shutdown_requested(&self.id))
+                .await
+            {
+                Wait::Retry => {}
+                Wait::Exhausted => {
+                    let attempts = backoff.attempts() + 1;
+                    self.dead_letter(router, batch.take(), e, attempts).await;
+                    return Ok(());
+                }
+                // the queue is left empty, so the dispatcher stops
+                Wait::Stopped => {
+                    abandon(router, batch.take(), &self.id).await;
+                    return Ok(());
                 }
             }
         }
+    }
 
-        Ok(())
+    async fn dead_letter(
+        &self,
+        router: &EventRouter,
+        events: Vec<Event>,
+        error: Error,
+        attempts: usize,
+    ) {
+        for event in events {
+            let dead_letter = self.dead_letter.as_deref();
+            send_to_dead_letter(router, dead_letter, event, &self.id, &error, attempts).await;
+        }
     }
 }
 
@@ -209,7 +678,7 @@ mod tests {
             channel: r"wlf_%{/file}".to_string(),
         });
 
-        let mut router = EventRouter::new();
+        let router = EventRouter::new();
         router.register_component(&dispatcher);
         let router = Arc::new(router);
 
@@ -224,8 +693,9 @@ mod tests {
                                 "file": "log1",
                                 "msg": "hello"
                             }),
-                            meta: EventMeta {},
+                            meta: EventMeta::default(),
                         },
+                        "test",
                         "redis_dispatcher",
                     )
                     .await
@@ -237,8 +707,9 @@ mod tests {
                                 "file": "log2",
                                 "msg": "hello"
                             }),
-                            meta: EventMeta {},
+                            meta: EventMeta::default(),
                         },
+                        "test",
                         "redis_dispatcher",
                     )
                     .await
@@ -250,4 +721,209 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn keep_undelivered_events() {
+        let mut dispatcher = RedisDispatcher::new("redis");
+        dispatcher.config.host = "127.0.0.1".to_string();
+        dispatcher.config.port = 1;
+        utils::test_utils::assert_keeps_undelivered_events(dispatcher).await;
+    }
+
+    #[test]
+    fn reject_atomic_batches_over_tls() {
+        let mut dispatcher = RedisDispatcher::new("redis");
+        dispatcher.set_batch(BatchConfig {
+            atomic: true,
+            ..Default::default()
+        });
+        assert!(dispatcher.check().is_ok());
+        dispatcher.config.tls = true;
+        assert!(dispatcher.check().is_err());
+    }
+
+    #[test]
+    fn key_value_commands() {
+        let mut dispatcher = RedisDispatcher::new("redis_dispatcher");
+        let event = |kind: &str| Event {
+            value: json!({ "type": kind, "ts": 1690848000, "data": { "id": 1, "name": "a" } }),
+            meta: EventMeta::default(),
+        };
+        let packed = |dispatcher: &RedisDispatcher, kind| {
+            dispatcher
+                .command(&event(kind))
+                .unwrap()
+                .get_packed_command()
+        };
+        let value = ValueConfig {
+            pointer: Some("/data".to_string()),
+            keep_deleted: false,
+        };
+
+        dispatcher.set_mode(Mode::Set {
+            key: "user:%{/data/id}".to_string(),
+            ttl_secs: Some(0),
+            value: ValueConfig::default(),
+        });
+        assert_eq!(
+            packed(&dispatcher, "insert"),
+            redis::cmd("SET")
+                .arg("user:1")
+                .arg(serde_json::to_string(&event("insert").value).unwrap())
+                .get_packed_command()
+        );
+
+        dispatcher.set_mode(Mode::Set {
+            key: "user:%{/data/id}".to_string(),
+            ttl_secs: Some(60),
+            value: value.clone(),
+        });
+        assert_eq!(
+            packed(&dispatcher, "insert"),
+            redis::cmd("SET")
+                .arg("user:1")
+                .arg(r#"{"id":1,"name":"a"}"#)
+                .arg("EX")
+                .arg(60)
+                .get_packed_command()
+        );
+        assert_eq!(
+            packed(&dispatcher, "delete"),
+            redis::cmd("DEL").arg("user:1").get_packed_command()
+        );
+
+        dispatcher.set_mode(Mode::HSet {
+            key: "users".to_string(),
+            field: "%{/data/id}".to_string(),
+            value: value.clone(),
+        });
+        assert_eq!(
+            packed(&dispatcher, "delete"),
+            redis::cmd("HDEL")
+                .arg("users")
+                .arg("1")
+                .get_packed_command()
+        );
+
+        dispatcher.set_mode(Mode::ZAdd {
+            key: "users".to_string(),
+            score: "/ts".to_string(),
+            member: None,
+            value: value.clone(),
+        });
+        assert!(dispatcher.check().is_err());
+
+        dispatcher.set_mode(Mode::ZAdd {
+            key: "users".to_string(),
+            score: "/ts".to_string(),
+            member: Some("%{/data/id}".to_string()),
+            value,
+        });
+        assert!(dispatcher.check().is_ok());
+        assert_eq!(
+            packed(&dispatcher, "update"),
+            redis::cmd("ZADD")
+                .arg("users")
+                .arg(1690848000.0)
+                .arg("1")
+                .get_packed_command()
+        );
+        assert_eq!(
+            packed(&dispatcher, "delete"),
+            redis::cmd("ZREM")
+                .arg("users")
+                .arg("1")
+                .get_packed_command()
+        );
+    }
+
+    #[test]
+    fn stream_commands() {
+        let mut dispatcher = RedisDispatcher::new("redis_dispatcher");
+        let event = |value| Event {
+            value,
+            meta: EventMeta::default(),
+        };
+        let packed = |dispatcher: &RedisDispatcher, event: &Event| {
+            dispatcher.command(event).unwrap().get_packed_command()
+        };
+        let fields = event(json!({ "msg": "hello", "n": 1 }));
+
+        // the field defaults to `event`
+        let mode: Mode =
+            serde_json::from_value(json!({ "type": "XADD", "key": "stream" })).unwrap();
+        dispatcher.set_mode(mode);
+        assert_eq!(
+            packed(&dispatcher, &fields),
+            redis::cmd("XADD")
+                .arg("stream")
+                .arg("*")
+                .arg("event")
+                .arg(serde_json::to_string(&fields).unwrap())
+                .get_packed_command()
+        );
+
+        dispatcher.set_mode(Mode::XADD {
+            key: "stream".to_string(),
+            field: "event".to_string(),
+            flatten: false,
+            trim: Some(StreamTrim::MaxLen {
+                threshold: 1000,
+                approximate: true,
+            }),
+        });
+        assert_eq!(
+            packed(&dispatcher, &fields),
+            redis::cmd("XADD")
+                .arg("stream")
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(1000)
+                .arg("*")
+                .arg("event")
+                .arg(serde_json::to_string(&fields).unwrap())
+                .get_packed_command()
+        );
+
+        dispatcher.set_mode(Mode::XADD {
+            key: "stream".to_string(),
+            field: "event".to_string(),
+            flatten: true,
+            trim: Some(StreamTrim::MinId {
+                threshold: "1690848000000-0".to_string(),
+                approximate: false,
+            }),
+        });
+        assert_eq!(
+            packed(&dispatcher, &fields),
+            redis::cmd("XADD")
+                .arg("stream")
+                .arg("MINID")
+                .arg("=")
+                .arg("1690848000000-0")
+                .arg("*")
+                .arg("msg")
+                .arg("hello")
+                .arg("n")
+                .arg("1")
+                .get_packed_command()
+        );
+
+        // nothing to flatten, the whole event is written to the field
+        for value in [json!({}), json!("hello")] {
+            let event = event(value);
+            assert_eq!(
+                packed(&dispatcher, &event),
+                redis::cmd("XADD")
+                    .arg("stream")
+                    .arg("MINID")
+                    .arg("=")
+                    .arg("1690848000000-0")
+                    .arg("*")
+                    .arg("event")
+                    .arg(serde_json::to_string(&event).unwrap())
+                    .get_packed_command()
+            );
+        }
+    }
 }
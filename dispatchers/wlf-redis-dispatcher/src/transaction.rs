@@ -0,0 +1,256 @@
+//! Atomic batches, sent as MULTI/EXEC on a connection of their own. redis-rs only keeps the
+//! first error among the replies of EXEC, while the other commands of the transaction are
+//! applied anyway, so the replies are read here and the reply of every command is parsed on
+//! its own.
+
+use std::io;
+
+use redis::{Cmd, ConnectionAddr, ConnectionInfo, ErrorKind, RedisError, RedisResult, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::connection::Replies;
+
+/// The replies of the commands if the transaction ran, else the error of EXEC.
+type Exec = RedisResult<Vec<RedisResult<Value>>>;
+
+pub(crate) struct TransactionConnection {
+    stream: BufReader<TcpStream>,
+}
+
+impl TransactionConnection {
+    pub(crate) async fn open(info: ConnectionInfo) -> RedisResult<Self> {
+        let ConnectionAddr::Tcp(host, port) = &info.addr else {
+            return Err((
+                ErrorKind::InvalidClientConfig,
+                "atomic batches are only sent over plain TCP",
+            )
+                .into());
+        };
+        let stream = TcpStream::connect((host.as_str(), *port)).await?;
+        let mut con = Self {
+            stream: BufReader::new(stream),
+        };
+
+        if let Some(password) = &info.redis.password {
+            let mut auth = redis::cmd("AUTH");
+            if let Some(username) = &info.redis.username {
+                auth.arg(username);
+            }
+            con.query(auth.arg(password)).await?;
+        }
+        if info.redis.db != 0 {
+            con.query(redis::cmd("SELECT").arg(info.redis.db)).await?;
+        }
+        Ok(con)
+    }
+
+    /// Sends the commands wrapped in MULTI/EXEC and returns the outcome of each of them.
+    pub(crate) async fn send(&mut self, cmds: &[Cmd]) -> Replies {
+        match self.exec(cmds).await {
+            Ok(replies) => replies,
+            Err(e) => cmds.iter().map(|_| Err(copy_error(&e))).collect(),
+        }
+    }
+
+    async fn exec(&mut self, cmds: &[Cmd]) -> RedisResult<Replies> {
+        let mut packed = Vec::new();
+        for cmd in transaction(cmds) {
+            cmd.write_packed_command(&mut packed);
+        }
+        self.stream.get_mut().write_all(&packed).await?;
+
+        // the replies to MULTI and to the queued commands
+        let mut replies = Vec::with_capacity(cmds.len() + 1);
+        for _ in 0..=cmds.len() {
+            replies.push(redis::parse_redis_value(&self.read_reply().await?));
+        }
+        let kind = self.stream.fill_buf().await?.first().copied();
+        let exec = match kind {
+            Some(b'*') => {
+                let mut header = Vec::new();
+                self.read_line(&mut header).await?;
+                match parse_header(&header)? {
+                    (_, len) if len >= 0 => {
+                        let mut results = Vec::with_capacity(len as usize);
+                        for _ in 0..len {
+                            results.push(redis::parse_redis_value(&self.read_reply().await?));
+                        }
+                        Ok(results)
+                    }
+                    // aborted by WATCH, which is not used
+                    _ => Err((ErrorKind::TypeError, "unexpected EXEC reply, nil").into()),
+                }
+            }
+            _ => match redis::parse_redis_value(&self.read_reply().await?) {
+                Ok(other) => {
+                    let detail = format!("{other:?}");
+                    Err((ErrorKind::TypeError, "unexpected EXEC reply", detail).into())
+                }
+                Err(e) => Err(e),
+            },
+        };
+        Ok(transaction_replies(replies, exec))
+    }
+
+    async fn query(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        let packed = cmd.get_packed_command();
+        self.stream.get_mut().write_all(&packed).await?;
+        redis::parse_redis_value(&self.read_reply().await?)
+    }
+
+    /// Reads a whole reply, nested ones included, as it was sent.
+    async fn read_reply(&mut self) -> io::Result<Vec<u8>> {
+        let mut reply = Vec::new();
+        let mut left = 1;
+        while left > 0 {
+            left -= 1;
+            let start = reply.len();
+            self.read_line(&mut reply).await?;
+            match parse_header(&reply[start..])? {
+                (b'$', len) if len >= 0 => {
+                    let mut data = vec![0; len as usize + 2];
+                    self.stream.read_exact(&mut data).await?;
+                    reply.extend(data);
+                }
+                (b'*', len) if len > 0 => left += len as usize,
+                _ => {}
+            }
+        }
+        Ok(reply)
+    }
+
+    async fn read_line(&mut self, buf: &mut Vec<u8>) -> io::Result<()> {
+        if self.stream.read_until(b'\n', buf).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+/// The type of a reply and the length of a bulk string or an array, 0 for the other types.
+fn parse_header(line: &[u8]) -> io::Result<(u8, i64)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid redis reply");
+    let (&kind, rest) = line.split_first().ok_or_else(invalid)?;
+    match kind {
+        b'+' | b'-' | b':' => Ok((kind, 0)),
+        b'$' | b'*' => {
+            let len = std::str::from_utf8(rest)
+                .ok()
+                .and_then(|len| len.trim_end().parse().ok())
+                .ok_or_else(invalid)?;
+            Ok((kind, len))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// The commands wrapped in MULTI/EXEC.
+fn transaction(cmds: &[Cmd]) -> Vec<Cmd> {
+    let mut transaction = vec![redis::cmd("MULTI")];
+    transaction.extend(cmds.iter().cloned());
+    transaction.push(redis::cmd("EXEC"));
+    transaction
+}
+
+/// The outcome of every command of a transaction from the replies to MULTI and the commands,
+/// and the one of EXEC. A command failing while the transaction runs gets its own error, the
+/// others are applied. If the transaction is aborted, the commands rejected when queued keep
+/// their own errors and the others share the one of EXEC.
+fn transaction_replies(replies: Vec<RedisResult<Value>>, exec: Exec) -> Replies {
+    let mut replies = replies.into_iter();
+    // without a transaction, the commands were run one by one
+    if let Some(Err(_)) = replies.next() {
+        return replies.map(|reply| reply.map(drop)).collect();
+    }
+    match exec {
+        Ok(results) if results.len() == replies.len() => {
+            results.into_iter().map(|result| result.map(drop)).collect()
+        }
+        Ok(results) => replies
+            .map(|_| {
+                let detail = format!("{} replies", results.len());
+                Err((ErrorKind::TypeError, "unexpected EXEC reply", detail).into())
+            })
+            .collect(),
+        Err(e) => replies
+            .map(|queued| {
+                queued?;
+                Err(copy_error(&e))
+            })
+            .collect(),
+    }
+}
+
+/// A copy of an error shared by several commands, as redis errors can't be cloned.
+pub(crate) fn copy_error(e: &RedisError) -> RedisError {
+    if e.is_io_error() {
+        io::Error::other(e.to_string()).into()
+    } else {
+        (e.kind(), "the transaction failed", e.to_string()).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transaction_outcome() {
+        let cmds = [
+            redis::cmd("RPUSH").arg("k").arg(1).to_owned(),
+            redis::cmd("RPUSH").arg("k").arg(2).to_owned(),
+        ];
+        let transaction = transaction(&cmds);
+        assert_eq!(transaction.len(), 4);
+        assert_eq!(
+            transaction[0].get_packed_command(),
+            b"*1\r\n$5\r\nMULTI\r\n"
+        );
+        assert_eq!(
+            transaction[1].get_packed_command(),
+            cmds[0].get_packed_command()
+        );
+        assert_eq!(transaction[3].get_packed_command(), b"*1\r\n$4\r\nEXEC\r\n");
+
+        let queued = || Ok(Value::Status("QUEUED".to_string()));
+        let replies = transaction_replies(
+            vec![Ok(Value::Okay), queued(), queued()],
+            Ok(vec![Ok(Value::Int(1)), Ok(Value::Int(2))]),
+        );
+        assert!(replies.iter().all(Result::is_ok));
+
+        // the second command failed while the transaction ran, the first one is applied
+        let wrong_type = redis::parse_redis_value(
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+        );
+        let replies = transaction_replies(
+            vec![Ok(Value::Okay), queued(), queued()],
+            Ok(vec![Ok(Value::Int(1)), wrong_type]),
+        );
+        assert!(replies[0].is_ok());
+        assert_eq!(replies[1].as_ref().unwrap_err().code(), Some("WRONGTYPE"));
+
+        // the second command is rejected, so the transaction is aborted
+        let replies = transaction_replies(
+            vec![
+                Ok(Value::Okay),
+                queued(),
+                Err((ErrorKind::ResponseError, "wrong number of arguments").into()),
+            ],
+            Err((ErrorKind::ExecAbortError, "discarded").into()),
+        );
+        assert_eq!(replies[0].as_ref().unwrap_err().code(), Some("EXECABORT"));
+        assert_eq!(replies[1].as_ref().unwrap_err().code(), Some("ERR"));
+    }
+
+    #[test]
+    fn reply_headers() {
+        assert_eq!(parse_header(b"*2\r\n").unwrap(), (b'*', 2));
+        assert_eq!(parse_header(b"$-1\r\n").unwrap(), (b'$', -1));
+        assert_eq!(parse_header(b"-ERR wrong\r\n").unwrap(), (b'-', 0));
+        assert!(parse_header(b"?\r\n").is_err());
+    }
+}
@@ -1,15 +1,16 @@
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::info;
 use wlf_core::{
     event_router::{EventRouter, EventRouterApi},
+    metrics::Metric,
     ComponentApi, ComponentKind, Event, Value,
 };
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BinlogFilter {
     pub id: String,
     pub destination: String,
@@ -38,25 +39,35 @@ impl ComponentApi for BinlogFilter {
             info!("{} receives new event:\n\t{event:?}", self.id);
 
             if !self.rules.eval(&event) {
+                router
+                    .metrics()
+                    .increment(Metric::EventsDropped, &self.id, 1);
                 continue;
             }
 
-            router.send_event(event, self.destination.as_str()).await?;
+            router
+                .send_event(event, &self.id, self.destination.as_str())
+                .await?;
         }
         Ok(())
     }
 }
 
-#[derive(Default, Deserialize, Debug)]
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct BinlogFilterRules {
     rules: Vec<BinlogFilterRule>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
+/// A rule matching the events of a table, `*` matches any table. A `*` database matches every
+/// event whatever its table, use `include_table`/`exclude_table` to match a table in any
+/// database. The last matching rule decides whether an event is kept.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
 enum BinlogFilterRule {
     Include { database: String, table: String },
     Exclude { database: String, table: String },
+    IncludeTable { table: String },
+    ExcludeTable { table: String },
 }
 
 impl BinlogFilterRules {
@@ -78,6 +89,20 @@ impl BinlogFilterRules {
         });
     }
 
+    /// Includes the table in any database.
+    pub fn include_table(&mut self, table: impl Into<String>) {
+        self.rules.push(BinlogFilterRule::IncludeTable {
+            table: table.into(),
+        });
+    }
+
+    /// Excludes the table in any database.
+    pub fn exclude_table(&mut self, table: impl Into<String>) {
+        self.rules.push(BinlogFilterRule::ExcludeTable {
+            table: table.into(),
+        });
+    }
+
     fn eval(&self, event: &Event) -> bool {
         self.rules.iter().fold(true, |st, rule| match rule {
             BinlogFilterRule::Include { database, table } => {
@@ -123,6 +148,60 @@ impl BinlogFilterRules {
                     _ => st,
                 }
             }
+            BinlogFilterRule::IncludeTable { table } => match event.value.pointer("/table") {
+                Some(Value::String(t)) if t == table => true,
+                _ => st,
+            },
+            BinlogFilterRule::ExcludeTable { table } => match event.value.pointer("/table") {
+                Some(Value::String(t)) if t == table => false,
+                _ => st,
+            },
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use wlf_core::{value, EventMeta};
+
+    use super::*;
+
+    fn event(database: &str, table: &str) -> Event {
+        Event {
+            value: value!({ "database": database, "table": table }),
+            meta: EventMeta::default(),
+        }
+    }
+
+    #[test]
+    fn match_wildcards() {
+        let mut rules = BinlogFilterRules::new();
+        rules.exclude("*", "*");
+        rules.include("shop", "*");
+        rules.exclude_table("audit");
+        rules.include("shop", "audit");
+
+        assert!(rules.eval(&event("shop", "orders")));
+        assert!(!rules.eval(&event("crm", "orders")));
+        assert!(!rules.eval(&event("crm", "audit")));
+        // the last matching rule wins
+        assert!(rules.eval(&event("shop", "audit")));
+
+        // a `*` database decides for every event, whatever the table
+        rules.include("*", "orders");
+        assert!(rules.eval(&event("crm", "audit")));
+    }
+
+    #[test]
+    fn keep_unmatched_events() {
+        let mut rules = BinlogFilterRules::new();
+        rules.exclude("shop", "orders");
+
+        assert!(rules.eval(&event("shop", "users")));
+        assert!(!rules.eval(&event("shop", "orders")));
+        assert!(rules.eval(&Event {
+            value: value!({ "message": "not a binlog event" }),
+            meta: EventMeta::default(),
+        }));
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::info;
 use wlf_core::{
@@ -9,12 +9,18 @@ use wlf_core::{
     ComponentApi, ComponentKind,
 };
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EventReplicator {
     id: String,
     destinations: Vec<String>,
 }
 
+impl EventReplicator {
+    pub fn destinations(&self) -> &[String] {
+        &self.destinations
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("event router error, {0}")]
@@ -36,7 +42,7 @@ impl ComponentApi for EventReplicator {
             info!("{} receives new event:\n\t{event:?}", self.id);
 
             for d in &self.destinations {
-                router.send_event(event.clone(), d).await?;
+                router.send_event(event.clone(), &self.id, d).await?;
             }
         }
         Ok(())
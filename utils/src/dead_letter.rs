@@ -0,0 +1,161 @@
+//! Routing of events that a component fails to handle
+
+use std::fmt::Display;
+
+use tracing::{error, warn};
+use wlf_core::{
+    event_router::{EventRouter, EventRouterApi},
+    metrics::Metric,
+    DeadLetter, Event,
+};
+
+/// Annotates the event with the failure and sends it to the dead-letter component. Without a
+/// dead-letter component, or if it can't take the event, the event is logged and dropped. An
+/// event that already failed once is never dead-lettered again, so it can't bounce between
+/// dead-letter components forever.
+pub async fn send_to_dead_letter(
+    router: &EventRouter,
+    dead_letter: Option<&str>,
+    mut event: Event,
+    component_id: &str,
+    error: impl Display,
+    attempts: usize,
+) {
+    router
+        .metrics()
+        .increment(Metric::EventsFailed, component_id, 1);
+    let Some(destination) = dead_letter.filter(|_| event.meta.dead_letter.is_none()) else {
+        router
+            .metrics()
+            .increment(Metric::EventsDropped, component_id, 1);
+        error!("{component_id} drops an event after {attempts} attempts, {error}:\n\t{event:?}");
+        return;
+    };
+
+    event.meta.dead_letter = Some(DeadLetter {
+        component_id: component_id.to_string(),
+        error: error.to_string(),
+        attempts,
+    });
+    if let Err(e) = router.send_event(event, component_id, destination).await {
+        router
+            .metrics()
+            .increment(Metric::EventsDropped, component_id, 1);
+        error!(
+            "{component_id} failed to send an event to dead-letter component {destination}, {e}"
+        );
+    }
+}
+
+/// Gives the events up without them counting as handled, along with the events still queued
+/// for the component, once its shutdown is requested while they can't be delivered. Their
+/// collector doesn't acknowledge them, so they are read again on the next start.
+pub async fn abandon(router: &EventRouter, mut events: Vec<Event>, component_id: &str) {
+    while let Ok(event) = router.poll_event(component_id).await {
+        events.push(event);
+    }
+    warn!(
+        "{component_id} stops with {} undelivered events, they are not acknowledged",
+        events.len()
+    );
+    for event in events {
+        if let Some(delivery) = event.meta.delivery {
+            delivery.abandon();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wlf_core::{value, ComponentKind, Delivery, EventMeta};
+
+    use crate::test_utils::DummyComponent;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn annotate_failed_event() {
+        let router = EventRouter::new();
+        router.register_component(&DummyComponent::new("dlq", ComponentKind::Dispatcher));
+
+        let event = Event {
+            value: value!({ "table": "t1" }),
+            meta: EventMeta::default(),
+        };
+        send_to_dead_letter(
+            &router,
+            Some("dlq"),
+            event,
+            "redis",
+            "no /id in the event",
+            0,
+        )
+        .await;
+
+        let event = router.poll_event("dlq").await.unwrap();
+        assert_eq!(event.meta.source.as_deref(), Some("redis"));
+        assert_eq!(event.meta.lineage, vec!["redis", "dlq"]);
+        assert_eq!(
+            event.meta.dead_letter,
+            Some(DeadLetter {
+                component_id: "redis".to_string(),
+                error: "no /id in the event".to_string(),
+                attempts: 0,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn drop_dead_lettered_event() {
+        let router = EventRouter::new();
+        router.register_component(&DummyComponent::new("dlq", ComponentKind::Dispatcher));
+
+        let dead_letter = DeadLetter {
+            component_id: "kafka".to_string(),
+            error: "message too large".to_string(),
+            attempts: 3,
+        };
+        let event = Event {
+            value: value!({ "table": "t1" }),
+            meta: EventMeta {
+                dead_letter: Some(dead_letter),
+                ..Default::default()
+            },
+        };
+        send_to_dead_letter(&router, Some("dlq"), event, "dlq", "message too large", 3).await;
+
+        assert!(router
+            .metrics()
+            .render(&[])
+            .contains("wlf_events_dropped_total{component=\"dlq\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn abandon_on_shutdown() {
+        let router = EventRouter::new();
+        router.register_component(&DummyComponent::new("redis", ComponentKind::Dispatcher));
+
+        let (delivery, receipt) = Delivery::track();
+        for _ in 0..2 {
+            let event = Event {
+                value: value!({ "table": "t1" }),
+                meta: EventMeta {
+                    delivery: Some(delivery.clone()),
+                    ..Default::default()
+                },
+            };
+            router.send_event(event, "binlog", "redis").await.unwrap();
+        }
+        drop(delivery);
+        router.request_shutdown("redis");
+
+        // the one being retried and the queued one are both kept from the collector
+        let event = router.poll_event("redis").await.unwrap();
+        abandon(&router, vec![event], "redis").await;
+        assert!(matches!(
+            router.poll_event("redis").await,
+            Err(wlf_core::event_router::Error::ShutDown)
+        ));
+        assert!(!receipt.is_delivered());
+    }
+}
@@ -1,9 +1,19 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, TimeZone, Utc};
 use once_cell::unsync::Lazy;
 use regex::{Captures, Regex};
+use serde::{de, Deserialize, Deserializer};
 use wlf_core::{Event, Value};
 
+pub mod dead_letter;
+pub mod retry;
 pub mod test_utils;
 
+/// Replaces every `%{/json/pointer}` in the template with the field of the event at the pointer.
+///
+/// `%{+format}` is replaced with the event timestamp (the `/timestamp` field, or now if the event
+/// has none) in the given Joda style date format, e.g. `wlf-%{+yyyy.MM.dd}`.
 pub fn substitute_with_event(template: &str, event: &Event) -> Result<String, String> {
     let replacer =
         Lazy::new(|| Regex::new(r"%\{(?P<path>.+?)\}").expect("can't create topic replacer"));
@@ -11,13 +21,24 @@ pub fn substitute_with_event(template: &str, event: &Event) -> Result<String, St
     let topic_name = replacer
         .replace_all(template, |caps: &Captures| {
             let path = &caps["path"];
-            if let Some(Value::String(value)) = event.value.pointer(path) {
-                value.to_string()
-            } else {
-                fail_reason = Some(format!(
-                    "no {path} field or {path} is not string, event: {event:?}"
-                ));
-                String::new()
+            if let Some(format) = path.strip_prefix('+') {
+                return match joda_to_strftime(format) {
+                    Ok(format) => event_timestamp(event).format(&format).to_string(),
+                    Err(e) => {
+                        fail_reason = Some(format!("invalid date format {format}, {e}"));
+                        String::new()
+                    }
+                };
+            }
+            match event.value.pointer(path) {
+                Some(Value::String(value)) => value.to_string(),
+                Some(Value::Number(value)) => value.to_string(),
+                _ => {
+                    fail_reason = Some(format!(
+                        "no {path} field or {path} is not string or number, event: {event:?}"
+                    ));
+                    String::new()
+                }
             }
         })
         .to_string();
@@ -27,3 +48,215 @@ pub fn substitute_with_event(template: &str, event: &Event) -> Result<String, St
         Ok(topic_name)
     }
 }
+
+/// Deserializes a duration or a count that must not be 0, e.g. the period of a timer.
+pub fn non_zero<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match number(deserializer)? {
+        0 => Err(de::Error::invalid_value(
+            de::Unexpected::Unsigned(0),
+            &"a number greater than 0",
+        )),
+        n => Ok(n),
+    }
+}
+
+/// Deserializes a number, or a string holding one, e.g. a port given as `"${PORT}"`.
+pub fn number<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    NumberOrString::deserialize(deserializer)?.parse()
+}
+
+/// Like [`number`], for optional fields.
+pub fn option_number<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    Option::<NumberOrString<T>>::deserialize(deserializer)?
+        .map(NumberOrString::parse)
+        .transpose()
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString<T> {
+    Number(T),
+    String(String),
+}
+
+impl<T> NumberOrString<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    fn parse<E: de::Error>(self) -> Result<T, E> {
+        match self {
+            NumberOrString::Number(n) => Ok(n),
+            NumberOrString::String(s) => s
+                .trim()
+                .parse()
+                .map_err(|e| E::custom(format!("invalid number {s:?}, {e}"))),
+        }
+    }
+}
+
+/// The `/timestamp` of the event, either a RFC 3339 string or seconds since the epoch.
+fn event_timestamp(event: &Event) -> DateTime<Utc> {
+    let timestamp = match event.value.pointer("/timestamp") {
+        Some(Value::String(t)) => DateTime::parse_from_rfc3339(t)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+        Some(Value::Number(t)) => t.as_i64().and_then(|t| Utc.timestamp_opt(t, 0).single()),
+        _ => None,
+    };
+    timestamp.unwrap_or_else(Utc::now)
+}
+
+/// Converts the common Joda date patterns (as used by logstash and elasticsearch) to strftime.
+///
+/// Text in single quotes is kept as is, and `w` is the ISO week of the `x` week year, as the
+/// calendar year (`y`) and the week year differ around New Year.
+fn joda_to_strftime(format: &str) -> Result<String, String> {
+    let mut strftime = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // `''` is a quote, inside or outside of a literal
+            if chars.peek() == Some(&'\'') {
+                chars.next();
+                strftime.push('\'');
+                continue;
+            }
+            loop {
+                match chars.next() {
+                    Some('\'') if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        strftime.push('\'');
+                    }
+                    Some('\'') => break,
+                    Some('%') => strftime.push_str("%%"),
+                    Some(c) => strftime.push(c),
+                    None => return Err("unterminated quoted text".to_string()),
+                }
+            }
+            continue;
+        }
+        let mut count = 1;
+        while chars.peek() == Some(&c) {
+            chars.next();
+            count += 1;
+        }
+        let pattern = match (c, count) {
+            ('y' | 'Y', 2) => "%y".to_string(),
+            ('y' | 'Y', _) => "%Y".to_string(),
+            ('x', 2) => "%g".to_string(),
+            ('x', _) => "%G".to_string(),
+            ('w', 1) => "%-V".to_string(),
+            ('w', _) => "%V".to_string(),
+            ('M', 1) => "%-m".to_string(),
+            ('M', _) => "%m".to_string(),
+            ('d', 1) => "%-d".to_string(),
+            ('d', _) => "%d".to_string(),
+            ('H', 1) => "%-H".to_string(),
+            ('H', _) => "%H".to_string(),
+            ('m', 1) => "%-M".to_string(),
+            ('m', _) => "%M".to_string(),
+            ('s', 1) => "%-S".to_string(),
+            ('s', _) => "%S".to_string(),
+            (c, _) if c.is_ascii_alphabetic() => {
+                return Err(format!("unsupported pattern letter {c}"))
+            }
+            ('%', _) => "%%".repeat(count),
+            (c, _) => c.to_string().repeat(count),
+        };
+        strftime.push_str(&pattern);
+    }
+    Ok(strftime)
+}
+
+#[cfg(test)]
+mod tests {
+    use wlf_core::{value, EventMeta};
+
+    use super::*;
+
+    #[test]
+    fn substitute() {
+        let event = Event {
+            value: value!({
+                "database": "d1",
+                "table": "t1",
+                "timestamp": "2023-08-01T10:20:30Z",
+                "data": { "id": 1 }
+            }),
+            meta: EventMeta::default(),
+        };
+
+        assert_eq!(
+            substitute_with_event("wlf-%{/database}-%{/table}-%{/data/id}", &event),
+            Ok("wlf-d1-t1-1".to_string())
+        );
+        assert_eq!(
+            substitute_with_event("wlf-%{/table}-%{+yyyy.MM.dd}", &event),
+            Ok("wlf-t1-2023.08.01".to_string())
+        );
+        assert!(substitute_with_event("wlf-%{/nothing}", &event).is_err());
+        assert!(substitute_with_event("wlf-%{+yyyy.MM.dd G}", &event).is_err());
+    }
+
+    #[test]
+    fn joda() {
+        assert_eq!(
+            joda_to_strftime("yyyy-MM-dd'T'HH:mm:ss").unwrap(),
+            "%Y-%m-%dT%H:%M:%S"
+        );
+        assert_eq!(joda_to_strftime("'week' w, ''yy").unwrap(), "week %-V, '%y");
+        assert!(joda_to_strftime("yyyy 'week").is_err());
+        assert!(joda_to_strftime("yyyy.MM.dd z").is_err());
+
+        // 2021-01-01 is in the 53rd week of 2020
+        let timestamp = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let format = joda_to_strftime("xxxx.ww").unwrap();
+        assert_eq!(timestamp.format(&format).to_string(), "2020.53");
+    }
+
+    #[test]
+    fn numbers_as_strings() {
+        #[derive(Deserialize)]
+        struct Config {
+            #[serde(deserialize_with = "number")]
+            port: u16,
+            #[serde(default, deserialize_with = "option_number")]
+            timeout_secs: Option<u64>,
+            #[serde(deserialize_with = "non_zero")]
+            interval_ms: u64,
+        }
+
+        let config: Config = serde_json::from_value(
+            value!({ "port": "6380", "timeout_secs": 5, "interval_ms": "10" }),
+        )
+        .unwrap();
+        assert_eq!(config.port, 6380);
+        assert_eq!(config.timeout_secs, Some(5));
+        assert_eq!(config.interval_ms, 10);
+
+        let config: Config = serde_json::from_value(
+            value!({ "port": 6380, "timeout_secs": null, "interval_ms": 10 }),
+        )
+        .unwrap();
+        assert_eq!(config.timeout_secs, None);
+
+        for invalid in [
+            value!({ "port": "redis", "interval_ms": 10 }),
+            value!({ "port": "70000", "interval_ms": 10 }),
+            value!({ "port": 6380, "interval_ms": "0" }),
+        ] {
+            assert!(serde_json::from_value::<Config>(invalid).is_err());
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Exponential backoff for reconnecting to external services
+
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RetryConfig {
+    /// Delay before the first retry, in milliseconds.
+    #[serde(
+        default = "default_initial_backoff_ms",
+        deserialize_with = "crate::number"
+    )]
+    pub initial_backoff_ms: u64,
+    /// Upper bound of the delay between two retries, in milliseconds.
+    #[serde(default = "default_max_backoff_ms", deserialize_with = "crate::number")]
+    pub max_backoff_ms: u64,
+    /// Factor the delay grows by after every failed retry.
+    #[serde(default = "default_multiplier", deserialize_with = "crate::number")]
+    pub multiplier: f64,
+    /// Fraction of the delay that is randomized, so that many clients don't retry in lockstep.
+    #[serde(default = "default_jitter", deserialize_with = "crate::number")]
+    pub jitter: f64,
+    /// Give up after this many retries, retry forever if not set.
+    #[serde(default, deserialize_with = "crate::option_number")]
+    pub max_retries: Option<usize>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            multiplier: default_multiplier(),
+            jitter: default_jitter(),
+            max_retries: None,
+        }
+    }
+}
+
+pub fn default_initial_backoff_ms() -> u64 {
+    100
+}
+
+pub fn default_max_backoff_ms() -> u64 {
+    30_000
+}
+
+pub fn default_multiplier() -> f64 {
+    2.0
+}
+
+pub fn default_jitter() -> f64 {
+    0.2
+}
+
+/// How [`Backoff::wait_unless`] ended.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Wait {
+    /// The delay is over, retry now
+    Retry,
+    /// There should be no more retries
+    Exhausted,
+    /// Stopped while waiting, e.g. on shutdown
+    Stopped,
+}
+
+/// The retry state of a single operation.
+pub struct Backoff {
+    config: RetryConfig,
+    attempts: usize,
+}
+
+impl Backoff {
+    pub fn new(config: &RetryConfig) -> Self {
+        Self {
+            config: config.clone(),
+            attempts: 0,
+        }
+    }
+
+    /// Number of retries so far.
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    /// The delay before the next retry, `None` once the retries are exhausted.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self
+            .config
+            .max_retries
+            .is_some_and(|max| self.attempts >= max)
+        {
+            return None;
+        }
+
+        let base = self.config.initial_backoff_ms as f64
+            * self.config.multiplier.powi(self.attempts.min(64) as i32);
+        let base = base.min(self.config.max_backoff_ms as f64);
+        let jitter = self.config.jitter.clamp(0.0, 1.0);
+        // uniformly in [base * (1 - jitter), base * (1 + jitter)]
+        let delay = base * (1.0 - jitter + 2.0 * jitter * random_unit());
+        self.attempts += 1;
+        Some(Duration::from_millis(delay as u64))
+    }
+
+    /// Sleeps before the next retry, returns false if there should be no more retries.
+    pub async fn wait(&mut self, reason: impl std::fmt::Display) -> bool {
+        self.wait_unless(reason, std::future::pending()).await == Wait::Retry
+    }
+
+    /// Sleeps before the next retry like [`wait`](Self::wait), but gives up as soon as `stop`
+    /// resolves, e.g. once the shutdown of the component is requested.
+    pub async fn wait_unless(
+        &mut self,
+        reason: impl std::fmt::Display,
+        stop: impl Future<Output = ()>,
+    ) -> Wait {
+        let Some(delay) = self.next_delay() else {
+            return Wait::Exhausted;
+        };
+        warn!(
+            "{reason}, retrying in {delay:?} (attempt {})",
+            self.attempts
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => Wait::Retry,
+            _ = stop => Wait::Stopped,
+        }
+    }
+}
+
+/// A random number in `[0, 1)`, good enough for jitter.
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_delays() {
+        let config = RetryConfig {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+            jitter: 0.0,
+            max_retries: Some(6),
+            ..Default::default()
+        };
+        let mut backoff = Backoff::new(&config);
+        let delays: Vec<_> = std::iter::from_fn(|| backoff.next_delay())
+            .map(|d| d.as_millis())
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+
+        let config = RetryConfig {
+            jitter: 0.5,
+            ..config
+        };
+        let mut backoff = Backoff::new(&config);
+        for _ in 0..6 {
+            let delay = backoff.next_delay().unwrap().as_millis();
+            assert!((50..=1500).contains(&delay));
+        }
+    }
+
+    #[tokio::test]
+    async fn stop_waiting() {
+        let config = RetryConfig {
+            initial_backoff_ms: 60_000,
+            max_retries: Some(1),
+            ..Default::default()
+        };
+        let mut backoff = Backoff::new(&config);
+        let stop = std::future::ready(());
+        assert_eq!(backoff.wait_unless("failed", stop).await, Wait::Stopped);
+        let stop = std::future::pending();
+        assert_eq!(backoff.wait_unless("failed", stop).await, Wait::Exhausted);
+    }
+}
@@ -1,9 +1,12 @@
 //! Utilities for testing
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use wlf_core::{event_router::EventRouter, ComponentApi, ComponentKind};
+use wlf_core::{
+    event_router::{EventRouter, EventRouterApi},
+    value, ComponentApi, ComponentKind, Delivery, Event, EventMeta,
+};
 
 pub struct DummyComponent {
     id: String,
@@ -33,3 +36,32 @@ impl ComponentApi for DummyComponent {
         Ok(())
     }
 }
+
+/// Runs a dispatcher whose destination can't be reached and checks that it holds on to the
+/// event it is given, instead of dropping it as if it was delivered.
+pub async fn assert_keeps_undelivered_events(dispatcher: impl ComponentApi) {
+    let router = Arc::new(EventRouter::new());
+    router.register_component(&dispatcher);
+    let (delivery, receipt) = Delivery::track();
+    let event = Event {
+        value: value!({ "type": "insert", "data": { "id": 1 } }),
+        meta: EventMeta {
+            delivery: Some(delivery),
+            ..Default::default()
+        },
+    };
+    router
+        .send_event(event, "collector", dispatcher.id())
+        .await
+        .unwrap();
+
+    // polled by reference, so that the events of the dispatcher are not dropped on timeout
+    let mut run = dispatcher.run(Arc::clone(&router));
+    let stopped = tokio::time::timeout(Duration::from_millis(500), &mut run).await;
+    assert!(stopped.is_err(), "{} stopped", dispatcher.id());
+    assert!(
+        !receipt.is_delivered(),
+        "{} drops an event before it is written",
+        dispatcher.id()
+    );
+}
@@ -0,0 +1,562 @@
+//! A minimal HTTP API to inspect and manage the running pipeline.
+
+use std::{io, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{info, warn};
+use wlf_core::event_router::{self, ComponentStatus, EventRouter, EventRouterApi, Tap, TapOptions};
+
+/// Longest request head that is accepted, requests have no body.
+const MAX_HEAD_LEN: usize = 8 * 1024;
+
+/// How long a client may take to send the request head.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AdminConfig {
+    /// The address the admin API listens on.
+    #[serde(default = "default_address")]
+    pub(crate) address: String,
+    /// The pipeline is reported unhealthy once a component has more events queued.
+    #[serde(
+        default = "default_max_queue_depth",
+        deserialize_with = "utils::number"
+    )]
+    pub(crate) max_queue_depth: usize,
+    /// Token the requests that pause, resume or tap a component must send as
+    /// `Authorization: Bearer <token>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) token: Option<String>,
+}
+
+pub(crate) fn default_address() -> String {
+    "127.0.0.1:8686".to_string()
+}
+
+pub(crate) fn default_max_queue_depth() -> usize {
+    10_000
+}
+
+/// Serves the admin API:
+/// - `GET /components` lists the components with their kind, status, queue depth and position
+/// - `GET /components/<id>` shows one of them
+/// - `POST /components/<id>/pause` and `POST /components/<id>/resume` hold and release it
+/// - `GET /metrics` exports the metrics in the Prometheus text format
+/// - `GET /healthz` fails once a component is dead or has too many events queued
+/// - `GET /readyz` fails until every component is running, and connected if it connects
+/// - `GET /components/<id>/tap` streams copies of the events sent to it as JSON lines, see
+///   [`tap_options`] for the query
+///
+/// The pause, resume and tap requests need the token of the config, if any.
+pub(crate) async fn serve(listener: TcpListener, router: Arc<EventRouter>, config: AdminConfig) {
+    if config.token.is_none() && matches!(listener.local_addr(), Ok(a) if !a.ip().is_loopback()) {
+        warn!(
+            "admin API on {} has no token, anyone reaching it can pause and tap components",
+            config.address
+        );
+    }
+    let config = Arc::new(config);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle(stream, Arc::clone(&router), Arc::clone(&config)));
+            }
+            Err(e) => warn!("admin API failed to accept a connection, {e}"),
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, router: Arc<EventRouter>, config: Arc<AdminConfig>) {
+    let response = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(Some(request))) => {
+            info!("admin API request {} {}", request.method, request.path);
+            match route(&request, &router, &config) {
+                Reply::Response(response) => response,
+                Reply::Tap(tap, limit) => {
+                    if let Err(e) = stream_tap(&mut stream, tap, limit).await {
+                        info!("admin API tap is closed, {e}");
+                    }
+                    return;
+                }
+            }
+        }
+        Ok(Ok(None)) => Response::json(400, json!({ "error": "malformed request" })),
+        Ok(Err(e)) => {
+            warn!("admin API failed to read a request, {e}");
+            return;
+        }
+        Err(_) => Response::json(408, json!({ "error": "the request took too long" })),
+    };
+    if let Err(e) = response.write(&mut stream).await {
+        warn!("admin API failed to respond, {e}");
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    /// The bearer token of the `Authorization` header
+    token: Option<String>,
+}
+
+/// Reads the head of the request, returns none if it is not HTTP.
+async fn read_request(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD_LEN {
+            return Ok(None);
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+    let token = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+        .and_then(|(_, value)| value.trim().strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        token,
+    }))
+}
+
+enum Reply {
+    Response(Response),
+    /// The copies of events are streamed until the limit, if any
+    Tap(Tap, Option<usize>),
+}
+
+/// Whether the given token matches the configured one, if any.
+///
+/// The bytes are compared in constant time so the response time doesn't tell how much of a guess
+/// is right.
+fn authorized(given: Option<&str>, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    let Some(given) = given else {
+        return false;
+    };
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn route(request: &Request, router: &EventRouter, config: &AdminConfig) -> Reply {
+    let method = request.method.as_str();
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let managing = matches!(
+        (method, segments.as_slice()),
+        ("POST", ["components", _, "pause" | "resume"]) | ("GET", ["components", _, "tap"])
+    );
+    if managing && !authorized(request.token.as_deref(), config.token.as_deref()) {
+        let error = "a valid bearer token is required";
+        return Reply::Response(Response::json(401, json!({ "error": error })));
+    }
+    if let ("GET", ["components", id, "tap"]) = (method, segments.as_slice()) {
+        return match tap(router, id, query) {
+            Ok((tap, limit)) => Reply::Tap(tap, limit),
+            Err(response) => Reply::Response(response),
+        };
+    }
+    Reply::Response(match (method, segments.as_slice()) {
+        ("GET", ["healthz"]) => health(router, config),
+        ("GET", ["readyz"]) => readiness(router),
+        ("GET", ["metrics"]) => {
+            let metrics = router.metrics().render(&router.components());
+            Response::text(200, "text/plain; version=0.0.4", metrics)
+        }
+        ("GET", ["components"]) => Response::json(200, json!(router.components())),
+        ("GET", ["components", id]) => component(router, id),
+        ("POST", ["components", id, "pause"]) => match router.pause(id) {
+            Ok(()) => component(router, id),
+            Err(e) => Response::json(404, json!({ "error": e.to_string() })),
+        },
+        ("POST", ["components", id, "resume"]) => match router.resume(id) {
+            Ok(()) => component(router, id),
+            Err(e) => Response::json(404, json!({ "error": e.to_string() })),
+        },
+        _ => Response::json(404, json!({ "error": format!("no route {method} {path}") })),
+    })
+}
+
+fn component(router: &EventRouter, id: &str) -> Response {
+    match router.components().into_iter().find(|c| c.id == id) {
+        Some(info) => Response::json(200, json!(info)),
+        None => Response::json(404, json!({ "error": format!("no such component {id}") })),
+    }
+}
+
+fn tap(router: &EventRouter, id: &str, query: &str) -> Result<(Tap, Option<usize>), Response> {
+    let (options, limit) =
+        tap_options(query).map_err(|e| Response::json(400, json!({ "error": e })))?;
+    match router.tap(id, options) {
+        Ok(tap) => Ok((tap, limit)),
+        Err(e @ event_router::Error::NoSuchComponent(_)) => {
+            Err(Response::json(404, json!({ "error": e.to_string() })))
+        }
+        Err(e) => Err(Response::json(400, json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Parses the query of a tap, all the parameters are optional:
+/// - `sample=<n>` copies one of every n events
+/// - `where=<pointer>=<value>` copies only the events whose field at the JSON pointer has the
+///   value, a JSON value or else a string, it can be repeated
+/// - `limit=<n>` closes the tap after n events
+pub(crate) fn tap_options(query: &str) -> Result<(TapOptions, Option<usize>), String> {
+    let mut options = TapOptions::default();
+    let mut limit = None;
+    for param in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+        let value = decode(value);
+        match key {
+            "sample" => match value.parse() {
+                Ok(sample) if sample > 0 => options.sample = sample,
+                _ => {
+                    return Err(format!(
+                        "invalid sample {value}, expected a positive number"
+                    ))
+                }
+            },
+            "limit" => {
+                let n = value
+                    .parse()
+                    .map_err(|_| format!("invalid limit {value}"))?;
+                limit = Some(n);
+            }
+            "where" => {
+                let Some((pointer, expected)) = value.split_once('=') else {
+                    return Err(format!("invalid where {value}, expected <pointer>=<value>"));
+                };
+                if !pointer.starts_with('/') {
+                    return Err(format!("invalid JSON pointer {pointer}"));
+                }
+                let expected = serde_json::from_str(expected)
+                    .unwrap_or_else(|_| Value::String(expected.to_string()));
+                options.fields.push((pointer.to_string(), expected));
+            }
+            _ => return Err(format!("unknown tap parameter {key}")),
+        }
+    }
+    Ok((options, limit))
+}
+
+/// Decodes a percent-encoded query value.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = value.get(i + 1..i + 3);
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 2;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+async fn stream_tap(stream: &mut TcpStream, tap: Tap, limit: Option<usize>) -> io::Result<()> {
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n";
+    stream.write_all(head.as_bytes()).await?;
+    let mut sent = 0;
+    let mut buf = [0; 64];
+    while limit != Some(sent) {
+        // the client sends nothing more, a read only ends when it closes the connection
+        let event = tokio::select! {
+            event = tap.next() => event,
+            read = stream.read(&mut buf) => match read {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(_) => continue,
+            },
+        };
+        let Some(event) = event else {
+            break;
+        };
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+        stream.write_all(&line).await?;
+        sent += 1;
+    }
+    stream.shutdown().await
+}
+
+fn health(router: &EventRouter, config: &AdminConfig) -> Response {
+    let mut problems = Vec::new();
+    for component in router.components() {
+        let id = &component.id;
+        match component.status {
+            ComponentStatus::Failed => problems.push(format!("{id} has failed")),
+            ComponentStatus::Stopped => problems.push(format!("{id} has stopped")),
+            _ => {}
+        }
+        match component.queue_depth {
+            Some(depth) if depth > config.max_queue_depth => problems.push(format!(
+                "{id} has {depth} events queued, more than {}",
+                config.max_queue_depth
+            )),
+            _ => {}
+        }
+    }
+    let status = if problems.is_empty() { 200 } else { 503 };
+    Response::json(
+        status,
+        json!({ "healthy": problems.is_empty(), "problems": problems }),
+    )
+}
+
+fn readiness(router: &EventRouter) -> Response {
+    let waiting: Vec<String> = router
+        .components()
+        .into_iter()
+        .filter(|c| !c.ready)
+        .map(|c| c.id)
+        .collect();
+    let status = if waiting.is_empty() { 200 } else { 503 };
+    Response::json(
+        status,
+        json!({ "ready": waiting.is_empty(), "waiting": waiting }),
+    )
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    fn text(status: u16, content_type: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    async fn write(&self, stream: &mut TcpStream) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            408 => "Request Timeout",
+            503 => "Service Unavailable",
+            _ => "",
+        };
+        let head = format!(
+            "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(self.body.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::test_utils::DummyComponent;
+    use wlf_core::ComponentKind;
+
+    use super::*;
+
+    async fn request(address: std::net::SocketAddr, method: &str, path: &str) -> String {
+        request_with(address, method, path, "").await
+    }
+
+    async fn request_with(
+        address: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &str,
+    ) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    fn body(response: &str) -> serde_json::Value {
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn manage_components() {
+        let router = Arc::new(EventRouter::new());
+        router.register_component(&DummyComponent::new("kafka", ComponentKind::Dispatcher));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = AdminConfig {
+            address: address.to_string(),
+            max_queue_depth: default_max_queue_depth(),
+            token: None,
+        };
+        tokio::spawn(serve(listener, Arc::clone(&router), config));
+
+        let response = request(address, "GET", "/components").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(
+            body(&response),
+            json!([{
+                "id": "kafka",
+                "kind": "Dispatcher",
+                "status": "Starting",
+                "ready": false,
+                "paused": false,
+                "queue_depth": 0,
+                "position": null,
+            }])
+        );
+
+        let response = request(address, "POST", "/components/kafka/pause").await;
+        assert_eq!(body(&response)["paused"], json!(true));
+        assert!(router.components()[0].paused);
+
+        let response = request(address, "POST", "/components/redis/resume").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+
+        let response = request(address, "GET", "/readyz").await;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert_eq!(body(&response)["waiting"], json!(["kafka"]));
+        router.set_ready("kafka", true);
+        let response = request(address, "GET", "/readyz").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        let response = request(address, "GET", "/healthz").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        router.set_status("kafka", ComponentStatus::Failed);
+        let response = request(address, "GET", "/healthz").await;
+        assert_eq!(body(&response)["problems"], json!(["kafka has failed"]));
+
+        let response = request(address, "GET", "/metrics").await;
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(response.contains("wlf_router_queue_length{component=\"kafka\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn require_token() {
+        let router = Arc::new(EventRouter::new());
+        router.register_component(&DummyComponent::new("kafka", ComponentKind::Dispatcher));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = AdminConfig {
+            address: address.to_string(),
+            max_queue_depth: default_max_queue_depth(),
+            token: Some("secret".to_string()),
+        };
+        tokio::spawn(serve(listener, Arc::clone(&router), config));
+
+        let response = request(address, "POST", "/components/kafka/pause").await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+        let response = request_with(
+            address,
+            "POST",
+            "/components/kafka/pause",
+            "Authorization: Bearer wrong\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+        let response = request_with(
+            address,
+            "POST",
+            "/components/kafka/pause",
+            "Authorization: Bearer secreT\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+        assert!(!router.components()[0].paused);
+
+        let response = request_with(
+            address,
+            "POST",
+            "/components/kafka/pause",
+            "authorization: Bearer secret\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(router.components()[0].paused);
+
+        // reading needs no token
+        let response = request(address, "GET", "/components").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn time_out_slow_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = AdminConfig {
+            address: address.to_string(),
+            max_queue_depth: default_max_queue_depth(),
+            token: None,
+        };
+        tokio::spawn(serve(listener, Arc::new(EventRouter::new()), config));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /components HTTP/1.1\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+    }
+
+    #[test]
+    fn parse_tap_query() {
+        let (options, limit) =
+            tap_options("sample=10&where=%2Ftable%3Dorders&where=/n=1&limit=5").unwrap();
+        assert_eq!(options.sample, 10);
+        assert_eq!(
+            options.fields,
+            vec![
+                ("/table".to_string(), json!("orders")),
+                ("/n".to_string(), json!(1)),
+            ]
+        );
+        assert_eq!(limit, Some(5));
+
+        assert!(tap_options("sample=0").is_err());
+        assert!(tap_options("where=table").is_err());
+    }
+}
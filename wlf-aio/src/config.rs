@@ -1,37 +1,118 @@
-use serde::Deserialize;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
 use wlf_binlog_collector::BinlogCollector;
 use wlf_binlog_filter::BinlogFilter;
-use wlf_core::ComponentApi;
+use wlf_core::{ComponentApi, ComponentKind};
 use wlf_elasticsearch_dispatcher::ElasticsearchDispatcher;
 use wlf_event_replicator::EventReplicator;
+use wlf_file_dispatcher::FileDispatcher;
 use wlf_kafka_dispatcher::KafkaDispatcher;
+use wlf_redis_collector::RedisCollector;
 use wlf_redis_dispatcher::RedisDispatcher;
 
-#[derive(Deserialize, Debug, Default)]
+use crate::{admin::AdminConfig, supervisor::Supervised};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct Config {
+    /// Config files or directories whose components are added, relative to this file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) include: Vec<PathBuf>,
     #[serde(default)]
-    pub(crate) collectors: Vec<Collector>,
+    pub(crate) collectors: Vec<Supervised<Collector>>,
     #[serde(default)]
-    pub(crate) transformers: Vec<Transformer>,
+    pub(crate) transformers: Vec<Supervised<Transformer>>,
     #[serde(default)]
-    pub(crate) dispatchers: Vec<Dispatcher>,
+    pub(crate) dispatchers: Vec<Supervised<Dispatcher>>,
+    /// How long transformers and dispatchers may take to drain their queues on shutdown.
+    #[serde(
+        default = "default_shutdown_timeout_secs",
+        deserialize_with = "utils::number"
+    )]
+    pub(crate) shutdown_timeout_secs: u64,
+    /// Serve the HTTP admin API, it is started with the pipeline and not reloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) admin: Option<AdminConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            collectors: Vec::new(),
+            transformers: Vec::new(),
+            dispatchers: Vec::new(),
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
+            admin: None,
+        }
+    }
+}
+
+impl Config {
+    /// Id, kind and destinations of every component, in the order they are configured.
+    pub(crate) fn components(&self) -> Vec<(&str, ComponentKind, Vec<&str>)> {
+        let mut components = Vec::new();
+        for c in self.collectors.iter().map(|c| &c.component) {
+            components.push((
+                c.as_component().id(),
+                ComponentKind::Collector,
+                c.destinations(),
+            ));
+        }
+        for t in self.transformers.iter().map(|t| &t.component) {
+            components.push((
+                t.as_component().id(),
+                ComponentKind::Transformer,
+                t.destinations(),
+            ));
+        }
+        for d in self.dispatchers.iter().map(|d| &d.component) {
+            components.push((
+                d.as_component().id(),
+                ComponentKind::Dispatcher,
+                d.destinations(),
+            ));
+        }
+        components
+    }
 }
 
-#[derive(Deserialize, Debug)]
+pub(crate) fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub(crate) enum Collector {
     Binlog(BinlogCollector),
+    Redis(RedisCollector),
 }
 
 impl Collector {
+    pub(crate) fn into_component(self) -> Box<dyn ComponentApi> {
+        match self {
+            Collector::Binlog(c) => Box::new(c),
+            Collector::Redis(c) => Box::new(c),
+        }
+    }
+
     pub(crate) fn as_component(&self) -> &dyn ComponentApi {
         match self {
             Collector::Binlog(c) => c,
+            Collector::Redis(c) => c,
+        }
+    }
+
+    /// Ids of the components the collector sends events to.
+    pub(crate) fn destinations(&self) -> Vec<&str> {
+        match self {
+            Collector::Binlog(c) => vec![c.destination.as_str()],
+            Collector::Redis(c) => vec![c.destination.as_str()],
         }
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub(crate) enum Transformer {
     BinlogFilter(BinlogFilter),
@@ -39,28 +120,75 @@ pub(crate) enum Transformer {
 }
 
 impl Transformer {
+    pub(crate) fn into_component(self) -> Box<dyn ComponentApi> {
+        match self {
+            Transformer::BinlogFilter(t) => Box::new(t),
+            Transformer::EventReplicator(t) => Box::new(t),
+        }
+    }
+
     pub(crate) fn as_component(&self) -> &dyn ComponentApi {
         match self {
             Transformer::BinlogFilter(t) => t,
             Transformer::EventReplicator(t) => t,
         }
     }
+
+    /// Ids of the components the transformer sends events to.
+    pub(crate) fn destinations(&self) -> Vec<&str> {
+        match self {
+            Transformer::BinlogFilter(t) => vec![t.destination.as_str()],
+            Transformer::EventReplicator(t) => {
+                t.destinations().iter().map(String::as_str).collect()
+            }
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub(crate) enum Dispatcher {
     Kafka(KafkaDispatcher),
     Redis(RedisDispatcher),
     Elasticsearch(ElasticsearchDispatcher),
+    File(FileDispatcher),
 }
 
 impl Dispatcher {
+    pub(crate) fn into_component(self) -> Box<dyn ComponentApi> {
+        match self {
+            Dispatcher::Kafka(d) => Box::new(d),
+            Dispatcher::Redis(d) => Box::new(d),
+            Dispatcher::Elasticsearch(d) => Box::new(d),
+            Dispatcher::File(d) => Box::new(d),
+        }
+    }
+
     pub(crate) fn as_component(&self) -> &dyn ComponentApi {
         match self {
             Dispatcher::Kafka(d) => d,
             Dispatcher::Redis(d) => d,
             Dispatcher::Elasticsearch(d) => d,
+            Dispatcher::File(d) => d,
+        }
+    }
+
+    /// Rejects settings of the dispatcher that can't work together.
+    pub(crate) fn check(&self) -> Result<(), String> {
+        match self {
+            Dispatcher::Redis(d) => d.check().map_err(|e| e.to_string()),
+            Dispatcher::Kafka(_) | Dispatcher::Elasticsearch(_) | Dispatcher::File(_) => Ok(()),
         }
     }
+
+    /// Ids of the components the dispatcher sends events to.
+    pub(crate) fn destinations(&self) -> Vec<&str> {
+        let dead_letter = match self {
+            Dispatcher::Kafka(d) => &d.dead_letter,
+            Dispatcher::Redis(d) => &d.dead_letter,
+            Dispatcher::Elasticsearch(d) => &d.dead_letter,
+            Dispatcher::File(_) => return Vec::new(),
+        };
+        dead_letter.iter().map(String::as_str).collect()
+    }
 }
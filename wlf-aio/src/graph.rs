@@ -0,0 +1,117 @@
+use std::fmt::Write;
+
+use clap::ValueEnum;
+use wlf_core::ComponentKind;
+
+use crate::config::Config;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+    Mermaid,
+}
+
+/// Renders the pipeline, dispatchers sending events to their dead-letter component are dashed.
+pub(crate) fn render(config: &Config, format: GraphFormat) -> String {
+    let components = config.components();
+    let mut out = String::new();
+    match format {
+        GraphFormat::Dot => {
+            out.push_str("digraph wlf {\n    rankdir=LR;\n");
+            for (id, kind, _) in &components {
+                let shape = match kind {
+                    ComponentKind::Collector => "invhouse",
+                    ComponentKind::Transformer => "box",
+                    ComponentKind::Dispatcher => "house",
+                };
+                writeln!(out, "    \"{id}\" [shape={shape}];").unwrap();
+            }
+            for (from, kind, destinations) in &components {
+                for to in destinations {
+                    let style = match kind {
+                        ComponentKind::Dispatcher => " [style=dashed, label=\"dead letter\"]",
+                        _ => "",
+                    };
+                    writeln!(out, "    \"{from}\" -> \"{to}\"{style};").unwrap();
+                }
+            }
+            out.push_str("}\n");
+        }
+        GraphFormat::Mermaid => {
+            // ids may contain characters mermaid doesn't accept, nodes are numbered instead
+            let node = |id: &str| {
+                let index = components.iter().position(|(i, _, _)| *i == id);
+                format!("n{}", index.map_or(id.to_string(), |i| i.to_string()))
+            };
+            out.push_str("flowchart LR\n");
+            for (id, kind, _) in &components {
+                let (open, close) = match kind {
+                    ComponentKind::Collector => ("[/", "/]"),
+                    ComponentKind::Transformer => ("[", "]"),
+                    ComponentKind::Dispatcher => ("[\\", "\\]"),
+                };
+                writeln!(out, "    {}{open}\"{id}\"{close}", node(id)).unwrap();
+            }
+            for (from, kind, destinations) in &components {
+                for to in destinations {
+                    let arrow = match kind {
+                        ComponentKind::Dispatcher => "-. dead letter .->",
+                        _ => "-->",
+                    };
+                    writeln!(out, "    {} {arrow} {}", node(from), node(to)).unwrap();
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_graphs() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+collectors:
+  - id: binlog
+    type: Binlog
+    destination: kafka
+    user: root
+dispatchers:
+  - id: kafka
+    type: Kafka
+    bootstrap_brokers: ["localhost:9092"]
+    dead_letter: redis
+  - id: redis
+    type: Redis
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            render(&config, GraphFormat::Dot),
+            r#"digraph wlf {
+    rankdir=LR;
+    "binlog" [shape=invhouse];
+    "kafka" [shape=house];
+    "redis" [shape=house];
+    "binlog" -> "kafka";
+    "kafka" -> "redis" [style=dashed, label="dead letter"];
+}
+"#
+        );
+        assert_eq!(
+            render(&config, GraphFormat::Mermaid),
+            r#"flowchart LR
+    n0[/"binlog"/]
+    n1[\"kafka"\]
+    n2[\"redis"\]
+    n0 --> n1
+    n1 -. dead letter .-> n2
+"#
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::{env, fs};
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum Error {
+    #[error("environment variable {0} is not set")]
+    MissingVariable(String),
+    #[error("failed to read secret file {path}, {error}")]
+    SecretFile { path: String, error: String },
+    #[error("missing }} after ${{{0}")]
+    Unterminated(String),
+}
+
+/// Replaces the placeholders in the string values of a YAML config, the text of the values is
+/// never parsed again. The values stay strings, numeric fields accept numbers in strings.
+pub(crate) fn interpolate_yaml(value: &mut serde_yaml::Value) -> Result<(), Error> {
+    use serde_yaml::Value;
+    match value {
+        Value::String(s) => *s = interpolate(s)?,
+        Value::Sequence(values) => values.iter_mut().try_for_each(interpolate_yaml)?,
+        Value::Mapping(map) => map.values_mut().try_for_each(interpolate_yaml)?,
+        Value::Tagged(tagged) => interpolate_yaml(&mut tagged.value)?,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+/// Replaces the placeholders in the string values of a TOML config.
+pub(crate) fn interpolate_toml(value: &mut toml::Value) -> Result<(), Error> {
+    use toml::Value;
+    match value {
+        Value::String(s) => *s = interpolate(s)?,
+        Value::Array(values) => values.iter_mut().try_for_each(interpolate_toml)?,
+        Value::Table(table) => table
+            .iter_mut()
+            .try_for_each(|(_, value)| interpolate_toml(value))?,
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) | Value::Datetime(_) => {}
+    }
+    Ok(())
+}
+
+/// Replaces the placeholders in the string values of a JSON config.
+pub(crate) fn interpolate_json(value: &mut serde_json::Value) -> Result<(), Error> {
+    use serde_json::Value;
+    match value {
+        Value::String(s) => *s = interpolate(s)?,
+        Value::Array(values) => values.iter_mut().try_for_each(interpolate_json)?,
+        Value::Object(map) => map.values_mut().try_for_each(interpolate_json)?,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+/// Replaces `${ENV_VAR}`, `${ENV_VAR:-default}` and `${file:/path}` in a config string, `$${` is
+/// kept as a literal `${`. The content of a secret file is used without its trailing newline.
+pub(crate) fn interpolate(text: &str) -> Result<String, Error> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find('}') else {
+            return Err(Error::Unterminated(
+                rest.lines().next().unwrap_or_default().to_string(),
+            ));
+        };
+        out.push_str(&resolve(&rest[..end])?);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn resolve(expression: &str) -> Result<String, Error> {
+    if let Some(path) = expression.strip_prefix("file:") {
+        let content = fs::read_to_string(path).map_err(|e| Error::SecretFile {
+            path: path.to_string(),
+            error: e.to_string(),
+        })?;
+        return Ok(content.trim_end_matches(['\n', '\r']).to_string());
+    }
+
+    let (name, default) = match expression.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (expression, None),
+    };
+    // like the shell, an empty variable takes the default too
+    match (env::var(name), default) {
+        (Ok(value), Some(default)) if value.is_empty() => Ok(default.to_string()),
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(default)) => Ok(default.to_string()),
+        (Err(_), None) => Err(Error::MissingVariable(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolate_config() {
+        env::set_var("WLF_TEST_PASSWORD", "secret");
+        let secret_file = env::temp_dir().join(format!("wlf-secret-{}", std::process::id()));
+        fs::write(&secret_file, "token\n").unwrap();
+
+        let text = format!(
+            "password: ${{WLF_TEST_PASSWORD}}\nport: ${{WLF_TEST_UNSET:-6379}}\nauth: ${{file:{}}}\ntopic: $${{literal}}.%{{/table}}",
+            secret_file.display()
+        );
+        assert_eq!(
+            interpolate(&text).unwrap(),
+            "password: secret\nport: 6379\nauth: token\ntopic: ${literal}.%{/table}"
+        );
+        fs::remove_file(secret_file).unwrap();
+
+        assert_eq!(
+            interpolate("password: ${WLF_TEST_UNSET}"),
+            Err(Error::MissingVariable("WLF_TEST_UNSET".to_string()))
+        );
+        assert_eq!(
+            interpolate("password: ${WLF_TEST_PASSWORD"),
+            Err(Error::Unterminated("WLF_TEST_PASSWORD".to_string()))
+        );
+    }
+
+    #[test]
+    fn interpolate_values() {
+        env::set_var("WLF_TEST_QUOTED", "a\"b\nc: d");
+        env::set_var("WLF_TEST_PORT", "6379");
+
+        let mut yaml: serde_yaml::Value = serde_yaml::from_str(
+            "password: ${WLF_TEST_QUOTED}\nport: ${WLF_TEST_PORT}\nkey: k-${WLF_TEST_PORT}",
+        )
+        .unwrap();
+        interpolate_yaml(&mut yaml).unwrap();
+        assert_eq!(yaml["password"], serde_yaml::Value::from("a\"b\nc: d"));
+        // numeric fields parse the string
+        assert_eq!(yaml["port"], serde_yaml::Value::from("6379"));
+        assert_eq!(yaml["key"], serde_yaml::Value::from("k-6379"));
+
+        let mut toml: toml::Value = toml::from_str("port = \"${WLF_TEST_PORT}\"").unwrap();
+        interpolate_toml(&mut toml).unwrap();
+        assert_eq!(toml["port"], toml::Value::String("6379".to_string()));
+
+        let mut json = serde_json::json!({ "hosts": ["${WLF_TEST_QUOTED}"] });
+        interpolate_json(&mut json).unwrap();
+        assert_eq!(json, serde_json::json!({ "hosts": ["a\"b\nc: d"] }));
+    }
+}
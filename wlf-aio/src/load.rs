@@ -0,0 +1,289 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    config::{default_shutdown_timeout_secs, Config},
+    interpolate, maxwell,
+};
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("failed to read {0}, {1}")]
+    Io(PathBuf, io::Error),
+    #[error("failed to parse {0}, {1}")]
+    Parse(PathBuf, String),
+    #[error("failed to interpolate {0}, {1}")]
+    Interpolate(PathBuf, interpolate::Error),
+    #[error("failed to convert the maxwell config {0}, {1}")]
+    Maxwell(PathBuf, maxwell::Error),
+    #[error("can't recognize the config format of {0}")]
+    UnknownFormat(PathBuf),
+    #[error("{0} includes itself")]
+    IncludeCycle(PathBuf),
+    #[error("{0} sets {1} differently from another config file")]
+    Conflict(PathBuf, &'static str),
+}
+
+/// Loads a config file, or every config file of a directory, in the order of their names. The
+/// components of the files listed under `include` are added, their paths are relative to the
+/// including file. A file is only loaded once, and the other settings set in several files must
+/// agree. The placeholders of the string values are replaced if `interpolate` is set.
+pub(crate) fn load_config(path: &Path, interpolate: bool) -> Result<Config, Error> {
+    let mut loader = Loader {
+        interpolate,
+        including: Vec::new(),
+        loaded: HashSet::new(),
+    };
+    loader.load(path)
+}
+
+struct Loader {
+    interpolate: bool,
+    /// The files and directories being loaded, to detect include cycles
+    including: Vec<PathBuf>,
+    /// The files already loaded
+    loaded: HashSet<PathBuf>,
+}
+
+impl Loader {
+    fn load(&mut self, path: &Path) -> Result<Config, Error> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        if self.including.contains(&canonical) {
+            return Err(Error::IncludeCycle(path.to_path_buf()));
+        }
+
+        if path.is_dir() {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(path).map_err(|e| Error::Io(path.to_path_buf(), e))? {
+                let file = entry.map_err(|e| Error::Io(path.to_path_buf(), e))?.path();
+                if file.is_file() && format(&file).is_some() {
+                    files.push(file);
+                }
+            }
+            files.sort();
+
+            self.including.push(canonical);
+            let mut config = Config::default();
+            for file in files {
+                let other = self.load(&file)?;
+                merge(&mut config, other, &file)?;
+            }
+            self.including.pop();
+            return Ok(config);
+        }
+
+        // e.g. a fragment both included and in an included directory
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(Config::default());
+        }
+        let mut config = parse(path, self.interpolate)?;
+        self.including.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for include in std::mem::take(&mut config.include) {
+            let include = dir.join(include);
+            let other = self.load(&include)?;
+            merge(&mut config, other, &include)?;
+        }
+        self.including.pop();
+        Ok(config)
+    }
+}
+
+/// Adds the components of `other`, loaded from `path`. Its other settings are taken unless they
+/// are left to their default, and must be the same if `config` sets them too.
+fn merge(config: &mut Config, other: Config, path: &Path) -> Result<(), Error> {
+    let conflict = |setting| Error::Conflict(path.to_path_buf(), setting);
+    let default_timeout = default_shutdown_timeout_secs();
+    match (config.shutdown_timeout_secs, other.shutdown_timeout_secs) {
+        (_, timeout) if timeout == default_timeout => {}
+        (current, timeout) if current == default_timeout || current == timeout => {
+            config.shutdown_timeout_secs = timeout
+        }
+        _ => return Err(conflict("shutdown_timeout_secs")),
+    }
+    match (&config.admin, other.admin) {
+        (_, None) => {}
+        (None, admin) => config.admin = admin,
+        (Some(current), Some(admin)) if *current == admin => {}
+        _ => return Err(conflict("admin")),
+    }
+
+    config.collectors.extend(other.collectors);
+    config.transformers.extend(other.transformers);
+    config.dispatchers.extend(other.dispatchers);
+    Ok(())
+}
+
+fn format(path: &Path) -> Option<&str> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .filter(|e| ["yaml", "yml", "toml", "json", "properties"].contains(e))
+}
+
+/// Parses a config file. The placeholders are replaced in the parsed string values rather than
+/// in the text, so that a value can't change the structure of the config.
+fn parse(path: &Path, interpolate: bool) -> Result<Config, Error> {
+    let Some(format) = format(path) else {
+        return Err(Error::UnknownFormat(path.to_path_buf()));
+    };
+    let text = fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+    // parsed straight from the text otherwise, for the locations in the errors
+    let interpolate = interpolate && text.contains("${");
+    let parse_error = |e: String| Error::Parse(path.to_path_buf(), e);
+    let interpolate_error = |e| Error::Interpolate(path.to_path_buf(), e);
+    match format {
+        "toml" if interpolate => {
+            let mut value: toml::Value =
+                toml::from_str(&text).map_err(|e| parse_error(e.to_string()))?;
+            interpolate::interpolate_toml(&mut value).map_err(interpolate_error)?;
+            value.try_into().map_err(|e| parse_error(e.to_string()))
+        }
+        "toml" => toml::from_str(&text).map_err(|e| parse_error(e.to_string())),
+        "json" if interpolate => {
+            let mut value: serde_json::Value =
+                serde_json::from_str(&text).map_err(|e| parse_error(e.to_string()))?;
+            interpolate::interpolate_json(&mut value).map_err(interpolate_error)?;
+            serde_json::from_value(value).map_err(|e| parse_error(e.to_string()))
+        }
+        "json" => serde_json::from_str(&text).map_err(|e| parse_error(e.to_string())),
+        "properties" => {
+            let mut properties =
+                java_properties::read(text.as_bytes()).map_err(|e| parse_error(e.to_string()))?;
+            if interpolate {
+                for value in properties.values_mut() {
+                    *value = interpolate::interpolate(value).map_err(interpolate_error)?;
+                }
+            }
+            maxwell::convert(properties).map_err(|e| Error::Maxwell(path.to_path_buf(), e))
+        }
+        _ if interpolate => {
+            let mut value: serde_yaml::Value =
+                serde_yaml::from_str(&text).map_err(|e| parse_error(e.to_string()))?;
+            interpolate::interpolate_yaml(&mut value).map_err(interpolate_error)?;
+            serde_yaml::from_value(value).map_err(|e| parse_error(e.to_string()))
+        }
+        _ => serde_yaml::from_str(&text).map_err(|e| parse_error(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Dispatcher;
+
+    #[test]
+    fn load_with_includes() {
+        let dir = std::env::temp_dir().join(format!("wlf-load-{}", std::process::id()));
+        fs::create_dir_all(dir.join("fragments")).unwrap();
+        fs::write(
+            dir.join("main.yaml"),
+            r#"
+include: [fragments, fragments/b.json]
+shutdown_timeout_secs: 10
+collectors:
+  - id: binlog
+    type: Binlog
+    destination: filter
+    user: root
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("fragments/a.toml"),
+            r#"
+[admin]
+address = "0.0.0.0:8686"
+
+[[transformers]]
+id = "filter"
+type = "BinlogFilter"
+destination = "kafka"
+rules = []
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("fragments/b.json"),
+            r#"{ "dispatchers": [{ "id": "kafka", "type": "Kafka", "bootstrap_brokers": [] }] }"#,
+        )
+        .unwrap();
+        fs::write(dir.join("fragments/README.md"), "not a config").unwrap();
+
+        let config = load_config(&dir.join("main.yaml"), true).unwrap();
+        assert_eq!(config.shutdown_timeout_secs, 10);
+        assert_eq!(config.admin.as_ref().unwrap().address, "0.0.0.0:8686");
+        let ids: Vec<_> = config.components().into_iter().map(|(id, ..)| id).collect();
+        assert_eq!(ids, vec!["binlog", "filter", "kafka"]);
+
+        fs::write(dir.join("fragments/c.yaml"), "shutdown_timeout_secs: 20").unwrap();
+        assert!(matches!(
+            load_config(&dir.join("main.yaml"), true),
+            Err(Error::Conflict(_, "shutdown_timeout_secs"))
+        ));
+
+        fs::write(dir.join("fragments/c.yaml"), "include: [../main.yaml]").unwrap();
+        assert!(matches!(
+            load_config(&dir.join("main.yaml"), true),
+            Err(Error::IncludeCycle(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn interpolate_secrets() {
+        std::env::set_var("WLF_TEST_NUMERIC_SECRET", "123456");
+        std::env::set_var("WLF_TEST_REDIS_PORT", "6380");
+        let dir = std::env::temp_dir().join(format!("wlf-secrets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("redis.yaml"),
+            r#"
+shutdown_timeout_secs: ${WLF_TEST_UNSET:-15}
+dispatchers:
+  - id: redis
+    type: Redis
+    auth: ${WLF_TEST_NUMERIC_SECRET}
+    port: ${WLF_TEST_REDIS_PORT}
+    mode:
+      type: RPush
+      key: ${WLF_TEST_UNSET:-true}
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("redis.toml"),
+            r#"
+[[dispatchers]]
+id = "redis"
+type = "Redis"
+auth = "${WLF_TEST_NUMERIC_SECRET}"
+port = "${WLF_TEST_REDIS_PORT}"
+"#,
+        )
+        .unwrap();
+
+        for file in ["redis.yaml", "redis.toml"] {
+            let config = load_config(&dir.join(file), true).unwrap();
+            let Dispatcher::Redis(redis) = &config.dispatchers[0].component else {
+                panic!("not a redis dispatcher");
+            };
+            // the numeric secret stays a string, the port becomes a number
+            assert_eq!(redis.config.auth.as_deref(), Some("123456"));
+            assert_eq!(redis.config.port, 6380);
+        }
+        let config = load_config(&dir.join("redis.yaml"), true).unwrap();
+        assert_eq!(config.shutdown_timeout_secs, 15);
+        let Dispatcher::Redis(redis) = &config.dispatchers[0].component else {
+            panic!("not a redis dispatcher");
+        };
+        assert!(matches!(&redis.mode, wlf_redis_dispatcher::Mode::RPush { key } if key == "true"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
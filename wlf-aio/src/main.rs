@@ -1,225 +1,173 @@
-use std::{collections::HashMap, error::Error, fs::File, io::BufReader, path::PathBuf, sync::Arc};
-
-use clap::{command, Parser};
-use config::Dispatcher;
-use tracing::{error, warn};
-use wlf_binlog_collector::BinlogCollector;
-use wlf_binlog_filter::{BinlogFilter, BinlogFilterRules};
-use wlf_core::event_router::{EventRouter, EventRouterApi};
-use wlf_kafka_dispatcher::{CompressionType, KafkaDispatcher};
-use wlf_redis_dispatcher::RedisDispatcher;
-
-use crate::config::{Collector, Config, Transformer};
-
+use std::{error::Error, path::PathBuf, process::ExitCode};
+
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use graph::GraphFormat;
+use load::load_config;
+use pipeline::Pipeline;
+use reload::reload_requests;
+use shutdown::shutdown_signal;
+use tap::TapArgs;
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+use validate::{validate, EXIT_INVALID_CONFIG};
+
+use crate::config::Config;
+
+mod admin;
 mod config;
+mod graph;
+mod interpolate;
+mod load;
+mod maxwell;
+mod pipeline;
+mod reload;
+mod shutdown;
+mod supervisor;
+mod tap;
+mod validate;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    #[arg(short, long, value_name = "FILE")]
-    config: PathBuf,
+    /// A YAML, TOML, JSON or maxwell `.properties` config, or a directory of them
+    #[arg(short, long, value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the pipeline, the default. The config is reloaded on SIGHUP.
+    Run {
+        /// Also reload the config whenever the file changes
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Parse and check the config without connecting anywhere
+    Validate,
+    /// Print the pipeline as a graph
+    Graph {
+        #[arg(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+    /// Print the YAML equivalent of a maxwell `.properties` config, other configs are not
+    /// supported as their numeric fields can't keep `${...}` placeholders
+    Convert,
+    /// Print copies of the events sent to a component of a running pipeline, through its
+    /// admin API. The config is only needed for the address of the admin API.
+    Tap(TapArgs),
 }
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn Error>> {
-    tracing_subscriber::fmt::init();
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    // stdout is left to the events of the stdout dispatcher
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
 
     let cli = Cli::parse();
-    let config_type = cli
-        .config
-        .extension()
-        .expect("can't recognize config file format");
-    let config: Config = match config_type.to_str().unwrap() {
-        "yaml" => serde_yaml::from_reader(BufReader::new(File::open(cli.config)?))?,
-        "properties" => {
-            let properties = java_properties::read(BufReader::new(File::open(cli.config)?))?;
-            convert_maxwell_java_properties_to_config(properties)
-        }
-        _ => {
-            panic!("can't recognize config file format")
-        }
-    };
-
-    let mut router = EventRouter::new();
-    for c in &config.collectors {
-        router.register_component(c.as_component());
+    if let Some(Command::Tap(args)) = cli.command {
+        let config = cli
+            .config
+            .as_deref()
+            .map(|path| load_config(path, true))
+            .transpose()?;
+        return tap::tap(args, config.and_then(|c| c.admin)).await;
     }
-    for t in &config.transformers {
-        router.register_component(t.as_component());
-    }
-    for d in &config.dispatchers {
-        router.register_component(d.as_component());
-    }
-    let router = Arc::new(router);
-
-    let mut handles = vec![];
-    for c in config.collectors {
-        let router_c = Arc::clone(&router);
-        let handle = tokio::spawn(async move {
-            if let Err(e) = c.as_component().run(router_c).await {
-                error!(e);
-            }
-        });
-        handles.push(handle);
+    let Some(path) = cli.config else {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--config <FILE> is required",
+            )
+            .exit();
+    };
+    // convert prints the config, placeholders are kept rather than resolved to secrets
+    let interpolate = !matches!(cli.command, Some(Command::Convert));
+    if !interpolate && path.extension().and_then(|e| e.to_str()) != Some("properties") {
+        Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "convert only takes a maxwell `.properties` config",
+            )
+            .exit();
     }
-    for t in config.transformers {
-        let router_c = Arc::clone(&router);
-        let handle = tokio::spawn(async move {
-            if let Err(e) = t.as_component().run(router_c).await {
-                error!(e);
+    let config = load_config(&path, interpolate)?;
+
+    match cli.command.unwrap_or(Command::Run { watch: false }) {
+        Command::Run { watch } => run(path, config, watch).await,
+        Command::Validate => match validate(&config) {
+            Ok(()) => {
+                println!("{} is valid", path.display());
+                Ok(ExitCode::SUCCESS)
             }
-        });
-        handles.push(handle);
-    }
-    for d in config.dispatchers {
-        let router_c = Arc::clone(&router);
-        let handle = tokio::spawn(async move {
-            if let Err(e) = d.as_component().run(router_c).await {
-                error!(e);
+            Err(report) => {
+                eprintln!("{report}");
+                Ok(ExitCode::from(EXIT_INVALID_CONFIG))
             }
-        });
-        handles.push(handle);
-    }
-
-    for handle in handles {
-        handle.await.expect("something went wrong");
+        },
+        Command::Graph { format } => {
+            print!("{}", graph::render(&config, format));
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Convert => {
+            // enums as maps instead of YAML tags, the way configs are written
+            let mut serializer = serde_yaml::Serializer::new(std::io::stdout());
+            serde_yaml::with::singleton_map_recursive::serialize(&config, &mut serializer)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Tap(_) => unreachable!("the tap needs no config"),
     }
-
-    Ok(())
 }
 
-fn convert_maxwell_java_properties_to_config(mut properties: HashMap<String, String>) -> Config {
-    let mut config = Config::default();
-
-    fn maxwell_key_to_wlf_format(maxwell: String) -> String {
-        maxwell
-            .replace("%{table}", "%{/table}")
-            .replace("%{database}", "%{/database}")
+async fn run(path: PathBuf, config: Config, watch: bool) -> Result<ExitCode, Box<dyn Error>> {
+    if let Err(report) = validate(&config) {
+        error!("{report}");
+        return Ok(ExitCode::from(EXIT_INVALID_CONFIG));
     }
 
-    // mysql
-    let mut collector = BinlogCollector {
-        id: "collector".to_string(),
-        destination: "filter".to_string(),
-        host: properties
-            .remove("host")
-            .unwrap_or_else(wlf_binlog_collector::default_host),
-        user: properties.remove("user").expect("mysql user not specified"),
-        password: properties.remove("password").unwrap_or_default(),
-        port: properties
-            .remove("port")
-            .map(|port| port.parse().expect("unknown port number"))
-            .unwrap_or_else(wlf_binlog_collector::default_port),
-    };
-
-    // filter
-    if let Some(rules) = properties.remove("filter") {
-        let mut filter = BinlogFilter {
-            id: "filter".to_string(),
-            destination: "dispatcher".to_string(),
-            rules: BinlogFilterRules::new(),
-        };
-        for rule in rules.split(',') {
-            if let Some(dbtb) = rule.strip_prefix("exclude:") {
-                let dbtb: Vec<&str> = dbtb.trim().split('.').collect();
-                let db = dbtb.first().expect("no database in filter");
-                let tb = dbtb.get(1).expect("no table in filter");
-                filter.rules.exclude(*db, *tb);
-            } else if let Some(dbtb) = rule.strip_prefix("include:") {
-                let dbtb: Vec<&str> = dbtb.trim().split('.').collect();
-                let db = dbtb.first().expect("no database in filter");
-                let tb = dbtb.get(1).expect("no table in filter");
-                filter.rules.include(*db, *tb);
-            } else {
-                panic!("filter broken");
-            }
+    let admin = config.admin.clone();
+    // bound before the components start, so that they don't have to be stopped if it fails
+    let listener = match &admin {
+        Some(admin) => {
+            let listener = TcpListener::bind(&admin.address)
+                .await
+                .map_err(|e| format!("failed to bind the admin API to {}, {e}", admin.address))?;
+            info!("admin API listens on {}", admin.address);
+            Some((listener, admin.clone()))
         }
-        config.transformers.push(Transformer::BinlogFilter(filter));
-    } else {
-        collector.destination = "dispatcher".to_string();
+        None => None,
+    };
+    let mut pipeline = Pipeline::start(config);
+    if let Some((listener, admin)) = listener {
+        tokio::spawn(admin::serve(listener, pipeline.router(), admin));
     }
-
-    // producer
-    let dispatcher_type = properties.remove("producer").expect("no producer");
-    let dispatcher = match dispatcher_type.as_str() {
-        "kafka" => {
-            let dispatcher = KafkaDispatcher {
-                id: "dispatcher".to_string(),
-                topic: maxwell_key_to_wlf_format(
-                    properties
-                        .remove("kafka_topic")
-                        .unwrap_or_else(wlf_kafka_dispatcher::default_topic),
-                ),
-                bootstrap_brokers: properties
-                    .remove("kafka.bootstrap.servers")
-                    .expect("no bootstrap_brokers specified")
-                    .split(',')
-                    .map(|s| s.to_string())
-                    .collect(),
-                compression_type: properties
-                    .remove("kafka.compression.type")
-                    .map(|c| match c.as_str() {
-                        "snappy" => CompressionType::Snappy,
-                        "gzip" => CompressionType::Gzip,
-                        _ => panic!("compression_type not supported"),
-                    })
-                    .unwrap_or_default(),
-            };
-            Dispatcher::Kafka(dispatcher)
-        }
-        "redis" => {
-            let redis_key = maxwell_key_to_wlf_format(
-                properties
-                    .remove("redis_key")
-                    .unwrap_or_else(wlf_redis_dispatcher::default_key),
-            );
-            let redis_stream_json_key = properties
-                .remove("redis_stream_json_key")
-                .unwrap_or_else(wlf_redis_dispatcher::default_redis_stream_json_key);
-            let redis_mode = match properties.remove("redis_type").as_deref() {
-                Some("pubsub") => wlf_redis_dispatcher::Mode::Pub { channel: redis_key },
-                Some("xadd") => wlf_redis_dispatcher::Mode::XADD {
-                    key: redis_stream_json_key,
-                },
-                Some("lpush") => wlf_redis_dispatcher::Mode::LPush { key: redis_key },
-                Some("rpush") => wlf_redis_dispatcher::Mode::RPush { key: redis_key },
-                None => wlf_redis_dispatcher::Mode::default(),
-                _ => {
-                    panic!("redis_type not supported");
+    let mut reloads = reload_requests(path.clone(), watch);
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
+    while !pipeline.is_empty() {
+        tokio::select! {
+            _ = &mut signal => break,
+            Some(()) = reloads.recv() => {
+                // a broken config is reported and the pipeline keeps running as it is
+                match load_config(&path, true).map(|config| (validate(&config), config)) {
+                    Ok((Ok(()), config)) => {
+                        if config.admin != admin {
+                            warn!("the admin API settings only apply after a restart");
+                        }
+                        pipeline.reload(config).await
+                    }
+                    Ok((Err(report), _)) => error!("the config is not reloaded, {report}"),
+                    Err(e) => error!("the config is not reloaded, {e}"),
                 }
-            };
-            let redis_config = wlf_redis_dispatcher::Config {
-                host: properties
-                    .remove("redis_host")
-                    .unwrap_or_else(wlf_redis_dispatcher::default_host),
-                port: properties
-                    .remove("redis_port")
-                    .map(|port| port.parse().expect("wrong redis_port"))
-                    .unwrap_or_else(wlf_redis_dispatcher::default_port),
-                auth: properties.remove("redis_auth"),
-                database_number: properties
-                    .remove("redis_database")
-                    .map(|dbn| dbn.parse().expect("wrong redis_database"))
-                    .unwrap_or_else(wlf_redis_dispatcher::default_database_number),
-            };
-            let dispatcher = RedisDispatcher {
-                id: "dispatcher".to_string(),
-                mode: redis_mode,
-                config: redis_config,
-            };
-            Dispatcher::Redis(dispatcher)
-        }
-        _ => {
-            panic!("unsupported producer type");
+            }
+            Some(exit) = pipeline.next_exit() => {
+                if exit.critical {
+                    error!("critical component {} is dead, shutting down the pipeline", exit.id);
+                    break;
+                }
+            }
         }
-    };
-
-    config.collectors.push(Collector::Binlog(collector));
-    config.dispatchers.push(dispatcher);
-
-    for (k, v) in properties {
-        warn!("unrecognized property: {k}={v}");
     }
 
-    config
+    Ok(pipeline.shutdown().await)
 }
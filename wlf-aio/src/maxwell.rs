@@ -0,0 +1,626 @@
+//! Conversion of maxwell's `config.properties`, see https://maxwells-daemon.io/config/
+
+use std::{collections::HashMap, fmt::Display, path::PathBuf, str::FromStr};
+
+use thiserror::Error;
+use tracing::warn;
+use utils::retry::RetryConfig;
+use wlf_binlog_collector::{BinlogCollector, BinlogOutput};
+use wlf_binlog_filter::{BinlogFilter, BinlogFilterRules};
+use wlf_file_dispatcher::FileDispatcher;
+use wlf_kafka_dispatcher::{CompressionType, KafkaDispatcher};
+use wlf_redis_dispatcher::RedisDispatcher;
+
+use crate::{
+    config::{Collector, Config, Dispatcher, Transformer},
+    supervisor::Supervised,
+};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum Error {
+    #[error("{0} is required")]
+    Missing(&'static str),
+    #[error("invalid {key}={value}, {reason}")]
+    Invalid {
+        key: String,
+        value: String,
+        reason: String,
+    },
+    #[error("{key}={value} is not supported")]
+    Unsupported { key: String, value: String },
+}
+
+/// The properties that are not converted yet.
+struct Properties(HashMap<String, String>);
+
+impl Properties {
+    fn take(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    fn required(&mut self, key: &'static str) -> Result<String, Error> {
+        self.take(key).ok_or(Error::Missing(key))
+    }
+
+    fn parse<T: FromStr>(&mut self, key: &str) -> Result<Option<T>, Error>
+    where
+        T::Err: Display,
+    {
+        self.take(key)
+            .map(|value| {
+                value.trim().parse().map_err(|e: T::Err| Error::Invalid {
+                    key: key.to_string(),
+                    reason: e.to_string(),
+                    value,
+                })
+            })
+            .transpose()
+    }
+
+    fn flag(&mut self, key: &str, default: bool) -> Result<bool, Error> {
+        Ok(self.parse(key)?.unwrap_or(default))
+    }
+
+    /// A comma separated list.
+    fn list(&mut self, key: &str) -> Vec<String> {
+        self.take(key)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Drops an option that has no equivalent.
+    fn ignore(&mut self, key: &str, reason: &str) {
+        if let Some(value) = self.take(key) {
+            warn!("{key}={value} is ignored, {reason}");
+        }
+    }
+
+    /// Drops an output option that wlf can't provide, it is harmless if disabled.
+    fn ignore_flag(&mut self, key: &str) -> Result<(), Error> {
+        if self.flag(key, false)? {
+            warn!("{key}=true is ignored, it is not supported");
+        }
+        Ok(())
+    }
+}
+
+/// Converts the maxwell config into a pipeline of a binlog collector, a binlog filter if tables
+/// are filtered, and a dispatcher for the producer.
+pub(crate) fn convert(properties: HashMap<String, String>) -> Result<Config, Error> {
+    let mut p = Properties(properties);
+    let mut config = Config::default();
+
+    // mysql, wlf reads both the schema and the rows from the replication server
+    let host = p.take("host");
+    let port = p.parse("port")?;
+    let user = p.take("user");
+    let password = p.take("password");
+    let mut collector = BinlogCollector {
+        id: "collector".to_string(),
+        destination: "dispatcher".to_string(),
+        host: p
+            .take("replication_host")
+            .or(host)
+            .unwrap_or_else(wlf_binlog_collector::default_host),
+        user: p
+            .take("replication_user")
+            .or(user)
+            .ok_or(Error::Missing("user"))?,
+        password: p
+            .take("replication_password")
+            .or(password)
+            .unwrap_or_default(),
+        port: p
+            .parse("replication_port")?
+            .or(port)
+            .unwrap_or_else(wlf_binlog_collector::default_port),
+        replica_server_id: p
+            .parse("replica_server_id")?
+            .unwrap_or_else(wlf_binlog_collector::default_replica_server_id),
+        output: BinlogOutput {
+            ddl: p.flag("output_ddl", false)?,
+            binlog_position: p.flag("output_binlog_position", false)?,
+            server_id: p.flag("output_server_id", false)?,
+            nulls: p.flag("output_nulls", true)?,
+            primary_key: p.flag("output_primary_keys", false)?,
+            primary_key_columns: p.flag("output_primary_key_columns", false)?,
+        },
+        // like maxwell storing its position per client, replication resumes from the checkpoint
+        checkpoint_file: p
+            .take("client_id")
+            .map(|id| PathBuf::from(format!("{id}.checkpoint"))),
+        checkpoint_interval_secs: wlf_binlog_collector::default_checkpoint_interval_secs(),
+        delivery_timeout_secs: wlf_binlog_collector::default_delivery_timeout_secs(),
+    };
+    for key in [
+        "schema_host",
+        "schema_port",
+        "schema_user",
+        "schema_password",
+    ] {
+        p.ignore(key, "wlf reads the schema from the binlog");
+    }
+    p.ignore(
+        "schema_database",
+        "positions are kept in the checkpoint file of client_id",
+    );
+    // the replication connection is the only one, and can't be encrypted
+    let ssl = p.take("ssl");
+    if let Some((key, value)) = p
+        .take("replication_ssl")
+        .map(|v| ("replication_ssl", v))
+        .or(ssl.map(|v| ("ssl", v)))
+    {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "DISABLED" | "FALSE" => {}
+            "PREFERRED" => warn!("{key}={value} connects without SSL, it is not supported"),
+            _ => {
+                return Err(Error::Unsupported {
+                    key: key.to_string(),
+                    value,
+                })
+            }
+        }
+    }
+    p.ignore("schema_ssl", "wlf reads the schema from the binlog");
+    for key in [
+        "output_commit_info",
+        "output_xoffset",
+        "output_thread_id",
+        "output_gtid_position",
+        "output_schema_id",
+        "output_row_query",
+        "output_push_timestamp",
+    ] {
+        p.ignore_flag(key)?;
+    }
+    p.ignore("log_level", "set RUST_LOG instead");
+
+    // filter
+    if let Some(filter) = filter(&mut p)? {
+        config
+            .transformers
+            .push(Supervised::new(Transformer::BinlogFilter(filter)));
+        collector.destination = "filter".to_string();
+    }
+
+    // producer, maxwell writes to stdout by default
+    let dispatcher = match p.take("producer").as_deref().unwrap_or("stdout") {
+        "stdout" => Dispatcher::File(FileDispatcher {
+            id: "dispatcher".to_string(),
+            path: None,
+        }),
+        "file" => Dispatcher::File(FileDispatcher {
+            id: "dispatcher".to_string(),
+            path: Some(p.required("output_file")?.into()),
+        }),
+        "kafka" => Dispatcher::Kafka(kafka(&mut p)?),
+        "redis" => Dispatcher::Redis(redis(&mut p)?),
+        other => {
+            return Err(Error::Unsupported {
+                key: "producer".to_string(),
+                value: other.to_string(),
+            })
+        }
+    };
+
+    config
+        .collectors
+        .push(Supervised::new(Collector::Binlog(collector)));
+    config.dispatchers.push(Supervised::new(dispatcher));
+
+    for (k, v) in p.0 {
+        warn!("unrecognized property: {k}={v}");
+    }
+
+    Ok(config)
+}
+
+/// Maxwell's `%{table}` placeholders in wlf's format.
+fn template(maxwell: String) -> String {
+    maxwell
+        .replace("%{table}", "%{/table}")
+        .replace("%{database}", "%{/database}")
+}
+
+/// The legacy `include_*`/`exclude_*` options, then the rules of `filter`, where the last
+/// matching rule wins.
+fn filter(p: &mut Properties) -> Result<Option<BinlogFilter>, Error> {
+    let include_dbs = p.list("include_dbs");
+    let exclude_dbs = p.list("exclude_dbs");
+    let include_tables = p.list("include_tables");
+    let exclude_tables = p.list("exclude_tables");
+    let filter = p.take("filter");
+
+    let legacy = [&include_dbs, &exclude_dbs, &include_tables, &exclude_tables];
+    if legacy.iter().all(|names| names.is_empty()) && filter.is_none() {
+        return Ok(None);
+    }
+    for name in legacy.into_iter().flatten() {
+        if name.starts_with('/') {
+            return Err(Error::Unsupported {
+                key: "include_*/exclude_* regex".to_string(),
+                value: name.clone(),
+            });
+        }
+    }
+
+    let mut rules = BinlogFilterRules::new();
+    if !include_dbs.is_empty() || !include_tables.is_empty() {
+        let all = vec!["*".to_string()];
+        rules.exclude("*", "*");
+        for database in if include_dbs.is_empty() {
+            &all
+        } else {
+            &include_dbs
+        } {
+            for table in if include_tables.is_empty() {
+                &all
+            } else {
+                &include_tables
+            } {
+                include(&mut rules, database, table);
+            }
+        }
+    }
+    for database in exclude_dbs {
+        rules.exclude(database, "*");
+    }
+    for table in exclude_tables {
+        rules.exclude_table(table);
+    }
+
+    for rule in filter.iter().flat_map(|f| f.split(',')) {
+        let rule = rule.trim();
+        let invalid = |reason: &str| Error::Invalid {
+            key: "filter".to_string(),
+            value: rule.to_string(),
+            reason: reason.to_string(),
+        };
+        let (kind, pattern) = rule
+            .split_once(':')
+            .ok_or_else(|| invalid("expected include: or exclude:"))?;
+        let (database, table) = pattern
+            .trim()
+            .split_once('.')
+            .ok_or_else(|| invalid("expected database.table"))?;
+        if database.starts_with('/') || table.starts_with('/') || table.contains('.') {
+            return Err(Error::Unsupported {
+                key: "filter".to_string(),
+                value: rule.to_string(),
+            });
+        }
+        match kind.trim() {
+            "include" => include(&mut rules, database, table),
+            "exclude" | "blacklist" => exclude(&mut rules, database, table),
+            _ => return Err(invalid("expected include: or exclude:")),
+        }
+    }
+
+    Ok(Some(BinlogFilter {
+        id: "filter".to_string(),
+        destination: "dispatcher".to_string(),
+        rules,
+    }))
+}
+
+/// A `*` database of maxwell still matches the table, unlike the one of a filter rule.
+fn include(rules: &mut BinlogFilterRules, database: &str, table: &str) {
+    match (database, table) {
+        ("*", table) if table != "*" => rules.include_table(table),
+        (database, table) => rules.include(database, table),
+    }
+}
+
+fn exclude(rules: &mut BinlogFilterRules, database: &str, table: &str) {
+    match (database, table) {
+        ("*", table) if table != "*" => rules.exclude_table(table),
+        (database, table) => rules.exclude(database, table),
+    }
+}
+
+fn kafka(p: &mut Properties) -> Result<KafkaDispatcher, Error> {
+    let compression_type = match p.take("kafka.compression.type").as_deref() {
+        None | Some("none") => CompressionType::NoCompression,
+        Some("snappy") => CompressionType::Snappy,
+        Some("gzip") => CompressionType::Gzip,
+        Some(other) => {
+            return Err(Error::Unsupported {
+                key: "kafka.compression.type".to_string(),
+                value: other.to_string(),
+            })
+        }
+    };
+
+    let mut retry = RetryConfig::default();
+    if let Some(retries) = p.parse("kafka.retries")? {
+        retry.max_retries = Some(retries);
+    }
+    if let Some(backoff_ms) = p.parse("kafka.retry.backoff.ms")? {
+        retry.initial_backoff_ms = backoff_ms;
+    }
+
+    let key = match p.take("producer_partition_by").as_deref() {
+        None | Some("database") => Some("%{/database}".to_string()),
+        Some("table") => Some("%{/table}".to_string()),
+        Some("column") => {
+            let columns = p.list("producer_partition_columns");
+            if columns.is_empty() {
+                return Err(Error::Missing("producer_partition_columns"));
+            }
+            let columns: Vec<_> = columns.iter().map(|c| format!("%{{/data/{c}}}")).collect();
+            Some(columns.join("."))
+        }
+        // records without key are spread over the partitions
+        Some("random") => None,
+        Some(other) => {
+            return Err(Error::Unsupported {
+                key: "producer_partition_by".to_string(),
+                value: other.to_string(),
+            })
+        }
+    };
+    p.ignore(
+        "producer_partition_by_fallback",
+        "records whose key can't be built are sent without key",
+    );
+    p.ignore(
+        "kafka_partition_hash",
+        "records are partitioned by the murmur2 hash of their key, like the Java client does",
+    );
+
+    let dispatcher = KafkaDispatcher {
+        id: "dispatcher".to_string(),
+        topic: template(
+            p.take("kafka_topic")
+                .unwrap_or_else(wlf_kafka_dispatcher::default_topic),
+        ),
+        bootstrap_brokers: p
+            .required("kafka.bootstrap.servers")?
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .collect(),
+        key,
+        compression_type,
+        client_id: p.take("kafka.client.id"),
+        ack_timeout_ms: p.parse("kafka.request.timeout.ms")?,
+        retry,
+        dead_letter: None,
+    };
+
+    // the rest configures maxwell's java client, the ones about security and delivery
+    // guarantees can't be dropped silently
+    if let Some(protocol) = p.take("kafka.security.protocol") {
+        if !protocol.trim().eq_ignore_ascii_case("PLAINTEXT") {
+            return Err(Error::Unsupported {
+                key: "kafka.security.protocol".to_string(),
+                value: protocol,
+            });
+        }
+    }
+    // records are always acknowledged by all the in-sync replicas, which is at least what any
+    // setting asks for
+    if let Some(acks) = p.take("kafka.acks") {
+        if !matches!(acks.trim(), "all" | "-1" | "1" | "0") {
+            return Err(Error::Invalid {
+                key: "kafka.acks".to_string(),
+                value: acks,
+                reason: "expected all, -1, 1 or 0".to_string(),
+            });
+        }
+    }
+    if p.flag("kafka.enable.idempotence", false)? {
+        return Err(Error::Unsupported {
+            key: "kafka.enable.idempotence".to_string(),
+            value: "true".to_string(),
+        });
+    }
+    let mut options: Vec<String> =
+        p.0.keys()
+            .filter(|k| k.starts_with("kafka."))
+            .cloned()
+            .collect();
+    options.sort();
+    for option in options {
+        let unsupported = option.starts_with("kafka.sasl.")
+            || option.starts_with("kafka.ssl.")
+            || option == "kafka.transactional.id";
+        if unsupported {
+            let value = p.take(&option).unwrap_or_default();
+            return Err(Error::Unsupported { key: option, value });
+        }
+        p.ignore(&option, "the kafka client has no such option");
+    }
+
+    Ok(dispatcher)
+}
+
+fn redis(p: &mut Properties) -> Result<RedisDispatcher, Error> {
+    let redis_key = template(
+        p.take("redis_key")
+            .unwrap_or_else(wlf_redis_dispatcher::default_key),
+    );
+    let redis_stream_json_key = p
+        .take("redis_stream_json_key")
+        .unwrap_or_else(wlf_redis_dispatcher::default_redis_stream_json_key);
+    let mode = match p.take("redis_type").as_deref() {
+        Some("pubsub") => wlf_redis_dispatcher::Mode::Pub { channel: redis_key },
+        Some("xadd") => wlf_redis_dispatcher::Mode::XADD {
+            key: redis_key,
+            field: redis_stream_json_key,
+            flatten: false,
+            trim: None,
+        },
+        Some("lpush") => wlf_redis_dispatcher::Mode::LPush { key: redis_key },
+        Some("rpush") => wlf_redis_dispatcher::Mode::RPush { key: redis_key },
+        None => wlf_redis_dispatcher::Mode::default(),
+        Some(other) => {
+            return Err(Error::Unsupported {
+                key: "redis_type".to_string(),
+                value: other.to_string(),
+            })
+        }
+    };
+    let config = wlf_redis_dispatcher::Config {
+        username: None,
+        tls: false,
+        tls_insecure: false,
+        topology: wlf_redis_dispatcher::Topology::Standalone,
+        host: p
+            .take("redis_host")
+            .unwrap_or_else(wlf_redis_dispatcher::default_host),
+        port: p
+            .parse("redis_port")?
+            .unwrap_or_else(wlf_redis_dispatcher::default_port),
+        auth: p.take("redis_auth"),
+        database_number: p
+            .parse("redis_database")?
+            .unwrap_or_else(wlf_redis_dispatcher::default_database_number),
+    };
+    Ok(RedisDispatcher {
+        id: "dispatcher".to_string(),
+        mode,
+        batch: None,
+        retry: Default::default(),
+        dead_letter: None,
+        config,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(text: &str) -> HashMap<String, String> {
+        java_properties::read(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn convert_kafka() {
+        let config = convert(properties(
+            r#"
+host=mysql
+user=maxwell
+replication_host=replica
+replica_server_id=7
+client_id=orders
+output_ddl=true
+output_nulls=false
+output_primary_keys=true
+include_dbs=shop
+exclude_tables=audit
+filter=include: shop.audit_summary
+producer=kafka
+kafka.bootstrap.servers=k1:9092, k2:9092
+kafka_topic=maxwell_%{database}
+kafka.retries=3
+kafka.client.id=orders-producer
+kafka.request.timeout.ms=5000
+kafka.acks=1
+producer_partition_by=table
+"#,
+        ))
+        .unwrap();
+
+        let Collector::Binlog(collector) = &config.collectors[0].component else {
+            panic!("not a binlog collector");
+        };
+        assert_eq!(collector.host, "replica");
+        assert_eq!(collector.replica_server_id, 7);
+        assert_eq!(
+            collector.checkpoint_file,
+            Some(PathBuf::from("orders.checkpoint"))
+        );
+        assert!(collector.output.ddl && !collector.output.nulls);
+        assert!(collector.output.primary_key && !collector.output.primary_key_columns);
+        assert_eq!(collector.destination, "filter");
+
+        let Transformer::BinlogFilter(filter) = &config.transformers[0].component else {
+            panic!("not a binlog filter");
+        };
+        assert_eq!(
+            serde_json::to_value(&filter.rules).unwrap()["rules"],
+            serde_json::json!([
+                { "exclude": { "database": "*", "table": "*" } },
+                { "include": { "database": "shop", "table": "*" } },
+                { "exclude_table": { "table": "audit" } },
+                { "include": { "database": "shop", "table": "audit_summary" } },
+            ])
+        );
+
+        let Dispatcher::Kafka(kafka) = &config.dispatchers[0].component else {
+            panic!("not a kafka dispatcher");
+        };
+        assert_eq!(kafka.bootstrap_brokers, vec!["k1:9092", "k2:9092"]);
+        assert_eq!(kafka.topic, "maxwell_%{/database}");
+        assert_eq!(kafka.key.as_deref(), Some("%{/table}"));
+        assert_eq!(kafka.retry.max_retries, Some(3));
+        assert_eq!(kafka.client_id.as_deref(), Some("orders-producer"));
+        assert_eq!(kafka.ack_timeout_ms, Some(5000));
+    }
+
+    #[test]
+    fn convert_redis() {
+        let config = convert(properties(
+            "user=maxwell\nproducer=redis\nredis_type=xadd\nredis_key=%{table}",
+        ))
+        .unwrap();
+        let Dispatcher::Redis(redis) = &config.dispatchers[0].component else {
+            panic!("not a redis dispatcher");
+        };
+        let wlf_redis_dispatcher::Mode::XADD { key, field, .. } = &redis.mode else {
+            panic!("not a stream");
+        };
+        assert_eq!(key, "%{/table}");
+        assert_eq!(field, "message");
+    }
+
+    #[test]
+    fn report_errors() {
+        assert_eq!(
+            convert(properties("producer=stdout")).unwrap_err(),
+            Error::Missing("user")
+        );
+        assert!(matches!(
+            convert(properties("user=root\nport=x")).unwrap_err(),
+            Error::Invalid { key, .. } if key == "port"
+        ));
+        assert!(matches!(
+            convert(properties("user=root\nproducer=sqs")).unwrap_err(),
+            Error::Unsupported { key, .. } if key == "producer"
+        ));
+        assert!(matches!(
+            convert(properties("user=root\nfilter=include: /shop.*/.orders")).unwrap_err(),
+            Error::Unsupported { key, .. } if key == "filter"
+        ));
+        assert!(matches!(
+            convert(properties("user=root\nreplication_ssl=REQUIRED")).unwrap_err(),
+            Error::Unsupported { key, .. } if key == "replication_ssl"
+        ));
+        assert!(convert(properties("user=root\nssl=DISABLED")).is_ok());
+        let kafka = "user=root\nproducer=kafka\nkafka.bootstrap.servers=k1:9092\n";
+        assert!(matches!(
+            convert(properties(&format!("{kafka}kafka.security.protocol=SASL_SSL"))).unwrap_err(),
+            Error::Unsupported { key, .. } if key == "kafka.security.protocol"
+        ));
+        assert!(matches!(
+            convert(properties(&format!("{kafka}kafka.sasl.mechanism=PLAIN"))).unwrap_err(),
+            Error::Unsupported { key, .. } if key == "kafka.sasl.mechanism"
+        ));
+        assert!(matches!(
+            convert(properties(&format!("{kafka}kafka.acks=some"))).unwrap_err(),
+            Error::Invalid { key, .. } if key == "kafka.acks"
+        ));
+        assert!(convert(properties(&format!(
+            "{kafka}kafka.acks=all\nkafka.linger.ms=5"
+        )))
+        .is_ok());
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{sync::mpsc, time::Instant};
+use tracing::{error, info};
+use wlf_core::{
+    event_router::{EventRouter, EventRouterApi},
+    ComponentApi, ComponentKind,
+};
+
+use crate::{
+    config::Config,
+    shutdown::{shutdown_stages, EXIT_COMPONENT_FAILED, EXIT_DRAIN_TIMEOUT},
+    supervisor::{self, Exit, Supervision},
+};
+
+/// The running components of a config.
+pub(crate) struct Pipeline {
+    config: Config,
+    router: Arc<EventRouter>,
+    running: HashSet<String>,
+    exit_tx: mpsc::UnboundedSender<Exit>,
+    exit_rx: mpsc::UnboundedReceiver<Exit>,
+    /// Exits received while waiting for other components to stop
+    exits: VecDeque<Exit>,
+    failed: bool,
+}
+
+impl Pipeline {
+    pub(crate) fn start(config: Config) -> Self {
+        let (exit_tx, exit_rx) = mpsc::unbounded_channel();
+        let mut pipeline = Self {
+            config: Config::default(),
+            router: Arc::new(EventRouter::new()),
+            running: HashSet::new(),
+            exit_tx,
+            exit_rx,
+            exits: VecDeque::new(),
+            failed: false,
+        };
+        pipeline.spawn(instances(&config));
+        pipeline.config = config;
+        pipeline
+    }
+
+    pub(crate) fn router(&self) -> Arc<EventRouter> {
+        Arc::clone(&self.router)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    /// Waits for a component to be done for good.
+    pub(crate) async fn next_exit(&mut self) -> Option<Exit> {
+        if let Some(exit) = self.exits.pop_front() {
+            return Some(exit);
+        }
+        let exit = self.exit_rx.recv().await?;
+        self.record(&exit);
+        Some(exit)
+    }
+
+    /// Applies the new config: removed components are shut down, changed ones are restarted and
+    /// new ones are started, the others keep running.
+    pub(crate) async fn reload(&mut self, mut config: Config) {
+        let old = fingerprints(&self.config);
+        let new = fingerprints(&config);
+        let stopped: HashSet<String> = old
+            .iter()
+            .filter(|(id, fingerprint)| new.get(*id) != Some(fingerprint))
+            .map(|(id, _)| id.clone())
+            .collect();
+        let started: HashSet<String> = new
+            .iter()
+            .filter(|(id, fingerprint)| old.get(*id) != Some(fingerprint))
+            .map(|(id, _)| id.clone())
+            .collect();
+        if stopped.is_empty() && started.is_empty() {
+            info!("the config is unchanged");
+            return;
+        }
+        info!("reloading the config, stopping {stopped:?} and starting {started:?}");
+
+        let deadline = Instant::now() + Duration::from_secs(self.config.shutdown_timeout_secs);
+        let stuck: HashSet<String> = if self.stop(&stopped, deadline).await {
+            HashSet::new()
+        } else {
+            let stuck = stopped.intersection(&self.running).cloned().collect();
+            error!("components {stuck:?} did not stop in time, they keep their old config");
+            stuck
+        };
+
+        for id in stopped.difference(&stuck) {
+            // a component of the same kind is registered again and keeps its queued events
+            match (old.get(id), new.get(id)) {
+                // in case it had died on its own
+                (Some((old_kind, _)), Some((new_kind, _))) if old_kind == new_kind => {
+                    self.router.request_shutdown(id)
+                }
+                _ => self.router.unregister_component(id),
+            }
+        }
+        self.spawn(
+            instances(&config)
+                .into_iter()
+                .filter(|(c, _)| started.contains(c.id()) && !stuck.contains(c.id()))
+                .collect(),
+        );
+        keep_old_entries(&mut config, &self.config, &stuck);
+        self.config = config;
+    }
+
+    /// Shuts all the components down and returns the exit status of the process.
+    pub(crate) async fn shutdown(mut self) -> ExitCode {
+        if !self.running.is_empty() {
+            let timeout = Duration::from_secs(self.config.shutdown_timeout_secs);
+            info!("shutting down, waiting at most {timeout:?} for the queues to drain");
+            let ids = self.running.clone();
+            if !self.stop(&ids, Instant::now() + timeout).await {
+                error!("components {:?} did not shut down in time", self.running);
+                return ExitCode::from(EXIT_DRAIN_TIMEOUT);
+            }
+            info!("all components are shut down");
+        }
+
+        if self.failed {
+            ExitCode::from(EXIT_COMPONENT_FAILED)
+        } else {
+            ExitCode::SUCCESS
+        }
+    }
+
+    fn spawn(&mut self, instances: Vec<(Box<dyn ComponentApi>, Supervision)>) {
+        // every component is registered before any sends events
+        for (component, _) in &instances {
+            self.router.register_component(component.as_ref());
+        }
+        for (component, supervision) in instances {
+            self.running.insert(component.id().to_string());
+            supervisor::spawn(
+                component,
+                supervision,
+                Arc::clone(&self.router),
+                self.exit_tx.clone(),
+            );
+        }
+    }
+
+    /// Shuts the components down stage by stage, returns false if they are not all stopped
+    /// before the deadline.
+    async fn stop(&mut self, ids: &HashSet<String>, deadline: Instant) -> bool {
+        for stage in shutdown_stages(&self.config) {
+            let mut stage: HashSet<String> = stage
+                .into_iter()
+                .filter(|id| ids.contains(id) && self.running.contains(id))
+                .collect();
+            for id in &stage {
+                self.router.request_shutdown(id);
+            }
+            while !stage.is_empty() {
+                let Ok(Some(exit)) = tokio::time::timeout_at(deadline, self.exit_rx.recv()).await
+                else {
+                    return false;
+                };
+                if ids.contains(&exit.id) {
+                    self.running.remove(&exit.id);
+                    stage.remove(&exit.id);
+                    self.failed |= !exit.ok;
+                } else {
+                    self.record(&exit);
+                    self.exits.push_back(exit);
+                }
+            }
+        }
+        true
+    }
+
+    fn record(&mut self, exit: &Exit) {
+        self.running.remove(&exit.id);
+        self.failed |= !exit.ok || exit.critical;
+    }
+}
+
+/// Every component of the config, with how it is supervised.
+fn instances(config: &Config) -> Vec<(Box<dyn ComponentApi>, Supervision)> {
+    let mut instances = Vec::new();
+    for c in &config.collectors {
+        instances.push((c.component.clone().into_component(), c.supervision.clone()));
+    }
+    for t in &config.transformers {
+        instances.push((t.component.clone().into_component(), t.supervision.clone()));
+    }
+    for d in &config.dispatchers {
+        instances.push((d.component.clone().into_component(), d.supervision.clone()));
+    }
+    instances
+}
+
+/// Replaces the entries of the components in `config` by the ones of `old`, the config they
+/// still run with.
+fn keep_old_entries(config: &mut Config, old: &Config, ids: &HashSet<String>) {
+    let keep = |id: &str| !ids.contains(id);
+    config
+        .collectors
+        .retain(|c| keep(c.component.as_component().id()));
+    config
+        .transformers
+        .retain(|t| keep(t.component.as_component().id()));
+    config
+        .dispatchers
+        .retain(|d| keep(d.component.as_component().id()));
+    for c in &old.collectors {
+        if !keep(c.component.as_component().id()) {
+            config.collectors.push(c.clone());
+        }
+    }
+    for t in &old.transformers {
+        if !keep(t.component.as_component().id()) {
+            config.transformers.push(t.clone());
+        }
+    }
+    for d in &old.dispatchers {
+        if !keep(d.component.as_component().id()) {
+            config.dispatchers.push(d.clone());
+        }
+    }
+}
+
+/// The kind and config of every component, a component is restarted when they change.
+fn fingerprints(config: &Config) -> HashMap<String, (ComponentKind, serde_yaml::Value)> {
+    let mut fingerprints = HashMap::new();
+    for c in &config.collectors {
+        fingerprints.insert(
+            c.component.as_component().id().to_string(),
+            (ComponentKind::Collector, serde_yaml::to_value(c).unwrap()),
+        );
+    }
+    for t in &config.transformers {
+        fingerprints.insert(
+            t.component.as_component().id().to_string(),
+            (ComponentKind::Transformer, serde_yaml::to_value(t).unwrap()),
+        );
+    }
+    for d in &config.dispatchers {
+        fingerprints.insert(
+            d.component.as_component().id().to_string(),
+            (ComponentKind::Dispatcher, serde_yaml::to_value(d).unwrap()),
+        );
+    }
+    fingerprints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_changes() {
+        let old: Config = serde_yaml::from_str(
+            r#"
+transformers:
+  - id: filter
+    type: BinlogFilter
+    destination: kafka
+    rules: []
+dispatchers:
+  - id: kafka
+    type: Kafka
+    bootstrap_brokers: ["localhost:9092"]
+"#,
+        )
+        .unwrap();
+        let new: Config = serde_yaml::from_str(
+            r#"
+transformers:
+  - id: filter
+    type: BinlogFilter
+    destination: kafka
+    rules:
+      - exclude:
+          database: d1
+          table: "*"
+dispatchers:
+  - id: kafka
+    type: Kafka
+    bootstrap_brokers: ["localhost:9092"]
+"#,
+        )
+        .unwrap();
+
+        let (old, new) = (fingerprints(&old), fingerprints(&new));
+        assert_eq!(old["kafka"], new["kafka"]);
+        assert_ne!(old["filter"], new["filter"]);
+    }
+
+    #[test]
+    fn keep_stuck_entries() {
+        let old: Config = serde_yaml::from_str(
+            r#"
+dispatchers:
+  - id: kafka
+    type: Kafka
+    bootstrap_brokers: ["localhost:9092"]
+  - id: redis
+    type: Redis
+"#,
+        )
+        .unwrap();
+        let mut new: Config = serde_yaml::from_str(
+            r#"
+dispatchers:
+  - id: kafka
+    type: Kafka
+    bootstrap_brokers: ["localhost:9093"]
+"#,
+        )
+        .unwrap();
+
+        let stuck = HashSet::from(["kafka".to_string(), "redis".to_string()]);
+        keep_old_entries(&mut new, &old, &stuck);
+        assert_eq!(fingerprints(&new), fingerprints(&old));
+    }
+}
@@ -0,0 +1,61 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use tokio::sync::mpsc;
+
+/// How often a watched config file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Yields whenever the config should be reloaded: on SIGHUP, and if `watch` is set, whenever the
+/// config file, or a file of the config directory, is modified. Included files are not watched.
+pub(crate) fn reload_requests(path: PathBuf, watch: bool) -> mpsc::UnboundedReceiver<()> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let tx = tx.clone();
+        let mut hangup = signal(SignalKind::hangup()).expect("failed to listen for SIGHUP");
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                if tx.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    if watch {
+        tokio::spawn(async move {
+            let mut last = modified(&path);
+            loop {
+                tokio::time::sleep(WATCH_INTERVAL).await;
+                let current = modified(&path);
+                if current != last {
+                    last = current;
+                    if tx.send(()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    rx
+}
+
+/// The modification times of the file, or of the directory and its files.
+fn modified(path: &Path) -> Vec<Option<SystemTime>> {
+    let mtime = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut times = vec![mtime(path)];
+    if let Ok(entries) = fs::read_dir(path) {
+        let mut files: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        files.sort();
+        times.extend(files.iter().map(|f| mtime(f)));
+    }
+    times
+}
@@ -0,0 +1,127 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::config::Config;
+
+/// Exit status when a component returned an error.
+pub(crate) const EXIT_COMPONENT_FAILED: u8 = 1;
+/// Exit status when some components did not drain their queues in time, their events are lost.
+pub(crate) const EXIT_DRAIN_TIMEOUT: u8 = 2;
+
+/// Groups of component ids in the order they are shut down: all collectors first, then the
+/// transformers and dispatchers, each group only after every component sending to it.
+pub(crate) fn shutdown_stages(config: &Config) -> Vec<Vec<String>> {
+    let mut stages = vec![config
+        .collectors
+        .iter()
+        .map(|c| c.component.as_component().id().to_string())
+        .collect::<Vec<_>>()];
+
+    let mut edges: Vec<(&str, Vec<&str>)> = Vec::new();
+    for t in config.transformers.iter().map(|t| &t.component) {
+        edges.push((t.as_component().id(), t.destinations()));
+    }
+    for d in config.dispatchers.iter().map(|d| &d.component) {
+        edges.push((d.as_component().id(), d.destinations()));
+    }
+
+    // Kahn's algorithm, level by level
+    let mut upstreams: HashMap<&str, usize> = edges.iter().map(|(id, _)| (*id, 0)).collect();
+    for (_, destinations) in &edges {
+        for destination in destinations {
+            if let Some(n) = upstreams.get_mut(destination) {
+                *n += 1;
+            }
+        }
+    }
+    let mut left: HashSet<&str> = upstreams.keys().copied().collect();
+    while !left.is_empty() {
+        let mut stage: Vec<&str> = edges
+            .iter()
+            .map(|(id, _)| *id)
+            .filter(|id| left.contains(id) && upstreams[id] == 0)
+            .collect();
+        if stage.is_empty() {
+            // a cycle, stop whatever is left together
+            stage = edges
+                .iter()
+                .map(|(id, _)| *id)
+                .filter(|id| left.contains(id))
+                .collect();
+        }
+        for id in &stage {
+            left.remove(id);
+            let (_, destinations) = edges.iter().find(|(i, _)| i == id).expect("known id");
+            for destination in destinations {
+                if let Some(n) = upstreams.get_mut(destination) {
+                    *n = n.saturating_sub(1);
+                }
+            }
+        }
+        stages.push(stage.into_iter().map(String::from).collect());
+    }
+
+    stages
+}
+
+/// Resolves on SIGINT or SIGTERM.
+#[cfg(unix)]
+pub(crate) async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+/// Signals are not available, e.g. on WASI.
+#[cfg(not(unix))]
+pub(crate) async fn shutdown_signal() {
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+collectors:
+  - id: binlog
+    type: Binlog
+    destination: filter
+    user: root
+transformers:
+  - id: replicator
+    type: EventReplicator
+    destinations: [kafka, redis]
+  - id: filter
+    type: BinlogFilter
+    destination: replicator
+    rules: []
+dispatchers:
+  - id: kafka
+    type: Kafka
+    bootstrap_brokers: ["localhost:9092"]
+    dead_letter: redis
+  - id: redis
+    type: Redis
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            shutdown_stages(&config),
+            vec![
+                vec!["binlog"],
+                vec!["filter"],
+                vec!["replicator"],
+                vec!["kafka"],
+                vec!["redis"]
+            ]
+        );
+    }
+}
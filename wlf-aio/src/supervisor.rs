@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use utils::retry::{Backoff, RetryConfig};
+use wlf_core::{
+    event_router::{ComponentStatus, EventRouter, EventRouterApi},
+    ComponentApi,
+};
+
+/// A component entry of the configuration, with how it is supervised.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Supervised<T> {
+    // must come first, the component takes all the remaining fields
+    #[serde(flatten)]
+    pub(crate) supervision: Supervision,
+    #[serde(flatten)]
+    pub(crate) component: T,
+}
+
+impl<T> Supervised<T> {
+    pub(crate) fn new(component: T) -> Self {
+        Self {
+            supervision: Default::default(),
+            component,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct Supervision {
+    #[serde(default)]
+    pub(crate) restart: RestartConfig,
+    /// Shut down the whole pipeline once the component is dead for good.
+    #[serde(default)]
+    pub(crate) critical: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct RestartConfig {
+    #[serde(default)]
+    pub(crate) policy: RestartPolicy,
+    /// Give up after this many restarts, restart forever if not set.
+    #[serde(default, deserialize_with = "utils::option_number")]
+    pub(crate) max_restarts: Option<usize>,
+    /// Delay between restarts, it grows with every restart.
+    #[serde(default)]
+    pub(crate) backoff: RetryConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum RestartPolicy {
+    #[default]
+    Never,
+    /// Restart when the component returns an error or panics
+    OnFailure,
+    /// Restart whenever the component returns, unless it is being shut down
+    Always,
+}
+
+/// Sent when a component is done for good.
+#[derive(Debug)]
+pub(crate) struct Exit {
+    pub(crate) id: String,
+    pub(crate) ok: bool,
+    pub(crate) critical: bool,
+}
+
+/// Runs the component in its own task, restarting it according to the supervision settings.
+pub(crate) fn spawn(
+    component: Box<dyn ComponentApi>,
+    supervision: Supervision,
+    router: Arc<EventRouter>,
+    exit_tx: mpsc::UnboundedSender<Exit>,
+) {
+    let component: Arc<dyn ComponentApi> = Arc::from(component);
+    tokio::spawn(async move {
+        let id = component.id().to_string();
+        let ok = supervise(&id, component, &supervision.restart, &router).await;
+        let status = if ok {
+            ComponentStatus::Stopped
+        } else {
+            ComponentStatus::Failed
+        };
+        router.set_status(&id, status);
+        let _ = exit_tx.send(Exit {
+            id,
+            ok,
+            critical: supervision.critical,
+        });
+    });
+}
+
+/// Returns whether the last run of the component succeeded.
+async fn supervise(
+    id: &str,
+    component: Arc<dyn ComponentApi>,
+    restart: &RestartConfig,
+    router: &Arc<EventRouter>,
+) -> bool {
+    let mut backoff = Backoff::new(&RetryConfig {
+        max_retries: restart.max_restarts,
+        ..restart.backoff.clone()
+    });
+    loop {
+        router.set_status(id, ComponentStatus::Running);
+        router.set_ready(id, !component.reports_readiness());
+        // run in a task of its own, so that a panic is caught like an error
+        let run = tokio::spawn({
+            let component = Arc::clone(&component);
+            let router = Arc::clone(router);
+            async move { component.run(router).await.map_err(|e| e.to_string()) }
+        });
+        let ok = match run.await {
+            Ok(Ok(())) => true,
+            Ok(Err(e)) => {
+                error!("component {id} failed, {e}");
+                false
+            }
+            Err(e) => {
+                error!("component {id} panicked, {e}");
+                false
+            }
+        };
+
+        router.set_ready(id, false);
+        let shutting_down = router.shutdown_requested(id).now_or_never().is_some();
+        let restart_it = match restart.policy {
+            _ if shutting_down => false,
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !ok,
+            RestartPolicy::Always => true,
+        };
+        if !restart_it {
+            return ok;
+        }
+
+        let Some(delay) = backoff.next_delay() else {
+            error!(
+                "component {id} is not restarted again after {} restarts",
+                backoff.attempts()
+            );
+            return ok;
+        };
+        warn!("restarting component {id} in {delay:?}");
+        router.set_status(id, ComponentStatus::Restarting);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = router.shutdown_requested(id) => return ok,
+        }
+        info!(
+            "component {id} is restarted (restart {})",
+            backoff.attempts()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    use super::*;
+
+    #[test]
+    fn supervision_config() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+dispatchers:
+  - id: redis
+    type: Redis
+    port: 6380
+    critical: true
+    restart:
+      policy: OnFailure
+      max_restarts: 3
+  - id: kafka
+    type: Kafka
+    bootstrap_brokers: ["localhost:9092"]
+"#,
+        )
+        .unwrap();
+
+        let redis = &config.dispatchers[0];
+        assert_eq!(redis.component.as_component().id(), "redis");
+        assert!(redis.supervision.critical);
+        assert_eq!(redis.supervision.restart.policy, RestartPolicy::OnFailure);
+        assert_eq!(redis.supervision.restart.max_restarts, Some(3));
+
+        let kafka = &config.dispatchers[1];
+        assert!(!kafka.supervision.critical);
+        assert_eq!(kafka.supervision.restart.policy, RestartPolicy::Never);
+    }
+}
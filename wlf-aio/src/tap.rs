@@ -0,0 +1,104 @@
+//! A client of the tap of the admin API.
+
+use std::{error::Error, process::ExitCode};
+
+use clap::Args;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::admin::{default_address, AdminConfig};
+
+#[derive(Args)]
+pub(crate) struct TapArgs {
+    /// The component whose incoming events are printed
+    component: String,
+    /// Print one of every N events
+    #[arg(long, value_name = "N")]
+    sample: Option<u64>,
+    /// Only print the events whose field at the JSON pointer has the value, e.g. `/table=orders`
+    #[arg(long = "where", value_name = "POINTER=VALUE")]
+    filters: Vec<String>,
+    /// Stop after printing N events
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+    /// Address of the admin API, by default the one of the config
+    #[arg(long)]
+    address: Option<String>,
+    /// Token of the admin API, by default the one of the config
+    #[arg(long)]
+    token: Option<String>,
+}
+
+/// Prints the events of the tap as JSON lines until the pipeline closes it.
+pub(crate) async fn tap(
+    args: TapArgs,
+    admin: Option<AdminConfig>,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let (address, token) = match admin {
+        Some(admin) => (
+            args.address.or(Some(admin.address)),
+            args.token.or(admin.token),
+        ),
+        None => (args.address, args.token),
+    };
+    let address = address.unwrap_or_else(default_address);
+
+    let mut query = Vec::new();
+    if let Some(sample) = args.sample {
+        query.push(format!("sample={sample}"));
+    }
+    for filter in &args.filters {
+        query.push(format!("where={}", encode(filter)));
+    }
+    if let Some(limit) = args.limit {
+        query.push(format!("limit={limit}"));
+    }
+    let mut path = format!("/components/{}/tap", encode(&args.component));
+    if !query.is_empty() {
+        path = format!("{path}?{}", query.join("&"));
+    }
+
+    let mut stream = TcpStream::connect(&address)
+        .await
+        .map_err(|e| format!("failed to connect to the admin API at {address}, {e}"))?;
+    let authorization = token
+        .map(|token| format!("Authorization: Bearer {token}\r\n"))
+        .unwrap_or_default();
+    let request = format!("GET {path} HTTP/1.1\r\nHost: {address}\r\n{authorization}\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).await?;
+    // skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header).await? > 2 {
+        header.clear();
+    }
+
+    if !status_line.starts_with("HTTP/1.1 200") {
+        let mut body = String::new();
+        reader.read_to_string(&mut body).await?;
+        return Err(format!("{}, {body}", status_line.trim_end()).into());
+    }
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        println!("{line}");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Percent-encodes a query value.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
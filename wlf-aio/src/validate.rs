@@ -0,0 +1,276 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use thiserror::Error;
+
+use wlf_core::ComponentKind;
+
+use crate::config::Config;
+
+/// Exit status when the pipeline described by the configuration can't work.
+pub(crate) const EXIT_INVALID_CONFIG: u8 = 3;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum Problem {
+    #[error("component id {0} is used more than once")]
+    DuplicateId(String),
+    #[error("{from} sends events to {to}, which does not exist")]
+    UnknownDestination { from: String, to: String },
+    #[error("{from} sends events to {to}, which is a collector")]
+    CollectorDestination { from: String, to: String },
+    #[error("transformers {} form a cycle", .0.join(" -> "))]
+    Cycle(Vec<String>),
+    #[error("dead letters of {} form a cycle", .0.join(" -> "))]
+    DeadLetterCycle(Vec<String>),
+    #[error("transformer {0} has no inputs")]
+    UnusedTransformer(String),
+    #[error("dispatcher {0} has no inputs")]
+    UnusedDispatcher(String),
+    #[error("{id} is misconfigured, {reason}")]
+    Misconfigured { id: String, reason: String },
+}
+
+/// All the problems found in the pipeline.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Report(pub(crate) Vec<Problem>);
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the pipeline is invalid:")?;
+        for problem in &self.0 {
+            write!(f, "\n\t- {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Report {}
+
+/// Checks that the components are wired into a working pipeline.
+pub(crate) fn validate(config: &Config) -> Result<(), Report> {
+    let mut problems = Vec::new();
+
+    let components = config.components();
+
+    let mut kinds = HashMap::new();
+    for (id, kind, _) in &components {
+        if kinds.insert(*id, *kind).is_some() {
+            problems.push(Problem::DuplicateId(id.to_string()));
+        }
+    }
+
+    let mut inputs: HashMap<&str, usize> = HashMap::new();
+    // edges between transformers, ordered for a stable report
+    let mut transformer_graph: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    // edges out of transformers and dispatchers, a failed event must never come back to the
+    // dispatcher it failed in
+    let mut dead_letter_graph: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (from, kind, destinations) in &components {
+        for to in destinations {
+            match kinds.get(to) {
+                None => problems.push(Problem::UnknownDestination {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                }),
+                Some(ComponentKind::Collector) => problems.push(Problem::CollectorDestination {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                }),
+                Some(to_kind) => {
+                    *inputs.entry(to).or_default() += 1;
+                    if *kind != ComponentKind::Collector {
+                        dead_letter_graph.entry(from).or_default().push(to);
+                    }
+                    if *kind == ComponentKind::Transformer && *to_kind == ComponentKind::Transformer
+                    {
+                        transformer_graph.entry(from).or_default().push(to);
+                    }
+                }
+            }
+        }
+    }
+
+    for (id, kind, _) in &components {
+        if inputs.contains_key(id) {
+            continue;
+        }
+        match kind {
+            ComponentKind::Collector => {}
+            ComponentKind::Transformer => problems.push(Problem::UnusedTransformer(id.to_string())),
+            ComponentKind::Dispatcher => problems.push(Problem::UnusedDispatcher(id.to_string())),
+        }
+    }
+
+    for d in config.dispatchers.iter().map(|d| &d.component) {
+        if let Err(reason) = d.check() {
+            problems.push(Problem::Misconfigured {
+                id: d.as_component().id().to_string(),
+                reason,
+            });
+        }
+    }
+
+    problems.extend(
+        find_cycles(&transformer_graph)
+            .into_iter()
+            .map(Problem::Cycle),
+    );
+    problems.extend(
+        find_cycles(&dead_letter_graph)
+            .into_iter()
+            .filter(|cycle| {
+                cycle
+                    .iter()
+                    .any(|id| kinds.get(id.as_str()) == Some(&ComponentKind::Dispatcher))
+            })
+            .map(Problem::DeadLetterCycle),
+    );
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Report(problems))
+    }
+}
+
+/// Depth first search, a cycle is reported for every edge back to a node on the current path.
+fn find_cycles(graph: &BTreeMap<&str, Vec<&str>>) -> Vec<Vec<String>> {
+    fn visit<'a>(
+        node: &'a str,
+        graph: &BTreeMap<&'a str, Vec<&'a str>>,
+        path: &mut Vec<&'a str>,
+        done: &mut Vec<&'a str>,
+        cycles: &mut Vec<Vec<String>>,
+    ) {
+        if let Some(start) = path.iter().position(|n| *n == node) {
+            let mut cycle: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+            cycle.push(node.to_string());
+            cycles.push(cycle);
+            return;
+        }
+        if done.contains(&node) {
+            return;
+        }
+        path.push(node);
+        for next in graph.get(node).into_iter().flatten() {
+            visit(next, graph, path, done, cycles);
+        }
+        path.pop();
+        done.push(node);
+    }
+
+    let mut cycles = Vec::new();
+    let mut done = Vec::new();
+    for node in graph.keys() {
+        visit(node, graph, &mut Vec::new(), &mut done, &mut cycles);
+    }
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_problems() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+collectors:
+  - id: binlog
+    type: Binlog
+    destination: filter
+    user: root
+transformers:
+  - id: filter
+    type: BinlogFilter
+    destination: replicator
+    rules: []
+  - id: replicator
+    type: EventReplicator
+    destinations: [filter, kafka, binlog, es]
+  - id: orphan
+    type: BinlogFilter
+    destination: kafka
+    rules: []
+dispatchers:
+  - id: kafka
+    type: Kafka
+    bootstrap_brokers: ["localhost:9092"]
+  - id: kafka
+    type: Redis
+  - id: redis
+    type: Redis
+    database_number: 1
+    topology:
+      type: Cluster
+      nodes: ["localhost:7000"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            validate(&config),
+            Err(Report(vec![
+                Problem::DuplicateId("kafka".to_string()),
+                Problem::CollectorDestination {
+                    from: "replicator".to_string(),
+                    to: "binlog".to_string()
+                },
+                Problem::UnknownDestination {
+                    from: "replicator".to_string(),
+                    to: "es".to_string()
+                },
+                Problem::UnusedTransformer("orphan".to_string()),
+                Problem::UnusedDispatcher("redis".to_string()),
+                Problem::Misconfigured {
+                    id: "redis".to_string(),
+                    reason: "bad configuration, a redis cluster only has database 0".to_string()
+                },
+                Problem::Cycle(vec![
+                    "filter".to_string(),
+                    "replicator".to_string(),
+                    "filter".to_string()
+                ]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn reject_dead_letter_cycles() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+collectors:
+  - id: binlog
+    type: Binlog
+    destination: kafka
+    user: root
+dispatchers:
+  - id: kafka
+    type: Kafka
+    bootstrap_brokers: ["localhost:9092"]
+    dead_letter: redis
+  - id: redis
+    type: Redis
+    dead_letter: kafka
+  - id: es
+    type: Elasticsearch
+    dead_letter: es
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            validate(&config),
+            Err(Report(vec![
+                Problem::DeadLetterCycle(vec!["es".to_string(), "es".to_string()]),
+                Problem::DeadLetterCycle(vec![
+                    "kafka".to_string(),
+                    "redis".to_string(),
+                    "kafka".to_string()
+                ]),
+            ]))
+        );
+    }
+}
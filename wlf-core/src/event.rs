@@ -1,4 +1,15 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock, Weak,
+    },
+};
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
 
 use serde_json::Value;
 
@@ -9,5 +20,122 @@ pub struct Event {
     pub meta: EventMeta,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct EventMeta {}
+/// Filled in by the [`EventRouter`](crate::event_router::EventRouter) when the event is first
+/// sent, i.e. when it leaves its collector.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventMeta {
+    /// Unique id of the event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Id of the collector that produced the event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingested_at: Option<DateTime<Utc>>,
+    /// Ids of the components the event went through, starting with the source. The last one is
+    /// the component currently holding the event.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lineage: Vec<String>,
+    /// Free-form annotations of the components
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, Value>,
+    /// Why the event was routed to a dead-letter destination.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letter: Option<DeadLetter>,
+    /// Tells the collector when the event is handled, it is not serialized.
+    #[serde(skip)]
+    pub delivery: Option<Delivery>,
+}
+
+impl EventMeta {
+    /// Records that the event is sent from one component to another.
+    pub(crate) fn record_hop(&mut self, from: &str, to: &str) {
+        if self.id.is_none() {
+            self.id = Some(new_event_id());
+            self.source = Some(from.to_string());
+            self.ingested_at = Some(Utc::now());
+        }
+        if self.lineage.is_empty() {
+            self.lineage.push(from.to_string());
+        }
+        self.lineage.push(to.to_string());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// The component that failed to handle the event
+    pub component_id: String,
+    pub error: String,
+    /// How many times the component tried to deliver the event
+    pub attempts: usize,
+}
+
+/// Lets a collector know when its events are handled downstream, e.g. to acknowledge them to
+/// the source. An event is handled once it and all of its copies are dropped, i.e. delivered,
+/// filtered out or dead-lettered, so dispatchers keep their events until they are written.
+#[derive(Debug, Clone)]
+pub struct Delivery(
+    // only held, the receipt watches for the last clone to be dropped
+    #[allow(dead_code)] Arc<Handled>,
+);
+
+/// Wakes the receipt up once the last clone of the delivery is dropped.
+#[derive(Debug)]
+struct Handled(Arc<Notify>);
+
+impl Drop for Handled {
+    fn drop(&mut self) {
+        self.0.notify_one();
+    }
+}
+
+/// The collector side of a [`Delivery`].
+#[derive(Debug)]
+pub struct Receipt {
+    delivery: Weak<Handled>,
+    handled: Arc<Notify>,
+}
+
+impl Delivery {
+    /// Returns a delivery to put in the meta of one or more events, and the receipt to check
+    /// whether all of them are handled.
+    pub fn track() -> (Delivery, Receipt) {
+        let handled = Arc::new(Notify::new());
+        let delivery = Arc::new(Handled(handled.clone()));
+        let receipt = Receipt {
+            delivery: Arc::downgrade(&delivery),
+            handled,
+        };
+        (Delivery(delivery), receipt)
+    }
+
+    /// Gives the events up without them counting as handled, e.g. when a dispatcher stops
+    /// before it could write them: the receipt never resolves, so the source keeps them.
+    pub fn abandon(self) {
+        std::mem::forget(self)
+    }
+}
+
+impl Receipt {
+    pub fn is_delivered(&self) -> bool {
+        self.delivery.strong_count() == 0
+    }
+
+    /// Waits until the events are handled.
+    pub async fn delivered(&self) {
+        if !self.is_delivered() {
+            self.handled.notified().await;
+        }
+    }
+}
+
+/// A random per-process prefix followed by a counter, unique without coordination.
+fn new_event_id() -> String {
+    static PREFIX: OnceLock<u64> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let prefix = PREFIX.get_or_init(|| RandomState::new().build_hasher().finish());
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{prefix:016x}-{n:016x}")
+}
@@ -1,19 +1,67 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
 
 use async_trait::async_trait;
-use flume::{RecvError, SendError};
+use flume::{RecvError, SendError, TrySendError};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::Notify;
 
-use crate::{event::Event, ComponentApi, ComponentKind};
+use crate::{
+    event::Event,
+    metrics::{Metric, Metrics},
+    ComponentApi, ComponentKind,
+};
 use thiserror::Error;
-use tracing::error;
+use tracing::{error, info, warn};
 
 #[async_trait]
 pub trait EventRouterApi {
-    async fn send_event(&self, event: Event, component_id: &str) -> Result<(), Error>;
+    /// Sends the event from the component `from` to the component `to`, recording the hop in
+    /// the event meta.
+    async fn send_event(&self, event: Event, from: &str, to: &str) -> Result<(), Error>;
+    /// Waits for the next event of the component. Once the shutdown of the component is
+    /// requested, the queued events are still returned, then [`Error::ShutDown`].
     async fn poll_event(&self, component_id: &str) -> Result<Event, Error>;
-    fn register_component(&mut self, collector: &dyn ComponentApi);
+    /// Registers the component, it can be done while the pipeline is running. A component
+    /// that was shut down can be registered again, it keeps the events queued for it, its
+    /// pause, taps, readiness and position.
+    fn register_component(&self, component: &dyn ComponentApi);
+    /// Removes the component, the events queued for it are dropped.
+    fn unregister_component(&self, component_id: &str);
+    /// Asks the component to stop, see [`EventRouterApi::shutdown_requested`].
+    fn request_shutdown(&self, component_id: &str);
+    /// Resolves once the shutdown of the component is requested. Collectors should stop
+    /// producing events and return from `run` when it does, other components stop when
+    /// `poll_event` returns [`Error::ShutDown`].
+    async fn shutdown_requested(&self, component_id: &str);
+    /// Holds the component: a paused collector waits in `send_event`, other components wait
+    /// in `poll_event` and their events are queued meanwhile.
+    fn pause(&self, component_id: &str) -> Result<(), Error>;
+    fn resume(&self, component_id: &str) -> Result<(), Error>;
+    /// Records the lifecycle of the component, set by whoever runs it.
+    fn set_status(&self, component_id: &str, status: ComponentStatus);
+    /// Records whether the component is ready to work, e.g. connected to its service.
+    fn set_ready(&self, component_id: &str, ready: bool);
+    /// Records how far the component has read its source, e.g. the binlog position.
+    fn set_position(&self, component_id: &str, position: String);
+    /// The registered components, sorted by id.
+    fn components(&self) -> Vec<ComponentInfo>;
+    /// The metrics of the components, the router counts the events sent and received.
+    fn metrics(&self) -> &Metrics;
+    /// Copies the events sent to the component from now on, until the tap is dropped. A tap
+    /// that is not read fast enough misses events, delivery is never held up.
+    fn tap(&self, component_id: &str, options: TapOptions) -> Result<Tap, Error>;
 }
 
+/// Events a tap keeps for its reader, the ones that don't fit are not copied.
+const TAP_CAPACITY: usize = 1024;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("no such component {0}")]
@@ -22,12 +70,173 @@ pub enum Error {
     WrongComponentKind,
     #[error("internal error, {0}")]
     Internal(String),
+    #[error("component is shut down")]
+    ShutDown,
 }
 
 pub struct EventRouter {
-    registry: HashMap<String, ComponentRecord>,
+    registry: RwLock<HashMap<String, Arc<Entry>>>,
+    metrics: Metrics,
+}
+
+struct Entry {
+    record: ComponentRecord,
+    shutdown: Shutdown,
+    /// Shared with the entry of the component registered again
+    pause: Arc<Pause>,
+    taps: Arc<Mutex<Vec<TapSender>>>,
+    ready: AtomicBool,
+    status: Mutex<ComponentStatus>,
+    position: Mutex<Option<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ComponentStatus {
+    /// Registered but not run yet
+    Starting,
+    Running,
+    /// Waiting to be run again after it returned
+    Restarting,
+    /// Running, but asked to shut down
+    Stopping,
+    Stopped,
+    Failed,
+}
+
+/// A snapshot of a registered component.
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentInfo {
+    pub id: String,
+    pub kind: ComponentKind,
+    pub status: ComponentStatus,
+    pub ready: bool,
+    pub paused: bool,
+    /// Events waiting to be polled, collectors have no queue
+    pub queue_depth: Option<usize>,
+    pub position: Option<String>,
+}
+
+/// The cooperative shutdown signal of a component.
+#[derive(Default)]
+struct Shutdown {
+    requested: AtomicBool,
+    notify: Notify,
+}
+
+impl Shutdown {
+    fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    async fn wait(&self) {
+        loop {
+            // register before checking, so that a request in between is not missed
+            let notified = self.notify.notified();
+            if self.is_requested() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Whether the component is paused, see [`EventRouterApi::pause`].
+#[derive(Default)]
+struct Pause {
+    paused: AtomicBool,
+    notify: Notify,
+}
+
+impl Pause {
+    fn set(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    async fn wait_resumed(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if !self.is_paused() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Which of the events sent to a component a tap copies.
+#[derive(Debug, Clone)]
+pub struct TapOptions {
+    /// Copy one of every `sample` matching events.
+    pub sample: u64,
+    /// Copy only the events having these values, by JSON pointer.
+    pub fields: Vec<(String, Value)>,
+}
+
+impl Default for TapOptions {
+    fn default() -> Self {
+        Self {
+            sample: 1,
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// Copies of the events sent to a component, see [`EventRouterApi::tap`].
+pub struct Tap {
+    rx: flume::Receiver<Event>,
+}
+
+impl Tap {
+    /// Waits for the next copy, none once the component is gone.
+    pub async fn next(&self) -> Option<Event> {
+        self.rx.recv_async().await.ok()
+    }
+}
+
+struct TapSender {
+    tx: flume::Sender<Event>,
+    options: TapOptions,
+    /// Matching events to skip before the next copy
+    skip: u64,
 }
 
+impl TapSender {
+    /// Copies the event if it is selected, returns false once the tap is dropped.
+    fn offer(&mut self, event: &Event) -> bool {
+        if self.tx.is_disconnected() {
+            return false;
+        }
+        let matches = self
+            .options
+            .fields
+            .iter()
+            .all(|(pointer, value)| event.value.pointer(pointer) == Some(value));
+        if !matches {
+            return true;
+        }
+        if self.skip > 0 {
+            self.skip -= 1;
+            return true;
+        }
+        self.skip = self.options.sample.saturating_sub(1);
+        // a copy waiting in the tap must not hold back the delivery of the event
+        let mut copy = event.clone();
+        copy.meta.delivery = None;
+        !matches!(self.tx.try_send(copy), Err(TrySendError::Disconnected(_)))
+    }
+}
+
+#[derive(Clone)]
 pub enum ComponentRecord {
     Collector,
     Transformer {
@@ -43,66 +252,259 @@ pub enum ComponentRecord {
 impl EventRouter {
     pub fn new() -> Self {
         Self {
-            registry: HashMap::new(),
+            registry: RwLock::new(HashMap::new()),
+            metrics: Metrics::new(),
         }
     }
+
+    fn entry(&self, component_id: &str) -> Option<Arc<Entry>> {
+        self.registry.read().unwrap().get(component_id).cloned()
+    }
 }
 
 #[async_trait]
 impl EventRouterApi for EventRouter {
-    async fn send_event(&self, event: Event, component_id: &str) -> Result<(), Error> {
-        let r = self.registry.get(component_id).ok_or_else(|| {
-            error!("can't send event to component {component_id}, component does not exist");
-            Error::NoSuchComponent(component_id.to_string())
+    async fn send_event(&self, mut event: Event, from: &str, to: &str) -> Result<(), Error> {
+        if let Some(sender) = self.entry(from) {
+            tokio::select! {
+                _ = sender.pause.wait_resumed() => {}
+                _ = sender.shutdown.wait() => {}
+            }
+        }
+        let entry = self.entry(to).ok_or_else(|| {
+            error!("can't send event to component {to}, component does not exist");
+            Error::NoSuchComponent(to.to_string())
         })?;
-        let tx = match r {
+        let tx = match &entry.record {
             ComponentRecord::Collector => {
                 error!("can't send an event to collector");
                 return Err(Error::WrongComponentKind);
             }
             ComponentRecord::Dispatcher { tx, .. } | ComponentRecord::Transformer { tx, .. } => tx,
         };
+        event.meta.record_hop(from, to);
+        entry
+            .taps
+            .lock()
+            .unwrap()
+            .retain_mut(|tap| tap.offer(&event));
         tx.send_async(event).await?;
+        self.metrics.increment(Metric::EventsSent, from, 1);
         Ok(())
     }
     async fn poll_event(&self, component_id: &str) -> Result<Event, Error> {
-        let r = self.registry.get(component_id).ok_or_else(|| {
+        let entry = self.entry(component_id).ok_or_else(|| {
             error!("can't send event to component {component_id}, component does not exist");
             Error::NoSuchComponent(component_id.to_string())
         })?;
-        let rx = match r {
+        let rx = match &entry.record {
             ComponentRecord::Collector => {
                 error!("collector can't receive an event");
                 return Err(Error::WrongComponentKind);
             }
             ComponentRecord::Dispatcher { rx, .. } | ComponentRecord::Transformer { rx, .. } => rx,
         };
-        Ok(rx.recv_async().await?)
-    }
-    fn register_component(&mut self, component: &dyn ComponentApi) {
-        if self.registry.contains_key(component.id()) {
-            error!("component {} has already been registered", component.id());
-            return;
+        let shutdown = &entry.shutdown;
+        let mut event = None;
+        if !shutdown.is_requested() {
+            let resumed_recv = async {
+                entry.pause.wait_resumed().await;
+                rx.recv_async().await
+            };
+            tokio::select! {
+                received = resumed_recv => event = Some(received?),
+                _ = shutdown.wait() => {}
+            }
         }
-        match component.kind() {
-            ComponentKind::Collector => {
-                self.registry
-                    .insert(component.id().to_owned(), ComponentRecord::Collector);
+        let event = match event {
+            Some(event) => event,
+            // drain what is left
+            None => rx.try_recv().map_err(|_| Error::ShutDown)?,
+        };
+        self.metrics
+            .increment(Metric::EventsReceived, component_id, 1);
+        Ok(event)
+    }
+    fn register_component(&self, component: &dyn ComponentApi) {
+        let mut registry = self.registry.write().unwrap();
+        let entry = match registry.get(component.id()) {
+            Some(entry) if !entry.shutdown.is_requested() => {
+                error!("component {} has already been registered", component.id());
+                return;
             }
-            ComponentKind::Transformer => {
-                let (tx, rx) = flume::unbounded();
-                self.registry.insert(
-                    component.id().to_owned(),
-                    ComponentRecord::Transformer { tx, rx },
-                );
+            // the restarted component is still paused, tapped and ready as it was
+            Some(entry) if entry.record.kind() == component.kind() => {
+                info!("component {} is registered again", component.id());
+                Entry {
+                    record: entry.record.clone(),
+                    shutdown: Shutdown::default(),
+                    pause: Arc::clone(&entry.pause),
+                    taps: Arc::clone(&entry.taps),
+                    ready: AtomicBool::new(entry.ready.load(Ordering::SeqCst)),
+                    status: Mutex::new(ComponentStatus::Starting),
+                    position: Mutex::new(entry.position.lock().unwrap().clone()),
+                }
             }
-            ComponentKind::Dispatcher => {
-                let (tx, rx) = flume::unbounded();
-                self.registry.insert(
-                    component.id().to_owned(),
-                    ComponentRecord::Dispatcher { tx, rx },
+            Some(_) => {
+                error!(
+                    "component {} is registered with another kind",
+                    component.id()
                 );
+                return;
             }
+            None => {
+                let record = match component.kind() {
+                    ComponentKind::Collector => ComponentRecord::Collector,
+                    ComponentKind::Transformer => {
+                        let (tx, rx) = flume::unbounded();
+                        ComponentRecord::Transformer { tx, rx }
+                    }
+                    ComponentKind::Dispatcher => {
+                        let (tx, rx) = flume::unbounded();
+                        ComponentRecord::Dispatcher { tx, rx }
+                    }
+                };
+                Entry {
+                    record,
+                    shutdown: Shutdown::default(),
+                    pause: Arc::default(),
+                    taps: Arc::default(),
+                    ready: AtomicBool::new(false),
+                    status: Mutex::new(ComponentStatus::Starting),
+                    position: Mutex::new(None),
+                }
+            }
+        };
+        registry.insert(component.id().to_owned(), Arc::new(entry));
+    }
+    fn unregister_component(&self, component_id: &str) {
+        let Some(entry) = self.registry.write().unwrap().remove(component_id) else {
+            error!("can't unregister component {component_id}, component does not exist");
+            return;
+        };
+        if let ComponentRecord::Transformer { rx, .. } | ComponentRecord::Dispatcher { rx, .. } =
+            &entry.record
+        {
+            if !rx.is_empty() {
+                warn!("{} events queued for {component_id} are dropped", rx.len());
+                self.metrics
+                    .increment(Metric::EventsDropped, component_id, rx.len() as u64);
+            }
+        }
+        // whoever still polls the component stops
+        entry.shutdown.request();
+    }
+    fn request_shutdown(&self, component_id: &str) {
+        match self.entry(component_id) {
+            Some(entry) => entry.shutdown.request(),
+            None => error!("can't shut down component {component_id}, component does not exist"),
+        }
+    }
+    async fn shutdown_requested(&self, component_id: &str) {
+        match self.entry(component_id) {
+            Some(entry) => entry.shutdown.wait().await,
+            None => {
+                error!("can't wait for component {component_id}, component does not exist");
+                std::future::pending().await
+            }
+        }
+    }
+    fn pause(&self, component_id: &str) -> Result<(), Error> {
+        let entry = self
+            .entry(component_id)
+            .ok_or_else(|| Error::NoSuchComponent(component_id.to_string()))?;
+        entry.pause.set(true);
+        info!("component {component_id} is paused");
+        Ok(())
+    }
+    fn resume(&self, component_id: &str) -> Result<(), Error> {
+        let entry = self
+            .entry(component_id)
+            .ok_or_else(|| Error::NoSuchComponent(component_id.to_string()))?;
+        entry.pause.set(false);
+        info!("component {component_id} is resumed");
+        Ok(())
+    }
+    fn set_status(&self, component_id: &str, status: ComponentStatus) {
+        if let Some(entry) = self.entry(component_id) {
+            *entry.status.lock().unwrap() = status;
+        }
+    }
+    fn set_ready(&self, component_id: &str, ready: bool) {
+        if let Some(entry) = self.entry(component_id) {
+            entry.ready.store(ready, Ordering::SeqCst);
+        }
+    }
+    fn set_position(&self, component_id: &str, position: String) {
+        if let Some(entry) = self.entry(component_id) {
+            *entry.position.lock().unwrap() = Some(position);
+        }
+    }
+    fn components(&self) -> Vec<ComponentInfo> {
+        let mut components: Vec<_> = self
+            .registry
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| entry.info(id))
+            .collect();
+        components.sort_by(|a, b| a.id.cmp(&b.id));
+        components
+    }
+    fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+    fn tap(&self, component_id: &str, options: TapOptions) -> Result<Tap, Error> {
+        let entry = self
+            .entry(component_id)
+            .ok_or_else(|| Error::NoSuchComponent(component_id.to_string()))?;
+        if let ComponentRecord::Collector = entry.record {
+            return Err(Error::WrongComponentKind);
+        }
+        let (tx, rx) = flume::bounded(TAP_CAPACITY);
+        let mut taps = entry.taps.lock().unwrap();
+        // the dropped taps of a component receiving no events are only removed here
+        taps.retain(|tap| !tap.tx.is_disconnected());
+        taps.push(TapSender {
+            tx,
+            options,
+            skip: 0,
+        });
+        info!("component {component_id} is tapped");
+        Ok(Tap { rx })
+    }
+}
+
+impl Entry {
+    fn info(&self, id: &str) -> ComponentInfo {
+        let status = match *self.status.lock().unwrap() {
+            ComponentStatus::Running if self.shutdown.is_requested() => ComponentStatus::Stopping,
+            status => status,
+        };
+        let queue_depth = match &self.record {
+            ComponentRecord::Collector => None,
+            ComponentRecord::Transformer { rx, .. } | ComponentRecord::Dispatcher { rx, .. } => {
+                Some(rx.len())
+            }
+        };
+        ComponentInfo {
+            id: id.to_string(),
+            kind: self.record.kind(),
+            status,
+            ready: self.ready.load(Ordering::SeqCst),
+            paused: self.pause.is_paused(),
+            queue_depth,
+            position: self.position.lock().unwrap().clone(),
+        }
+    }
+}
+
+impl ComponentRecord {
+    fn kind(&self) -> ComponentKind {
+        match self {
+            ComponentRecord::Collector => ComponentKind::Collector,
+            ComponentRecord::Transformer { .. } => ComponentKind::Transformer,
+            ComponentRecord::Dispatcher { .. } => ComponentKind::Dispatcher,
         }
     }
 }
@@ -124,3 +526,214 @@ impl From<RecvError> for Error {
         Error::Internal("failed to receive event".to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{value, Delivery, EventMeta};
+
+    use super::*;
+
+    struct Dispatcher;
+
+    #[async_trait]
+    impl ComponentApi for Dispatcher {
+        fn id(&self) -> &str {
+            "dispatcher"
+        }
+        fn kind(&self) -> ComponentKind {
+            ComponentKind::Dispatcher
+        }
+        async fn run(&self, _router: Arc<EventRouter>) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn drain_on_shutdown() {
+        let router = EventRouter::new();
+        router.register_component(&Dispatcher);
+
+        let event = Event {
+            value: value!({ "n": 1 }),
+            meta: EventMeta::default(),
+        };
+        router
+            .send_event(event, "collector", "dispatcher")
+            .await
+            .unwrap();
+        router.request_shutdown("dispatcher");
+        router.shutdown_requested("dispatcher").await;
+
+        let event = router.poll_event("dispatcher").await.unwrap();
+        assert_eq!(event.meta.source.as_deref(), Some("collector"));
+        assert_eq!(event.meta.lineage, vec!["collector", "dispatcher"]);
+        assert!(matches!(
+            router.poll_event("dispatcher").await,
+            Err(Error::ShutDown)
+        ));
+    }
+
+    #[tokio::test]
+    async fn register_again() {
+        let router = EventRouter::new();
+        router.register_component(&Dispatcher);
+
+        let event = Event {
+            value: value!({ "n": 1 }),
+            meta: EventMeta::default(),
+        };
+        router
+            .send_event(event.clone(), "collector", "dispatcher")
+            .await
+            .unwrap();
+        router.request_shutdown("dispatcher");
+
+        // the restarted component gets the queued event
+        router.register_component(&Dispatcher);
+        let polled = router.poll_event("dispatcher").await.unwrap();
+        assert_eq!(polled.value, event.value);
+
+        router.unregister_component("dispatcher");
+        assert!(matches!(
+            router.send_event(event, "collector", "dispatcher").await,
+            Err(Error::NoSuchComponent(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume() {
+        let router = Arc::new(EventRouter::new());
+        router.register_component(&Dispatcher);
+        router.pause("dispatcher").unwrap();
+
+        let event = Event {
+            value: value!({ "n": 1 }),
+            meta: EventMeta::default(),
+        };
+        router
+            .send_event(event, "collector", "dispatcher")
+            .await
+            .unwrap();
+        let poll = tokio::spawn({
+            let router = Arc::clone(&router);
+            async move { router.poll_event("dispatcher").await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!poll.is_finished());
+
+        let info = &router.components()[0];
+        assert!(info.paused);
+        assert_eq!(info.queue_depth, Some(1));
+        assert_eq!(info.status, ComponentStatus::Starting);
+
+        router.resume("dispatcher").unwrap();
+        assert!(poll.await.unwrap().is_ok());
+        assert!(matches!(
+            router.pause("nobody"),
+            Err(Error::NoSuchComponent(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn keep_state_on_restart() {
+        let router = Arc::new(EventRouter::new());
+        router.register_component(&Dispatcher);
+        router.pause("dispatcher").unwrap();
+        router.set_ready("dispatcher", true);
+        let tap = router.tap("dispatcher", TapOptions::default()).unwrap();
+
+        router.request_shutdown("dispatcher");
+        router.register_component(&Dispatcher);
+        let info = &router.components()[0];
+        assert!(info.paused);
+        assert!(info.ready);
+        assert_eq!(info.status, ComponentStatus::Starting);
+
+        let event = Event {
+            value: value!({ "n": 1 }),
+            meta: EventMeta::default(),
+        };
+        router
+            .send_event(event, "collector", "dispatcher")
+            .await
+            .unwrap();
+        assert_eq!(tap.next().await.unwrap().value, value!({ "n": 1 }));
+        let poll = tokio::spawn({
+            let router = Arc::clone(&router);
+            async move { router.poll_event("dispatcher").await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!poll.is_finished());
+        router.resume("dispatcher").unwrap();
+        assert!(poll.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn tap() {
+        let router = EventRouter::new();
+        router.register_component(&Dispatcher);
+        let options = TapOptions {
+            sample: 2,
+            fields: vec![("/table".to_string(), value!("t1"))],
+        };
+        let tap = router.tap("dispatcher", options).unwrap();
+
+        for (n, table) in [(1, "t1"), (2, "t2"), (3, "t1"), (4, "t1")] {
+            let event = Event {
+                value: value!({ "n": n, "table": table }),
+                meta: EventMeta::default(),
+            };
+            router
+                .send_event(event, "collector", "dispatcher")
+                .await
+                .unwrap();
+        }
+
+        // delivery is unaffected
+        assert_eq!(router.components()[0].queue_depth, Some(4));
+        // one of every two events of t1
+        assert_eq!(tap.next().await.unwrap().value["n"], 1);
+        assert_eq!(tap.next().await.unwrap().value["n"], 4);
+        assert!(tap.rx.is_empty());
+
+        // dropped taps are removed, even without events
+        drop(tap);
+        let _tap = router.tap("dispatcher", TapOptions::default()).unwrap();
+        let entry = router.entry("dispatcher").unwrap();
+        assert_eq!(entry.taps.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn track_delivery() {
+        let router = EventRouter::new();
+        router.register_component(&Dispatcher);
+        let tap = router.tap("dispatcher", TapOptions::default()).unwrap();
+
+        let (delivery, receipt) = Delivery::track();
+        let event = Event {
+            value: value!({ "n": 1 }),
+            meta: EventMeta {
+                delivery: Some(delivery),
+                ..Default::default()
+            },
+        };
+        let copy = event.clone();
+        router
+            .send_event(event, "collector", "dispatcher")
+            .await
+            .unwrap();
+        drop(copy);
+        assert!(!receipt.is_delivered());
+
+        let event = router.poll_event("dispatcher").await.unwrap();
+        assert!(!receipt.is_delivered());
+        // the receipt is woken up by the drop, the copy of the tap does not count
+        let waiting = tokio::time::timeout(Duration::from_secs(1), receipt.delivered());
+        let (waited, ()) = tokio::join!(waiting, async move { drop(event) });
+        assert!(waited.is_ok());
+        assert!(receipt.is_delivered());
+        assert!(tap.next().await.is_some());
+    }
+}
@@ -1,15 +1,17 @@
 mod event;
 pub mod event_router;
+pub mod metrics;
 
 use std::{error::Error, sync::Arc};
 
 use async_trait::async_trait;
-pub use event::{Event, EventMeta};
+pub use event::{DeadLetter, Delivery, Event, EventMeta, Receipt};
 use event_router::EventRouter;
+use serde::Serialize;
 pub use serde_json::json as value;
 pub use serde_json::Value;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
 pub enum ComponentKind {
     Collector,
     Transformer,
@@ -22,6 +24,12 @@ pub trait ComponentApi: 'static + Send + Sync {
     fn id(&self) -> &str;
     // Return the component kind(collector, transformer, or dispatcher)
     fn kind(&self) -> ComponentKind;
-    // Run the component. Use the `router` to recv/send events from/to other components
+    // Run the component. Use the `router` to recv/send events from/to other components.
+    // Return once the router asks the component to shut down, see `EventRouterApi::shutdown_requested`
     async fn run(&self, router: Arc<EventRouter>) -> Result<(), Box<dyn Error>>;
+    // Whether the component tells when it is ready with `EventRouterApi::set_ready`, e.g. once
+    // connected. Otherwise it is ready as soon as it runs.
+    fn reports_readiness(&self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,258 @@
+//! Metrics of the router and the components, rendered in the Prometheus text format.
+
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+use crate::event_router::ComponentInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Metric {
+    /// Counter of the events polled by a component
+    EventsReceived,
+    /// Counter of the events a component sent to another one
+    EventsSent,
+    /// Counter of the events a component discarded on purpose, or that were lost
+    EventsDropped,
+    /// Counter of the events a component failed to handle
+    EventsFailed,
+    /// Histogram of the seconds a dispatcher takes to deliver an event or a batch
+    DispatchLatency,
+    /// Histogram of the number of events per batch of a dispatcher
+    BatchSize,
+    /// Gauge of the seconds between the last collected binlog event being written and now
+    BinlogLag,
+}
+
+const METRICS: [Metric; 7] = [
+    Metric::EventsReceived,
+    Metric::EventsSent,
+    Metric::EventsDropped,
+    Metric::EventsFailed,
+    Metric::DispatchLatency,
+    Metric::BatchSize,
+    Metric::BinlogLag,
+];
+
+impl Metric {
+    fn name(self) -> &'static str {
+        match self {
+            Metric::EventsReceived => "wlf_events_received_total",
+            Metric::EventsSent => "wlf_events_sent_total",
+            Metric::EventsDropped => "wlf_events_dropped_total",
+            Metric::EventsFailed => "wlf_events_failed_total",
+            Metric::DispatchLatency => "wlf_dispatch_latency_seconds",
+            Metric::BatchSize => "wlf_batch_size",
+            Metric::BinlogLag => "wlf_binlog_lag_seconds",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Metric::EventsReceived => "Events polled by the component.",
+            Metric::EventsSent => "Events sent by the component to another one.",
+            Metric::EventsDropped => "Events discarded by the component or lost.",
+            Metric::EventsFailed => "Events the component failed to handle.",
+            Metric::DispatchLatency => "Time taken to deliver an event or a batch.",
+            Metric::BatchSize => "Events per batch delivered by the dispatcher.",
+            Metric::BinlogLag => "Time since the last collected binlog event was written.",
+        }
+    }
+
+    fn is_gauge(self) -> bool {
+        matches!(self, Metric::BinlogLag)
+    }
+
+    /// Upper bounds of the buckets of a histogram, none for a counter or a gauge.
+    fn buckets(self) -> Option<&'static [f64]> {
+        match self {
+            Metric::DispatchLatency => Some(&[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0]),
+            Metric::BatchSize => Some(&[1.0, 10.0, 50.0, 100.0, 500.0, 1000.0, 5000.0]),
+            _ => None,
+        }
+    }
+}
+
+struct Histogram {
+    /// Cumulative count of every bucket
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// The metrics of every component, by metric and component id.
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<Metric, BTreeMap<String, u64>>>,
+    gauges: Mutex<BTreeMap<Metric, BTreeMap<String, f64>>>,
+    histograms: Mutex<BTreeMap<Metric, BTreeMap<String, Histogram>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `n` to a counter of the component.
+    pub fn increment(&self, metric: Metric, component_id: &str, n: u64) {
+        debug_assert!(
+            metric.buckets().is_none() && !metric.is_gauge(),
+            "{metric:?} is not a counter"
+        );
+        let mut counters = self.counters.lock().unwrap();
+        let counters = counters.entry(metric).or_default();
+        match counters.get_mut(component_id) {
+            Some(counter) => *counter += n,
+            None => {
+                counters.insert(component_id.to_string(), n);
+            }
+        }
+    }
+
+    /// Sets a gauge of the component to `value`.
+    pub fn set(&self, metric: Metric, component_id: &str, value: f64) {
+        debug_assert!(metric.is_gauge(), "{metric:?} is not a gauge");
+        let mut gauges = self.gauges.lock().unwrap();
+        let gauges = gauges.entry(metric).or_default();
+        match gauges.get_mut(component_id) {
+            Some(gauge) => *gauge = value,
+            None => {
+                gauges.insert(component_id.to_string(), value);
+            }
+        }
+    }
+
+    /// Records a value in a histogram of the component.
+    pub fn observe(&self, metric: Metric, component_id: &str, value: f64) {
+        let Some(bounds) = metric.buckets() else {
+            debug_assert!(false, "{metric:?} is not a histogram");
+            return;
+        };
+        let mut histograms = self.histograms.lock().unwrap();
+        let histograms = histograms.entry(metric).or_default();
+        if !histograms.contains_key(component_id) {
+            let histogram = Histogram {
+                buckets: vec![0; bounds.len()],
+                sum: 0.0,
+                count: 0,
+            };
+            histograms.insert(component_id.to_string(), histogram);
+        }
+        let histogram = histograms.get_mut(component_id).unwrap();
+        for (bound, bucket) in bounds.iter().zip(&mut histogram.buckets) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    /// Renders the metrics, with the queue length of the given components.
+    pub fn render(&self, components: &[ComponentInfo]) -> String {
+        let mut out = String::new();
+        let counters = self.counters.lock().unwrap();
+        let gauges = self.gauges.lock().unwrap();
+        let histograms = self.histograms.lock().unwrap();
+        for metric in METRICS {
+            let name = metric.name();
+            if metric.is_gauge() {
+                header(&mut out, name, metric.help(), "gauge");
+                for (id, value) in gauges.get(&metric).into_iter().flatten() {
+                    writeln!(out, "{name}{{component=\"{}\"}} {value}", escape(id)).unwrap();
+                }
+                continue;
+            }
+            let Some(bounds) = metric.buckets() else {
+                header(&mut out, name, metric.help(), "counter");
+                for (id, value) in counters.get(&metric).into_iter().flatten() {
+                    writeln!(out, "{name}{{component=\"{}\"}} {value}", escape(id)).unwrap();
+                }
+                continue;
+            };
+            header(&mut out, name, metric.help(), "histogram");
+            for (id, histogram) in histograms.get(&metric).into_iter().flatten() {
+                let id = escape(id);
+                for (bound, count) in bounds.iter().zip(&histogram.buckets) {
+                    writeln!(
+                        out,
+                        "{name}_bucket{{component=\"{id}\",le=\"{bound}\"}} {count}"
+                    )
+                    .unwrap();
+                }
+                let count = histogram.count;
+                writeln!(
+                    out,
+                    "{name}_bucket{{component=\"{id}\",le=\"+Inf\"}} {count}"
+                )
+                .unwrap();
+                writeln!(out, "{name}_sum{{component=\"{id}\"}} {}", histogram.sum).unwrap();
+                writeln!(out, "{name}_count{{component=\"{id}\"}} {count}").unwrap();
+            }
+        }
+
+        let name = "wlf_router_queue_length";
+        header(
+            &mut out,
+            name,
+            "Events queued in the router for the component.",
+            "gauge",
+        );
+        for component in components {
+            if let Some(depth) = component.queue_depth {
+                let id = escape(&component.id);
+                writeln!(out, "{name}{{component=\"{id}\"}} {depth}").unwrap();
+            }
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{event_router::ComponentStatus, ComponentKind};
+
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        metrics.increment(Metric::EventsReceived, "kafka", 2);
+        metrics.increment(Metric::EventsReceived, "kafka", 1);
+        metrics.observe(Metric::BatchSize, "redis", 20.0);
+        metrics.observe(Metric::BatchSize, "redis", 200.0);
+        metrics.set(Metric::BinlogLag, "binlog", 3.0);
+        metrics.set(Metric::BinlogLag, "binlog", 12.0);
+        let components = [ComponentInfo {
+            id: "kafka".to_string(),
+            kind: ComponentKind::Dispatcher,
+            status: ComponentStatus::Running,
+            ready: true,
+            paused: false,
+            queue_depth: Some(4),
+            position: None,
+        }];
+
+        let text = metrics.render(&components);
+        assert!(text.contains("# TYPE wlf_events_received_total counter\n"));
+        assert!(text.contains("wlf_events_received_total{component=\"kafka\"} 3\n"));
+        assert!(text.contains("wlf_batch_size_bucket{component=\"redis\",le=\"10\"} 0\n"));
+        assert!(text.contains("wlf_batch_size_bucket{component=\"redis\",le=\"50\"} 1\n"));
+        assert!(text.contains("wlf_batch_size_bucket{component=\"redis\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("wlf_batch_size_sum{component=\"redis\"} 220\n"));
+        assert!(text.contains("# TYPE wlf_binlog_lag_seconds gauge\n"));
+        assert!(text.contains("wlf_binlog_lag_seconds{component=\"binlog\"} 12\n"));
+        assert!(text.contains("wlf_router_queue_length{component=\"kafka\"} 4\n"));
+    }
+}